@@ -0,0 +1,44 @@
+//! Times many concurrent `set` calls and prints the throughput, to compare the
+//! default locks with the `dashmap` feature's by running
+//! `cargo bench [--features dashmap] --bench concurrent_set`.
+
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+use dcs::DCS;
+
+const THREADS: usize = 8;
+const SETS: usize = 5_000;
+
+fn main() {
+    let dcs = Arc::new(DCS::new());
+    let start = Instant::now();
+    let handles: Vec<_> = (0..THREADS)
+        .map(|t| {
+            let dcs = Arc::clone(&dcs);
+            thread::spawn(move || {
+                for i in 0..SETS {
+                    dcs.set(format!("t{}:{}", t, i), i.to_string()).unwrap();
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    let elapsed = start.elapsed();
+    assert_eq!(dcs.db_size().unwrap(), THREADS * SETS);
+    let backend = if cfg!(feature = "dashmap") {
+        "dashmap"
+    } else {
+        "rwlock"
+    };
+    println!(
+        "{}: {} sets in {:?} ({:.0} sets/s)",
+        backend,
+        THREADS * SETS,
+        elapsed,
+        (THREADS * SETS) as f64 / elapsed.as_secs_f64()
+    );
+}
@@ -0,0 +1,615 @@
+use std::cell::RefCell;
+use std::fmt::{Display, Write as _};
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::error::OrPoisoned;
+use crate::expiry::from_unix_millis;
+use crate::resp::parse_command;
+use crate::value;
+use crate::{DcsError, KeyType, DCS};
+
+/// An append-only log of every write, replayed to rebuild the store on open.
+///
+/// Each record is a command encoded like a Redis request, an array of bulk
+/// strings: `*<argc>\r\n` followed by `$<len>\r\n<arg>\r\n` for every argument.
+/// Commands that depend on randomness or timing are logged by their effect, so
+/// `set_pop` becomes `SREM` of the popped members and `expire` logs the absolute
+/// deadline. Expired keys are logged as `DEL` when they are evicted, and strings
+/// deleted to meet the memory budget as `EVICT`. The writes
+/// of a transaction are wrapped in `MULTI` and `EXEC` records and only replayed
+/// if the `EXEC` made it to the file.
+pub(crate) struct Aof {
+    out: Mutex<BufWriter<File>>,
+    /// Whether each flush is followed by `sync_data`, see `DcsBuilder::aof_fsync`.
+    fsync: bool,
+}
+
+thread_local! {
+    /// Addresses of the AOFs this thread is running `aof_batch` calls on. A write
+    /// is only flushed if its thread isn't batching on the same AOF.
+    static BATCHES: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+}
+
+/// The arguments of one AOF record, built up by the closure passed to `DCS::log`.
+#[derive(Default)]
+pub(crate) struct Record {
+    args: Vec<String>,
+}
+
+impl Record {
+    pub(crate) fn arg(&mut self, arg: impl Display) -> &mut Self {
+        self.args.push(arg.to_string());
+        self
+    }
+
+    pub(crate) fn args<T: Display>(&mut self, args: impl IntoIterator<Item = T>) -> &mut Self {
+        for arg in args {
+            self.arg(arg);
+        }
+        self
+    }
+}
+
+impl Aof {
+    fn append(&self, record: &Record) -> Result<(), DcsError> {
+        let mut encoded = format!("*{}\r\n", record.args.len());
+        for arg in &record.args {
+            let _ = write!(encoded, "${}\r\n{}\r\n", arg.len(), arg);
+        }
+        let mut out = self.out.lock().or_poisoned("aof")?;
+        out.write_all(encoded.as_bytes())?;
+        if !BATCHES.with(|batches| batches.borrow().contains(&self.addr())) {
+            self.flush(&mut out)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&self, out: &mut BufWriter<File>) -> Result<(), DcsError> {
+        out.flush()?;
+        if self.fsync {
+            out.get_ref().sync_data()?;
+        }
+        Ok(())
+    }
+
+    fn addr(&self) -> usize {
+        self as *const Aof as usize
+    }
+}
+
+/// Ends an `aof_batch` of the current thread when its closure returns or panics.
+struct BatchGuard(usize);
+
+impl BatchGuard {
+    fn new(aof: &Aof) -> BatchGuard {
+        BATCHES.with(|batches| batches.borrow_mut().push(aof.addr()));
+        BatchGuard(aof.addr())
+    }
+}
+
+impl Drop for BatchGuard {
+    fn drop(&mut self) {
+        BATCHES.with(|batches| {
+            let mut batches = batches.borrow_mut();
+            if let Some(i) = batches.iter().rposition(|&addr| addr == self.0) {
+                batches.remove(i);
+            }
+        });
+    }
+}
+
+impl DCS {
+    /// Opens a store backed by the append-only file at `path`, creating it if
+    /// needed. Existing records are replayed first, then every write is appended
+    /// and flushed to the OS before its method returns.
+    ///
+    /// Flushing only hands the record to the OS, so it survives the process
+    /// crashing but not the machine losing power before the OS writes it out. Use
+    /// `DcsBuilder::aof_fsync` to also wait for the disk.
+    ///
+    /// A record cut short by a crash is dropped. If appending a record fails, the
+    /// method fails with `DcsError::Io`, but the write may already be applied in
+    /// memory.
+    pub fn open_with_aof<P: AsRef<Path>>(path: P) -> Result<DCS, DcsError> {
+        DCS::new().attach_aof(path.as_ref(), false)
+    }
+
+    /// Replays the AOF at `path` into this freshly created store and logs every
+    /// later write to it, syncing it to disk after each flush if `fsync`.
+    pub(crate) fn attach_aof(mut self, path: &Path, fsync: bool) -> Result<DCS, DcsError> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        self.replaying = true;
+        let mut offset = 0;
+        // Records of a transaction are held back until its `EXEC`, with the
+        // offset of its `MULTI`.
+        let mut pending: Option<(usize, Vec<Vec<String>>)> = None;
+        while let Some((args, len)) = parse_record(&bytes[offset..])? {
+            match (args.first().map(String::as_str), &mut pending) {
+                (Some("MULTI"), None) => pending = Some((offset, Vec::new())),
+                (Some("EXEC"), Some(_)) => {
+                    if let Some((_, batch)) = pending.take() {
+                        for args in batch {
+                            self.replay(&args)?;
+                        }
+                    }
+                }
+                (_, Some((_, batch))) => batch.push(args),
+                _ => self.replay(&args)?,
+            }
+            offset += len;
+        }
+        self.replaying = false;
+        let valid = pending.map_or(offset, |(start, _)| start);
+        if valid < bytes.len() {
+            file.set_len(valid as u64)?;
+        }
+        self.aof = Some(Aof {
+            out: Mutex::new(BufWriter::new(file)),
+            fsync,
+        });
+        Ok(self)
+    }
+
+    /// Runs `f` with per-write flushing of the AOF suspended and flushes once at
+    /// the end, trading durability of the writes in between for throughput. Only
+    /// writes made by the calling thread are held back; other threads keep
+    /// flushing theirs, which also hands the batched ones so far to the OS.
+    pub fn aof_batch<T>(
+        &self,
+        f: impl FnOnce(&Self) -> Result<T, DcsError>,
+    ) -> Result<T, DcsError> {
+        let Some(aof) = &self.aof else {
+            return f(self);
+        };
+        let result = {
+            let _guard = BatchGuard::new(aof);
+            f(self)
+        };
+        aof.flush(&mut *aof.out.lock().or_poisoned("aof")?)?;
+        result
+    }
+
+    /// Appends the record built by `build` to the AOF, if there is one, and sends
+    /// the keyspace events it implies. Callers hold the write locks of everything
+    /// the command touches, so records are logged in the order their writes are
+    /// applied. The record is not even built if nothing consumes it.
+    pub(crate) fn log(
+        &self,
+        build: impl FnOnce(&mut Record) -> &mut Record,
+    ) -> Result<(), DcsError> {
+        self.counters.write();
+        self.log_marker(build)
+    }
+
+    /// Like `log`, for records like `MULTI` that delimit writes rather than being
+    /// one, so they aren't counted in `stats`.
+    pub(crate) fn log_marker(
+        &self,
+        build: impl FnOnce(&mut Record) -> &mut Record,
+    ) -> Result<(), DcsError> {
+        let notify = self.keyspace_enabled();
+        if self.aof.is_none() && !notify {
+            return Ok(());
+        }
+        let mut record = Record::default();
+        build(&mut record);
+        if let Some(aof) = &self.aof {
+            aof.append(&record)?;
+        }
+        if notify {
+            self.notify_record(&record.args)?;
+        }
+        Ok(())
+    }
+
+    fn replay(&self, args: &[String]) -> Result<(), DcsError> {
+        let (command, args) = args.split_first().ok_or_else(malformed)?;
+        match (command.as_str(), args) {
+            ("SET", [key, value]) => self.set(key.clone(), value.clone()),
+            ("SETBIT", [key, offset, bit]) => self
+                .set_bit(key.clone(), parse(offset)?, parse::<u8>(bit)? != 0)
+                .map(drop),
+            ("SETRANGE", [key, offset, value]) => {
+                self.set_range(key.clone(), parse(offset)?, value).map(drop)
+            }
+            ("SETBYTES", [key, hex]) => {
+                self.set_bytes(key.clone(), value::from_hex(hex).ok_or_else(malformed)?)
+            }
+            ("INCRBY", [key, delta]) => self.incr_by(key.clone(), parse(delta)?).map(drop),
+            ("APPEND", [key, value]) => self.append(key.clone(), value.clone()).map(drop),
+            ("MSET", pairs) => self.mset(parse_pairs(pairs)?),
+            ("DEL", [key]) => self.del(key).map(drop),
+            ("GETDEL", [key]) => self.get_del(key).map(drop),
+            ("EVICT", [key]) => self
+                .remove_string(self.store.write(key)?, key, "EVICT")
+                .map(drop),
+            ("RENAME", [old_key, new_key]) => self.rename(old_key, new_key.clone()).map(drop),
+            // Only copies that went through are logged, and replaying from the same
+            // state makes the same decision, so `replace` can always be set.
+            ("COPY", [src, dst]) => self.copy(src, dst.clone(), true).map(drop),
+            ("PEXPIREAT", [key, millis]) => {
+                // A deadline that passed while the store was closed still has to be
+                // recorded, the key is evicted on first access like any other.
+                let deadline = from_unix_millis(parse(millis)?).unwrap_or_else(Instant::now);
+                self.expire_at(key, deadline).map(drop)
+            }
+            ("PERSIST", [key]) => self.persist(key).map(drop),
+            ("FLUSHALL", []) => self.flush_all(),
+            ("FLUSHSTORE", [ty]) => self.flush_type(parse_key_type(ty)?),
+            ("RPUSH", [key, values @ ..]) => {
+                self.list_push_multi(key.clone(), values.to_vec()).map(drop)
+            }
+            ("RPOP", [key]) => self.list_pop(key).map(drop),
+            ("RPOP", [key, count]) => self.list_pop_multi(key, parse(count)?).map(drop),
+            ("LPUSH", [key, values @ ..]) => self
+                .list_push_front_multi(key.clone(), values.to_vec())
+                .map(drop),
+            ("LPOP", [key]) => self.list_pop_front(key).map(drop),
+            ("LSET", [key, index, value]) => self.list_set(key, parse(index)?, value.clone()),
+            ("LTRIM", [key, start, stop]) => self.list_trim(key, parse(start)?, parse(stop)?),
+            ("LREM", [key, count, value]) => self.list_remove(key, parse(count)?, value).map(drop),
+            ("LINSERT", [key, side, pivot, value]) => {
+                let before = match side.as_str() {
+                    "BEFORE" => true,
+                    "AFTER" => false,
+                    _ => return Err(malformed()),
+                };
+                self.list_insert(key, before, pivot, value.clone())
+                    .map(drop)
+            }
+            ("RPOPLPUSH", [src, dst]) => self.list_move(src, dst.clone()).map(drop),
+            ("HSET", [key, field, value]) => {
+                self.hash_set(key.clone(), field.clone(), value.clone())
+            }
+            ("HDEL", [key, fields @ ..]) => {
+                self.hash_del_multi(key.clone(), fields.to_vec()).map(drop)
+            }
+            ("HINCRBY", [key, field, delta]) => self
+                .hash_incr_by(key.clone(), field.clone(), parse(delta)?)
+                .map(drop),
+            ("HMSET", [key, pairs @ ..]) => self.hash_mset(key.clone(), parse_pairs(pairs)?),
+            ("SADD", [key, values @ ..]) => {
+                self.set_add_multi(key.clone(), values.to_vec()).map(drop)
+            }
+            ("SREM", [key, values @ ..]) => self
+                .set_remove_multi(key.clone(), values.to_vec())
+                .map(drop),
+            ("SINTERSTORE", [dest, keys @ ..]) => {
+                self.set_intersect_store(dest.clone(), keys).map(drop)
+            }
+            ("SUNIONSTORE", [dest, keys @ ..]) => {
+                self.set_union_store(dest.clone(), keys).map(drop)
+            }
+            ("SDIFFSTORE", [dest, keys @ ..]) => self.set_diff_store(dest.clone(), keys).map(drop),
+            ("SMOVE", [src, dst, value]) => self.set_move(src, dst.clone(), value).map(drop),
+            ("ZADD", [key, pairs @ ..]) => {
+                let pairs = parse_pairs(pairs)?
+                    .into_iter()
+                    .map(|(score, member)| Ok((parse(&score)?, member)))
+                    .collect::<Result<_, DcsError>>()?;
+                self.zset_add_multi(key.clone(), pairs).map(drop)
+            }
+            ("ZREM", [key, members @ ..]) => self
+                .zset_remove_multi(key.clone(), members.to_vec())
+                .map(drop),
+            ("ZUNIONSTORE" | "ZINTERSTORE", [dest, count, rest @ ..]) => {
+                let count: usize = parse(count)?;
+                if rest.len() != count * 2 {
+                    return Err(malformed());
+                }
+                let (keys, weights) = rest.split_at(count);
+                let weights = weights
+                    .iter()
+                    .map(|w| parse(w))
+                    .collect::<Result<Vec<f64>, _>>()?;
+                if command == "ZUNIONSTORE" {
+                    self.zset_union_store(dest.clone(), keys, Some(&weights))
+                        .map(drop)
+                } else {
+                    self.zset_inter_store(dest.clone(), keys, Some(&weights))
+                        .map(drop)
+                }
+            }
+            _ => Err(malformed()),
+        }
+    }
+}
+
+/// Name a store is logged under by `FLUSHSTORE`.
+pub(crate) fn key_type_name(ty: KeyType) -> &'static str {
+    match ty {
+        KeyType::String => "string",
+        KeyType::List => "list",
+        KeyType::Hash => "hash",
+        KeyType::Set => "set",
+        KeyType::ZSet => "zset",
+    }
+}
+
+fn parse_key_type(name: &str) -> Result<KeyType, DcsError> {
+    match name {
+        "string" => Ok(KeyType::String),
+        "list" => Ok(KeyType::List),
+        "hash" => Ok(KeyType::Hash),
+        "set" => Ok(KeyType::Set),
+        "zset" => Ok(KeyType::ZSet),
+        _ => Err(malformed()),
+    }
+}
+
+fn parse<T: std::str::FromStr>(arg: &str) -> Result<T, DcsError> {
+    arg.parse().map_err(|_| malformed())
+}
+
+fn parse_pairs(args: &[String]) -> Result<Vec<(String, String)>, DcsError> {
+    if !args.len().is_multiple_of(2) {
+        return Err(malformed());
+    }
+    Ok(args
+        .chunks(2)
+        .map(|pair| (pair[0].clone(), pair[1].clone()))
+        .collect())
+}
+
+fn malformed() -> DcsError {
+    DcsError::Serialization("malformed AOF record".into())
+}
+
+/// Parses the record at the start of `buf`, returning its arguments and encoded
+/// length, or `None` if `buf` ends before the record does.
+fn parse_record(buf: &[u8]) -> Result<Option<(Vec<String>, usize)>, DcsError> {
+    let Some((args, len)) = parse_command(buf).map_err(|_| malformed())? else {
+        return Ok(None);
+    };
+    let args = args
+        .into_iter()
+        .map(|arg| String::from_utf8(arg).map_err(|_| malformed()))
+        .collect::<Result<_, _>>()?;
+    Ok(Some((args, len)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Transaction, DCS};
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::Barrier;
+    use std::thread;
+    use std::time::Duration;
+    use std::{env, process};
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("dcs-{}-{}.aof", name, process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_reopen_replays_writes() {
+        let path = temp_path("reopen");
+        {
+            let dcs = DCS::open_with_aof(&path).unwrap();
+            dcs.set("name".to_string(), "dcs".to_string()).unwrap();
+            dcs.incr_by("hits".to_string(), 41).unwrap();
+            dcs.set_bytes("blob".to_string(), vec![0xc3, 0x00, 0x28])
+                .unwrap();
+            dcs.set_bit("flags".to_string(), 9, true).unwrap();
+            dcs.set_range("name".to_string(), 1, "b").unwrap();
+            dcs.incr("hits".to_string()).unwrap();
+            dcs.list_push_multi("queue".to_string(), vec!["a".to_string(), "b".to_string()])
+                .unwrap();
+            dcs.list_push_front("queue".to_string(), "front".to_string())
+                .unwrap();
+            dcs.list_push_front_multi(
+                "queue".to_string(),
+                vec!["f1".to_string(), "f2".to_string()],
+            )
+            .unwrap();
+            dcs.list_insert("queue", false, "a", "a2".to_string())
+                .unwrap();
+            dcs.list_push_multi("queue".to_string(), vec!["c".to_string(), "d".to_string()])
+                .unwrap();
+            dcs.list_pop_multi("queue", 2).unwrap();
+            dcs.hash_set(
+                "user".to_string(),
+                "name".to_string(),
+                "line\r\nbreak".to_string(),
+            )
+            .unwrap();
+            dcs.hash_set("user".to_string(), "age".to_string(), "7".to_string())
+                .unwrap();
+            dcs.hash_del("user".to_string(), "age".to_string()).unwrap();
+            dcs.set_add_multi(
+                "tags".to_string(),
+                vec!["x".to_string(), "y".to_string(), "z".to_string()],
+            )
+            .unwrap();
+            dcs.set_pop("tags", 2).unwrap();
+            dcs.zset_add("board".to_string(), 0.1 + 0.2, "alice".to_string())
+                .unwrap();
+            dcs.zset_incr_by("board".to_string(), f64::INFINITY, "bob".to_string())
+                .unwrap();
+            dcs.zset_add_multi(
+                "board".to_string(),
+                vec![(1.0, "carol".to_string()), (2.0, "dave".to_string())],
+            )
+            .unwrap();
+            dcs.set("gone".to_string(), "soon".to_string()).unwrap();
+            dcs.del("gone").unwrap();
+            dcs.set("token".to_string(), "once".to_string()).unwrap();
+            dcs.get_del("token").unwrap();
+        }
+        let before = {
+            let dcs = DCS::open_with_aof(&path).unwrap();
+            dcs.set_members("tags").unwrap()
+        };
+        let dcs = DCS::open_with_aof(&path).unwrap();
+        let len = fs::metadata(&path).unwrap().len();
+        assert_eq!(dcs.get("name").unwrap(), Some("dbs".to_string()));
+        assert_eq!(fs::metadata(&path).unwrap().len(), len);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(dcs.get("hits").unwrap(), Some("42".to_string()));
+        assert_eq!(dcs.get_bytes("blob").unwrap(), Some(vec![0xc3, 0x00, 0x28]));
+        assert_eq!(dcs.get_bytes("flags").unwrap(), Some(vec![0x00, 0x40]));
+        assert_eq!(
+            dcs.list_range("queue", 0, -1).unwrap(),
+            vec!["f2", "f1", "front", "a", "a2", "b"]
+        );
+        assert_eq!(
+            dcs.hash_get("user", "name").unwrap(),
+            Some("line\r\nbreak".to_string())
+        );
+        assert_eq!(dcs.hash_get("user", "age").unwrap(), None);
+        assert_eq!(dcs.set_card("tags").unwrap(), 1);
+        assert_eq!(dcs.set_members("tags").unwrap(), before);
+        assert_eq!(dcs.zset_score("board", "alice").unwrap(), Some(0.1 + 0.2));
+        assert_eq!(dcs.zset_score("board", "bob").unwrap(), Some(f64::INFINITY));
+        assert_eq!(dcs.zset_score("board", "dave").unwrap(), Some(2.0));
+        assert!(!dcs.exists("gone").unwrap());
+        assert!(!dcs.exists("token").unwrap());
+    }
+
+    #[test]
+    fn test_replay_keeps_ttls() {
+        let path = temp_path("ttl");
+        {
+            let dcs = DCS::open_with_aof(&path).unwrap();
+            dcs.set("session".to_string(), "abc".to_string()).unwrap();
+            dcs.expire("session", Duration::from_secs(60)).unwrap();
+            dcs.list_push("short".to_string(), "a".to_string()).unwrap();
+            dcs.expire("short", Duration::from_millis(20)).unwrap();
+            dcs.list_push("short".to_string(), "b".to_string()).unwrap();
+        }
+        std::thread::sleep(Duration::from_millis(40));
+        let dcs = DCS::open_with_aof(&path).unwrap();
+        assert!(dcs.ttl("session").unwrap().is_some());
+        assert!(!dcs.exists("short").unwrap());
+        drop(dcs);
+        // The eviction above was logged, so the key stays gone.
+        let dcs = DCS::open_with_aof(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(dcs.list_len("short").unwrap(), 0);
+    }
+
+    #[test]
+    fn test_replay_keeps_strings_evicted_for_memory() {
+        let path = temp_path("evict");
+        {
+            let dcs = DCS::builder()
+                .max_memory(20)
+                .aof_path(&path)
+                .build()
+                .unwrap();
+            dcs.set("a".to_string(), "12345678".to_string()).unwrap();
+            dcs.set("b".to_string(), "12345678".to_string()).unwrap();
+            dcs.set("c".to_string(), "12345678".to_string()).unwrap();
+        }
+        let dcs = DCS::open_with_aof(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(dcs.get("a").unwrap(), None);
+        assert_eq!(dcs.get("c").unwrap(), Some("12345678".to_string()));
+    }
+
+    #[test]
+    fn test_truncated_record_is_dropped() {
+        let path = temp_path("truncated");
+        {
+            let dcs = DCS::open_with_aof(&path).unwrap();
+            dcs.set("a".to_string(), "1".to_string()).unwrap();
+            dcs.set("b".to_string(), "2".to_string()).unwrap();
+        }
+        let len = fs::metadata(&path).unwrap().len();
+        let file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(len - 3).unwrap();
+        {
+            let dcs = DCS::open_with_aof(&path).unwrap();
+            assert_eq!(dcs.get("b").unwrap(), None);
+            dcs.set("c".to_string(), "3".to_string()).unwrap();
+        }
+        let dcs = DCS::open_with_aof(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(dcs.get("a").unwrap(), Some("1".to_string()));
+        assert_eq!(dcs.get("c").unwrap(), Some("3".to_string()));
+    }
+
+    #[test]
+    fn test_aof_batch() {
+        let path = temp_path("batch");
+        let dcs = DCS::open_with_aof(&path).unwrap();
+        dcs.aof_batch(|dcs| {
+            for i in 0..100 {
+                dcs.list_push("log".to_string(), i.to_string())?;
+            }
+            Ok(())
+        })
+        .unwrap();
+        let reopened = DCS::open_with_aof(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(reopened.list_len("log").unwrap(), 100);
+        assert_eq!(
+            reopened.list_index("log", -1).unwrap(),
+            Some("99".to_string())
+        );
+    }
+
+    #[test]
+    fn test_aof_batch_leaves_other_threads_flushing() {
+        let path = temp_path("batch_threads");
+        let dcs = DCS::open_with_aof(&path).unwrap();
+        let started = Barrier::new(2);
+        let written = Barrier::new(2);
+        thread::scope(|s| {
+            s.spawn(|| {
+                dcs.aof_batch(|dcs| {
+                    dcs.set("batched".to_string(), "1".to_string())?;
+                    started.wait();
+                    written.wait();
+                    Ok(())
+                })
+                .unwrap();
+            });
+            started.wait();
+            dcs.set("other".to_string(), "2".to_string()).unwrap();
+            let logged = fs::read_to_string(&path).unwrap();
+            written.wait();
+            assert!(logged.contains("$5\r\nother\r\n"));
+        });
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_unfinished_transaction_is_dropped() {
+        let path = temp_path("transaction");
+        {
+            let dcs = DCS::open_with_aof(&path).unwrap();
+            dcs.set("a".to_string(), "1".to_string()).unwrap();
+            let mut tx = Transaction::new();
+            tx.incr("a".to_string())
+                .list_push("log".to_string(), "a".to_string());
+            dcs.exec(tx).unwrap();
+        }
+        let reopened = DCS::open_with_aof(&path).unwrap();
+        assert_eq!(reopened.get("a").unwrap(), Some("2".to_string()));
+        assert_eq!(reopened.list_len("log").unwrap(), 1);
+        drop(reopened);
+        // Cut the file right before the `EXEC` record.
+        let len = fs::metadata(&path).unwrap().len();
+        let file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(len - "*1\r\n$4\r\nEXEC\r\n".len() as u64)
+            .unwrap();
+        let dcs = DCS::open_with_aof(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(dcs.get("a").unwrap(), Some("1".to_string()));
+        assert!(!dcs.exists("log").unwrap());
+    }
+}
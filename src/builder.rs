@@ -0,0 +1,189 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::lru::Lru;
+use crate::{DcsError, ShardRouter, DCS, DEFAULT_SHARDS};
+
+/// Configures a `DCS` before creating it, as returned by [`DCS::builder`].
+/// Every setting starts out at what `DCS::new` uses.
+#[derive(Clone)]
+#[must_use]
+pub struct DcsBuilder {
+    shards: usize,
+    router: Option<Arc<dyn ShardRouter>>,
+    aof_path: Option<PathBuf>,
+    aof_fsync: bool,
+    default_ttl: Option<Duration>,
+    max_memory: Option<usize>,
+}
+
+impl DcsBuilder {
+    pub fn new() -> Self {
+        DcsBuilder {
+            shards: DEFAULT_SHARDS,
+            router: None,
+            aof_path: None,
+            aof_fsync: false,
+            default_ttl: None,
+            max_memory: None,
+        }
+    }
+
+    /// Number of shards every store is split into, see `DCS::with_shards`.
+    pub fn shards(mut self, shards: usize) -> Self {
+        self.shards = shards;
+        self
+    }
+
+    /// Picks the shard of each key, instead of a hash of the whole key. Use
+    /// `HashTagRouter` to keep related keys in one shard.
+    pub fn router(mut self, router: impl ShardRouter + 'static) -> Self {
+        self.router = Some(Arc::new(router));
+        self
+    }
+
+    /// Append-only file to replay and log writes to, see `DCS::open_with_aof`.
+    pub fn aof_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.aof_path = Some(path.into());
+        self
+    }
+
+    /// Syncs the AOF to disk with `sync_data` after every flush, so acknowledged
+    /// writes also survive a power loss, at the cost of waiting for the disk on
+    /// each write. Off by default.
+    pub fn aof_fsync(mut self, fsync: bool) -> Self {
+        self.aof_fsync = fsync;
+        self
+    }
+
+    /// TTL given to strings written by `set`, `set_nx`, `get_set`, `mset`,
+    /// `set_bytes` and `get_or_set_with` that don't have one yet, for using the
+    /// store as a cache.
+    pub fn default_ttl(mut self, ttl: Duration) -> Self {
+        self.default_ttl = Some(ttl);
+        self
+    }
+
+    /// Approximate number of bytes strings may take up, counting the length of
+    /// each key and value. A write going over it deletes the least recently read
+    /// or written other strings until it fits, and a string that can't fit even
+    /// alone is rejected with `DcsError::OomRejected`. Other data types are not
+    /// counted.
+    pub fn max_memory(mut self, bytes: usize) -> Self {
+        self.max_memory = Some(bytes);
+        self
+    }
+
+    /// Creates the store. Fails only if the AOF can't be opened or replayed.
+    pub fn build(self) -> Result<DCS, DcsError> {
+        let dcs = DCS {
+            default_ttl: self.default_ttl,
+            lru: self.max_memory.map(|bytes| Mutex::new(Lru::new(bytes))),
+            ..DCS::with_router(self.shards, self.router)
+        };
+        match self.aof_path {
+            Some(path) => dcs.attach_aof(&path, self.aof_fsync),
+            None => Ok(dcs),
+        }
+    }
+}
+
+impl fmt::Debug for DcsBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DcsBuilder")
+            .field("shards", &self.shards)
+            .field("custom_router", &self.router.is_some())
+            .field("aof_path", &self.aof_path)
+            .field("aof_fsync", &self.aof_fsync)
+            .field("default_ttl", &self.default_ttl)
+            .field("max_memory", &self.max_memory)
+            .finish()
+    }
+}
+
+impl Default for DcsBuilder {
+    fn default() -> Self {
+        DcsBuilder::new()
+    }
+}
+
+impl DCS {
+    pub fn builder() -> DcsBuilder {
+        DcsBuilder::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use std::{env, fs, process};
+
+    use crate::{DcsError, DCS};
+
+    #[test]
+    fn test_builder_sharded() {
+        let dcs = DCS::builder().shards(8).build().unwrap();
+        dcs.set("s".to_string(), "v".to_string()).unwrap();
+        dcs.list_push("l".to_string(), "v".to_string()).unwrap();
+        dcs.hash_set("h".to_string(), "f".to_string(), "v".to_string())
+            .unwrap();
+        dcs.set_add("t".to_string(), "v".to_string()).unwrap();
+        dcs.zset_add("z".to_string(), 1.0, "v".to_string()).unwrap();
+        assert_eq!(dcs.get("s").unwrap(), Some("v".to_string()));
+        assert_eq!(dcs.list_len("l").unwrap(), 1);
+        assert_eq!(dcs.hash_get("h", "f").unwrap(), Some("v".to_string()));
+        assert!(dcs.set_is_member("t", "v").unwrap());
+        assert_eq!(dcs.zset_score("z", "v").unwrap(), Some(1.0));
+        assert!(matches!(
+            dcs.list_push("s".to_string(), "v".to_string()),
+            Err(DcsError::WrongType { .. })
+        ));
+        assert_eq!(dcs.db_size().unwrap(), 5);
+    }
+
+    #[test]
+    fn test_builder_default_ttl() {
+        let dcs = DCS::builder()
+            .default_ttl(Duration::from_secs(60))
+            .build()
+            .unwrap();
+        dcs.set("cached".to_string(), "v".to_string()).unwrap();
+        assert!(dcs.ttl("cached").unwrap().is_some());
+        dcs.expire("cached", Duration::from_secs(5)).unwrap();
+        dcs.set("cached".to_string(), "w".to_string()).unwrap();
+        assert!(dcs.ttl("cached").unwrap().unwrap() <= Duration::from_secs(5));
+        dcs.list_push("queue".to_string(), "v".to_string()).unwrap();
+        assert_eq!(dcs.ttl("queue").unwrap(), None);
+    }
+
+    #[test]
+    fn test_builder_aof_path() {
+        let path = env::temp_dir().join(format!("dcs-builder-{}.aof", process::id()));
+        {
+            let dcs = DCS::builder().aof_path(&path).build().unwrap();
+            dcs.set("name".to_string(), "dcs".to_string()).unwrap();
+        }
+        let dcs = DCS::builder().aof_path(&path).build().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(dcs.get("name").unwrap(), Some("dcs".to_string()));
+    }
+
+    #[test]
+    fn test_builder_aof_fsync() {
+        let path = env::temp_dir().join(format!("dcs-builder-fsync-{}.aof", process::id()));
+        let dcs = DCS::builder()
+            .aof_path(&path)
+            .aof_fsync(true)
+            .build()
+            .unwrap();
+        dcs.set("name".to_string(), "dcs".to_string()).unwrap();
+        dcs.aof_batch(|dcs| dcs.list_push("log".to_string(), "a".to_string()))
+            .unwrap();
+        let reopened = DCS::open_with_aof(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(reopened.get("name").unwrap(), Some("dcs".to_string()));
+        assert_eq!(reopened.list_len("log").unwrap(), 1);
+    }
+}
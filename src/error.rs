@@ -0,0 +1,92 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::sync::LockResult;
+
+use crate::aof::key_type_name;
+use crate::KeyType;
+
+#[derive(Debug)]
+pub enum DcsError {
+    /// A thread panicked while holding the lock on the named store or piece of
+    /// bookkeeping, which may have been left half-updated.
+    Poisoned(String),
+    /// The value an increment applies to, at `key`, doesn't parse as an `i64`.
+    /// For hashes `key` is that of the hash.
+    NotAnInteger {
+        key: String,
+    },
+    InvalidUtf8,
+    Overflow,
+    /// A write through the `expected` store to a `key` already held by the
+    /// `found` one.
+    WrongType {
+        key: String,
+        expected: KeyType,
+        found: KeyType,
+    },
+    IndexOutOfRange,
+    InvalidScore,
+    WeightsMismatch,
+    OomRejected,
+    Io(io::Error),
+    Serialization(Box<dyn Error + Send + Sync>),
+}
+
+impl fmt::Display for DcsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DcsError::Poisoned(name) => write!(f, "lock on {} poisoned", name),
+            DcsError::NotAnInteger { key } => write!(f, "value at {} is not an integer", key),
+            DcsError::InvalidUtf8 => write!(f, "value is binary, not UTF-8 text"),
+            DcsError::Overflow => write!(f, "increment or decrement would overflow"),
+            DcsError::WrongType {
+                key,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{} holds a {}, not a {}",
+                key,
+                key_type_name(*found),
+                key_type_name(*expected)
+            ),
+            DcsError::IndexOutOfRange => write!(f, "index out of range"),
+            DcsError::InvalidScore => write!(f, "resulting score is not a number"),
+            DcsError::WeightsMismatch => {
+                write!(f, "number of weights does not match number of keys")
+            }
+            DcsError::OomRejected => write!(f, "value does not fit in the memory budget"),
+            DcsError::Io(err) => write!(f, "I/O error: {}", err),
+            DcsError::Serialization(err) => write!(f, "serialization error: {}", err),
+        }
+    }
+}
+
+impl Error for DcsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DcsError::Io(err) => Some(err),
+            DcsError::Serialization(err) => Some(err.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// Converts the result of taking a lock, naming what it guards in the error so
+/// that no guard type ends up in it.
+pub(crate) trait OrPoisoned<T> {
+    fn or_poisoned(self, name: &str) -> Result<T, DcsError>;
+}
+
+impl<T> OrPoisoned<T> for LockResult<T> {
+    fn or_poisoned(self, name: &str) -> Result<T, DcsError> {
+        self.map_err(|_| DcsError::Poisoned(name.to_string()))
+    }
+}
+
+impl From<io::Error> for DcsError {
+    fn from(err: io::Error) -> Self {
+        DcsError::Io(err)
+    }
+}
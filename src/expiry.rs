@@ -0,0 +1,125 @@
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::error::OrPoisoned;
+use crate::{DcsError, DCS};
+
+/// Stops the background sweeper started by [`DCS::start_expiry_sweeper`] when dropped.
+pub struct ExpirySweeperHandle {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for ExpirySweeperHandle {
+    fn drop(&mut self) {
+        // Dropping the sender wakes the sweeper up with a disconnect.
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl DCS {
+    /// Spawns a thread that removes expired keys every `interval`, so they are
+    /// reclaimed even if never read again.
+    ///
+    /// The store has to be shared through an `Arc`. The thread only keeps a weak
+    /// reference, so it also exits once the last `Arc` is dropped.
+    pub fn start_expiry_sweeper(self: &Arc<Self>, interval: Duration) -> ExpirySweeperHandle {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let dcs: Weak<DCS> = Arc::downgrade(self);
+        let thread = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                let Some(dcs) = dcs.upgrade() else { break };
+                if dcs.sweep_expired().is_err() {
+                    break;
+                }
+            }
+        });
+        ExpirySweeperHandle {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+
+    /// Removes every key whose TTL has passed and returns how many were removed.
+    pub(crate) fn sweep_expired(&self) -> Result<usize, DcsError> {
+        let now = Instant::now();
+        let candidates: Vec<String> = self
+            .expirations
+            .read()
+            .or_poisoned("expirations")?
+            .iter()
+            .filter(|(_, deadline)| **deadline <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        if candidates.is_empty() {
+            return Ok(0);
+        }
+        let mut expirations = self.expirations.write().or_poisoned("expirations")?;
+        // The TTLs may have been changed while only the read lock was held.
+        let expired: Vec<String> = candidates
+            .into_iter()
+            .filter(|key| {
+                expirations
+                    .get(key)
+                    .is_some_and(|deadline| *deadline <= now)
+            })
+            .collect();
+        for key in &expired {
+            expirations.remove(key);
+        }
+        self.remove_from_stores(&expired)?;
+        Ok(expired.len())
+    }
+}
+
+/// Converts a deadline to milliseconds since the Unix epoch, so it can be stored
+/// outside the process.
+pub(crate) fn to_unix_millis(deadline: Instant) -> u64 {
+    let wall = SystemTime::now() + deadline.saturating_duration_since(Instant::now());
+    wall.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
+
+/// The inverse of `to_unix_millis`. Returns `None` if the deadline has passed.
+pub(crate) fn from_unix_millis(millis: u64) -> Option<Instant> {
+    let wall = UNIX_EPOCH + Duration::from_millis(millis);
+    match wall.duration_since(SystemTime::now()) {
+        Ok(remaining) if !remaining.is_zero() => Some(Instant::now() + remaining),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::DCS;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_sweeper_reclaims_expired_keys() {
+        let dcs = Arc::new(DCS::new());
+        let _sweeper = dcs.start_expiry_sweeper(Duration::from_millis(10));
+        dcs.set("key1".to_string(), "value1".to_string()).unwrap();
+        dcs.expire("key1", Duration::from_millis(20)).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        assert!(!dcs.store.read("key1").unwrap().contains_key("key1"));
+        assert!(!dcs.expirations.read().unwrap().contains_key("key1"));
+    }
+
+    #[test]
+    fn test_sweeper_stops_on_drop() {
+        let dcs = Arc::new(DCS::new());
+        let sweeper = dcs.start_expiry_sweeper(Duration::from_millis(10));
+        drop(sweeper);
+        dcs.set("key1".to_string(), "value1".to_string()).unwrap();
+        dcs.expire("key1", Duration::from_millis(10)).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        assert!(dcs.store.read("key1").unwrap().contains_key("key1"));
+    }
+}
@@ -0,0 +1,152 @@
+/// Glob matching with Redis `KEYS` semantics: `*` matches any run of characters
+/// (including none), `?` matches a single character, `[abc]`, `[a-z]` and `[^a]`
+/// match character classes, and `\` escapes the character after it.
+pub(crate) fn glob_match(pattern: &str, input: &str) -> bool {
+    let tokens = tokenize(pattern);
+    let input: Vec<char> = input.chars().collect();
+    let (mut t, mut i) = (0, 0);
+    // Position of the last `*` seen and the input index it was tried against.
+    let mut backtrack: Option<(usize, usize)> = None;
+    while i < input.len() {
+        match tokens.get(t) {
+            Some(Token::Star) => {
+                backtrack = Some((t, i));
+                t += 1;
+                continue;
+            }
+            Some(token) if token.matches(input[i]) => {
+                t += 1;
+                i += 1;
+                continue;
+            }
+            _ => {}
+        }
+        match backtrack {
+            // Let the last `*` swallow one more character and retry.
+            Some((star, start)) => {
+                t = star + 1;
+                i = start + 1;
+                backtrack = Some((star, start + 1));
+            }
+            None => return false,
+        }
+    }
+    tokens[t..].iter().all(|token| matches!(token, Token::Star))
+}
+
+enum Token {
+    Star,
+    Any,
+    Literal(char),
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+impl Token {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Token::Star | Token::Any => true,
+            Token::Literal(literal) => *literal == c,
+            Token::Class { negated, ranges } => {
+                ranges.iter().any(|(lo, hi)| (*lo..=*hi).contains(&c)) != *negated
+            }
+        }
+    }
+}
+
+fn tokenize(pattern: &str) -> Vec<Token> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' => tokens.push(Token::Star),
+            '?' => tokens.push(Token::Any),
+            '\\' if i + 1 < chars.len() => {
+                i += 1;
+                tokens.push(Token::Literal(chars[i]));
+            }
+            '[' => match parse_class(&chars[i + 1..]) {
+                Some((token, len)) => {
+                    tokens.push(token);
+                    i += len;
+                }
+                // An unterminated class is matched literally.
+                None => tokens.push(Token::Literal('[')),
+            },
+            c => tokens.push(Token::Literal(c)),
+        }
+        i += 1;
+    }
+    tokens
+}
+
+/// Parses the body of a character class following `[`. Returns the token and how
+/// many characters were consumed, including the closing `]`.
+fn parse_class(chars: &[char]) -> Option<(Token, usize)> {
+    let mut i = 0;
+    let negated = chars.first() == Some(&'^');
+    if negated {
+        i += 1;
+    }
+    let mut ranges = Vec::new();
+    while i < chars.len() {
+        let lo = match chars[i] {
+            ']' => return Some((Token::Class { negated, ranges }, i + 1)),
+            '\\' if i + 1 < chars.len() => {
+                i += 1;
+                chars[i]
+            }
+            c => c,
+        };
+        if i + 2 < chars.len() && chars[i + 1] == '-' && chars[i + 2] != ']' {
+            let hi = chars[i + 2];
+            ranges.push(if lo <= hi { (lo, hi) } else { (hi, lo) });
+            i += 3;
+        } else {
+            ranges.push((lo, lo));
+            i += 1;
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::glob_match;
+
+    #[test]
+    fn test_star() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("user:*", "user:"));
+        assert!(glob_match("user:*", "user:42"));
+        assert!(glob_match("*:name", "user:42:name"));
+        assert!(!glob_match("user:*", "item:1"));
+    }
+
+    #[test]
+    fn test_question_mark() {
+        assert!(glob_match("item:?", "item:1"));
+        assert!(!glob_match("item:?", "item:"));
+        assert!(!glob_match("item:?", "item:12"));
+    }
+
+    #[test]
+    fn test_class() {
+        assert!(glob_match("h[ae]llo", "hallo"));
+        assert!(!glob_match("h[ae]llo", "hillo"));
+        assert!(glob_match("h[a-c]llo", "hbllo"));
+        assert!(glob_match("h[^e]llo", "hallo"));
+        assert!(!glob_match("h[^e]llo", "hello"));
+        assert!(glob_match("h[llo", "h[llo"));
+    }
+
+    #[test]
+    fn test_escape() {
+        assert!(glob_match("a\\*b", "a*b"));
+        assert!(!glob_match("a\\*b", "axb"));
+        assert!(glob_match("a\\?", "a?"));
+    }
+}
@@ -0,0 +1,209 @@
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{self, Receiver};
+
+use crate::error::OrPoisoned;
+use crate::{DcsError, KeyType, DCS};
+
+/// What a write did to a key, as reported by keyspace events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyOp {
+    /// The value was written, or had members or fields added or updated. Also
+    /// sent for the destination of a copy or of a `*_store` command.
+    Set,
+    /// The key was removed, including by a flush or because its TTL passed.
+    Del,
+    /// Elements were pushed onto the list.
+    Push,
+    /// Elements were popped off the list.
+    Pop,
+    /// Elements, members or fields were removed, leaving the key in place.
+    Remove,
+    /// A TTL was set.
+    Expire,
+    /// The TTL was removed.
+    Persist,
+    /// The key was renamed to another one.
+    RenameFrom,
+    /// Another key was renamed to this one.
+    RenameTo,
+}
+
+/// A write to one key in one store. A key held by several stores produces an
+/// event for each store a write touches.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub key: String,
+    pub key_type: KeyType,
+    pub op: KeyOp,
+}
+
+impl DCS {
+    /// Subscribes to the keyspace channel, which carries a `KeyEvent` for every
+    /// write, in the order the writes are applied. Events are only produced while
+    /// at least one subscriber is listening.
+    pub fn subscribe_keyspace(&self) -> Result<Receiver<KeyEvent>, DcsError> {
+        let (sender, receiver) = mpsc::channel();
+        let mut subscribers = self
+            .keyspace_subscribers
+            .write()
+            .or_poisoned("keyspace_subscribers")?;
+        subscribers.push(sender);
+        self.keyspace_enabled.store(true, Ordering::SeqCst);
+        Ok(receiver)
+    }
+
+    pub(crate) fn keyspace_enabled(&self) -> bool {
+        self.keyspace_enabled.load(Ordering::Relaxed)
+    }
+
+    /// Sends an event to every keyspace subscriber, pruning those that are gone.
+    pub(crate) fn notify_keyspace(
+        &self,
+        key: &str,
+        key_type: KeyType,
+        op: KeyOp,
+    ) -> Result<(), DcsError> {
+        if !self.keyspace_enabled() {
+            return Ok(());
+        }
+        let event = KeyEvent {
+            key: key.to_string(),
+            key_type,
+            op,
+        };
+        let mut subscribers = self
+            .keyspace_subscribers
+            .write()
+            .or_poisoned("keyspace_subscribers")?;
+        subscribers.retain(|sender| sender.send(event.clone()).is_ok());
+        if subscribers.is_empty() {
+            self.keyspace_enabled.store(false, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+
+    /// Sends the events for a write logged as `args`. Records that don't say which
+    /// stores they touched, like `DEL` or `PEXPIREAT`, produce none; their callers
+    /// notify directly.
+    pub(crate) fn notify_record(&self, args: &[String]) -> Result<(), DcsError> {
+        let Some((command, args)) = args.split_first() else {
+            return Ok(());
+        };
+        let (key_type, op) = match command.as_str() {
+            "SET" | "SETBYTES" | "SETBIT" | "SETRANGE" | "INCRBY" | "APPEND" => {
+                (KeyType::String, KeyOp::Set)
+            }
+            "MSET" => {
+                for key in args.iter().step_by(2) {
+                    self.notify_keyspace(key, KeyType::String, KeyOp::Set)?;
+                }
+                return Ok(());
+            }
+            "RPUSH" | "LPUSH" => (KeyType::List, KeyOp::Push),
+            "RPOP" | "LPOP" => (KeyType::List, KeyOp::Pop),
+            "LSET" | "LINSERT" => (KeyType::List, KeyOp::Set),
+            "LTRIM" | "LREM" => (KeyType::List, KeyOp::Remove),
+            "RPOPLPUSH" => {
+                if let [src, dst] = args {
+                    self.notify_keyspace(src, KeyType::List, KeyOp::Pop)?;
+                    self.notify_keyspace(dst, KeyType::List, KeyOp::Push)?;
+                }
+                return Ok(());
+            }
+            "HSET" | "HMSET" | "HINCRBY" => (KeyType::Hash, KeyOp::Set),
+            "HDEL" => (KeyType::Hash, KeyOp::Remove),
+            "SADD" | "SINTERSTORE" | "SUNIONSTORE" | "SDIFFSTORE" => (KeyType::Set, KeyOp::Set),
+            "SREM" => (KeyType::Set, KeyOp::Remove),
+            "SMOVE" => {
+                if let [src, dst, _] = args {
+                    self.notify_keyspace(src, KeyType::Set, KeyOp::Remove)?;
+                    self.notify_keyspace(dst, KeyType::Set, KeyOp::Set)?;
+                }
+                return Ok(());
+            }
+            "ZADD" | "ZUNIONSTORE" | "ZINTERSTORE" => (KeyType::ZSet, KeyOp::Set),
+            "ZREM" => (KeyType::ZSet, KeyOp::Remove),
+            _ => return Ok(()),
+        };
+        match args.first() {
+            Some(key) => self.notify_keyspace(key, key_type, op),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::{Receiver, TryRecvError};
+    use std::time::Duration;
+
+    use super::{KeyEvent, KeyOp};
+    use crate::{KeyType, DCS};
+
+    fn event(key: &str, key_type: KeyType, op: KeyOp) -> KeyEvent {
+        KeyEvent {
+            key: key.to_string(),
+            key_type,
+            op,
+        }
+    }
+
+    fn drain(events: &Receiver<KeyEvent>) -> Vec<KeyEvent> {
+        events.try_iter().collect()
+    }
+
+    #[test]
+    fn test_set_then_del_events() {
+        let dcs = DCS::new();
+        let events = dcs.subscribe_keyspace().unwrap();
+        dcs.set("k".to_string(), "v".to_string()).unwrap();
+        dcs.del("k").unwrap();
+        assert_eq!(
+            drain(&events),
+            vec![
+                event("k", KeyType::String, KeyOp::Set),
+                event("k", KeyType::String, KeyOp::Del),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_events_for_untyped_writes() {
+        let dcs = DCS::new();
+        dcs.list_push("a".to_string(), "x".to_string()).unwrap();
+        let events = dcs.subscribe_keyspace().unwrap();
+        dcs.expire("a", Duration::from_secs(60)).unwrap();
+        dcs.persist("a").unwrap();
+        dcs.rename("a", "b".to_string()).unwrap();
+        dcs.list_move("b", "c".to_string()).unwrap();
+        dcs.del("c").unwrap();
+        assert_eq!(
+            drain(&events),
+            vec![
+                event("a", KeyType::List, KeyOp::Expire),
+                event("a", KeyType::List, KeyOp::Persist),
+                event("a", KeyType::List, KeyOp::RenameFrom),
+                event("b", KeyType::List, KeyOp::RenameTo),
+                event("b", KeyType::List, KeyOp::Pop),
+                event("c", KeyType::List, KeyOp::Push),
+                event("c", KeyType::List, KeyOp::Del),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_no_events_once_unsubscribed() {
+        let dcs = DCS::new();
+        let events = dcs.subscribe_keyspace().unwrap();
+        drop(events);
+        dcs.set("k".to_string(), "v".to_string()).unwrap();
+        assert!(!dcs.keyspace_enabled());
+        let events = dcs.subscribe_keyspace().unwrap();
+        assert_eq!(events.try_recv(), Err(TryRecvError::Empty));
+        dcs.incr("n".to_string()).unwrap();
+        assert_eq!(
+            drain(&events),
+            vec![event("n", KeyType::String, KeyOp::Set)]
+        );
+    }
+}
@@ -1,330 +1,5057 @@
 use std::collections::{BTreeMap, HashMap, HashSet};
-use std::sync::PoisonError;
-use std::sync::RwLock;
-use std::sync::RwLockReadGuard;
-use std::sync::RwLockWriteGuard;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::time::{Duration, Instant};
 
+mod aof;
+mod builder;
+pub mod error;
+pub mod expiry;
+mod glob;
+mod keyspace;
+mod lru;
+mod pubsub;
+pub mod resp;
+mod rng;
+mod router;
+#[cfg(feature = "server")]
+pub mod server;
+mod sharded;
+#[cfg(feature = "serde")]
+pub mod snapshot;
+mod stats;
+mod transaction;
+mod value;
+mod version;
+mod zset;
+
+pub use builder::DcsBuilder;
+pub use error::DcsError;
+pub use expiry::ExpirySweeperHandle;
+pub use keyspace::{KeyEvent, KeyOp};
+pub use router::{HashRouter, HashTagRouter, ShardRouter};
+#[cfg(feature = "server")]
+pub use server::serve;
+#[cfg(feature = "serde")]
+pub use snapshot::{DcsSnapshot, SnapshotFormat};
+pub use stats::DcsStats;
+pub use transaction::{Transaction, TxnResult};
+
+use aof::{key_type_name, Aof};
+use error::OrPoisoned;
+use glob::glob_match;
+use lru::Lru;
+use rng::Rng;
+use sharded::{Map, ReadShards, Sharded, Shards, WriteShards};
+use stats::Counters;
+use value::StringValue;
+use version::Versions;
+use zset::ZSet;
+
+/// Seed for the generator behind random sampling, fixed so runs are reproducible.
+const RNG_SEED: u64 = 0x5eed;
+/// Largest length `set_bit` and `set_range` grow a string to, matching Redis.
+const MAX_STRING_LEN: usize = 512 * 1024 * 1024;
+/// Number of shards each store is split into by `DCS::new`.
+const DEFAULT_SHARDS: usize = 16;
+
+/// The store a key is held by, as returned by `DCS::type_of`. This is also the
+/// value type callers ask about, so there is no separate `ValueType`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyType {
+    String,
+    List,
+    Hash,
+    Set,
+    ZSet,
+}
+
+/// Conditions for `zset_add_opts`, mirroring the flags of Redis `ZADD`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ZAddOpts {
+    /// Only add new members, never update existing ones.
+    pub nx: bool,
+    /// Only update existing members, never add new ones.
+    pub xx: bool,
+    /// Only update an existing member if the new score is greater.
+    pub gt: bool,
+    /// Only update an existing member if the new score is lesser.
+    pub lt: bool,
+}
+
+/// A key holds one type of value at a time. Writing to it through the commands
+/// of another type fails with `DcsError::WrongType` until it is deleted.
+///
+/// Locks are always acquired in field declaration order, so `expirations`
+/// comes before any of the stores, and the shards of a store in index order. The
+/// AOF is written while holding the locks a write needs, and is the last lock
+/// taken.
 pub struct DCS {
-    store: RwLock<HashMap<String, String>>,
-    list_store: RwLock<HashMap<String, Vec<String>>>,
-    hash_store: RwLock<HashMap<String, HashMap<String, String>>>,
-    set_store: RwLock<HashMap<String, HashSet<String>>>,
-    zset_store: RwLock<HashMap<String, BTreeMap<String, f64>>>,
+    expirations: RwLock<HashMap<String, Instant>>,
+    store: Sharded<StringValue>,
+    list_store: Sharded<Vec<String>>,
+    hash_store: Sharded<HashMap<String, String>>,
+    set_store: Sharded<HashSet<String>>,
+    zset_store: Sharded<ZSet>,
+    /// Bumped after every list insertion to wake up blocked `list_bpop` callers.
+    list_pushes: Mutex<u64>,
+    list_pushed: Condvar,
+    rng: Mutex<Rng>,
+    /// Versions of string keys. Only ever locked last, while holding the string
+    /// store lock for a write.
+    versions: Mutex<Versions>,
+    /// Sizes and recency of strings, if there is a `DcsBuilder::max_memory`
+    /// budget. Only ever locked last, like `versions`, and never with it.
+    lru: Option<Mutex<Lru>>,
+    /// When each key was last read or written through the string commands or
+    /// `touch`. Only ever locked last, and never with `versions` or `lru`.
+    last_access: Sharded<Instant>,
+    /// Subscribers of each pub/sub channel.
+    channels: RwLock<HashMap<String, Vec<Sender<String>>>>,
+    /// Subscribers of keyspace events, sent to while holding the locks of the
+    /// write they describe.
+    keyspace_subscribers: RwLock<Vec<Sender<KeyEvent>>>,
+    /// Whether `keyspace_subscribers` is non-empty, so writes can skip building
+    /// events without taking its lock.
+    keyspace_enabled: AtomicBool,
+    counters: Counters,
+    /// TTL given to strings set without one, see `DcsBuilder::default_ttl`.
+    default_ttl: Option<Duration>,
+    aof: Option<Aof>,
+    /// Set while an AOF is replayed. Evictions are logged as `DEL` records, so
+    /// TTLs must not evict anything during replay.
+    replaying: bool,
+}
+
+impl Default for DCS {
+    fn default() -> Self {
+        DCS::new()
+    }
+}
+
+impl FromIterator<(String, String)> for DCS {
+    fn from_iter<I: IntoIterator<Item = (String, String)>>(pairs: I) -> Self {
+        DCS::from_pairs(pairs)
+    }
 }
 
 impl DCS {
     pub fn new() -> Self {
+        DCS::with_shards(DEFAULT_SHARDS)
+    }
+
+    /// Creates a store with every data type split into `shards` shards, at least
+    /// one. Writes to keys in different shards don't wait for each other, while
+    /// commands over the whole keyspace, like `keys`, lock every shard.
+    pub fn with_shards(shards: usize) -> Self {
+        DCS::with_router(shards, None)
+    }
+
+    /// Like `with_shards`, with every store picking shards through `router`.
+    pub(crate) fn with_router(shards: usize, router: Option<Arc<dyn ShardRouter>>) -> Self {
         DCS {
-            store: RwLock::new(HashMap::new()),
-            list_store: RwLock::new(HashMap::new()),
-            hash_store: RwLock::new(HashMap::new()),
-            set_store: RwLock::new(HashMap::new()),
-            zset_store: RwLock::new(HashMap::new()),
+            expirations: RwLock::new(HashMap::new()),
+            store: Sharded::new("strings", shards, router.clone()),
+            list_store: Sharded::new("lists", shards, router.clone()),
+            hash_store: Sharded::new("hashes", shards, router.clone()),
+            set_store: Sharded::new("sets", shards, router.clone()),
+            zset_store: Sharded::new("zsets", shards, router.clone()),
+            list_pushes: Mutex::new(0),
+            list_pushed: Condvar::new(),
+            rng: Mutex::new(Rng::new(RNG_SEED)),
+            versions: Mutex::new(Versions::new()),
+            lru: None,
+            last_access: Sharded::new("last_access", shards, router.clone()),
+            channels: RwLock::new(HashMap::new()),
+            keyspace_subscribers: RwLock::new(Vec::new()),
+            keyspace_enabled: AtomicBool::new(false),
+            counters: Counters::default(),
+            default_ttl: None,
+            aof: None,
+            replaying: false,
+        }
+    }
+
+    /// Creates a store holding the given strings, for seeding it in one go.
+    /// Later pairs win over earlier ones with the same key.
+    pub fn from_pairs(pairs: impl IntoIterator<Item = (String, String)>) -> Self {
+        let dcs = DCS::new();
+        dcs.store
+            .write_all()
+            .expect("a new store has no poisoned locks")
+            .extend(pairs.into_iter().map(|(key, value)| (key, value.into())));
+        dcs
+    }
+
+    /// Returns an independent copy of every store and TTL, taken under read locks
+    /// so it is a single point in time. The copy has the same configuration but
+    /// no AOF. Takes O(total data size), blocking writers while it copies.
+    pub fn snapshot(&self) -> Result<DCS, DcsError> {
+        let expirations = self.expirations.read().or_poisoned("expirations")?;
+        let store = self.store.read_all()?;
+        let list_store = self.list_store.read_all()?;
+        let hash_store = self.hash_store.read_all()?;
+        let set_store = self.set_store.read_all()?;
+        let zset_store = self.zset_store.read_all()?;
+        let last_access = self.last_access.read_all()?;
+        let lru = match &self.lru {
+            Some(lru) => Some(Mutex::new(lru.lock().or_poisoned("lru")?.clone())),
+            None => None,
+        };
+        let copy = DCS {
+            expirations: RwLock::new(expirations.clone()),
+            lru,
+            default_ttl: self.default_ttl,
+            ..DCS::with_router(self.store.shard_count(), self.store.router())
+        };
+        copy.store.write_all()?.extend(cloned(&store));
+        copy.list_store.write_all()?.extend(cloned(&list_store));
+        copy.hash_store.write_all()?.extend(cloned(&hash_store));
+        copy.set_store.write_all()?.extend(cloned(&set_store));
+        copy.zset_store.write_all()?.extend(cloned(&zset_store));
+        copy.last_access.write_all()?.extend(cloned(&last_access));
+        Ok(copy)
+    }
+
+    pub fn set(&self, key: String, value: String) -> Result<(), DcsError> {
+        self.evict_if_expired(&key)?;
+        self.check_budget(&key, value.len())?;
+        let len = value.len();
+        let mut store = self.write_typed(KeyType::String, &self.store, &key)?;
+        self.log(|r| r.arg("SET").arg(&key).arg(&value))?;
+        self.versions
+            .lock()
+            .or_poisoned("versions")?
+            .bump(&key, true);
+        store.insert(key.clone(), value.into());
+        drop(store);
+        self.track_string(&key, len)?;
+        self.apply_default_ttl(&key)
+    }
+
+    /// Returns the string at `key`. Fails with `InvalidUtf8` if it was set to
+    /// bytes that aren't valid UTF-8.
+    pub fn get(&self, key: &str) -> Result<Option<String>, DcsError> {
+        Ok(self.get_shared(key)?.map(|value| value.to_string()))
+    }
+
+    /// Like `get`, but returns the stored value itself rather than a copy of it,
+    /// which is cheaper for large values.
+    pub fn get_shared(&self, key: &str) -> Result<Option<Arc<str>>, DcsError> {
+        self.evict_if_expired(key)?;
+        let store = self.store.read(key)?;
+        let value = store.get(key);
+        self.counters.get_one(value.is_some());
+        if value.is_some() {
+            self.touch_string(key)?;
+        }
+        value.map(|value| value.text().cloned()).transpose()
+    }
+
+    /// Sets `key` to arbitrary bytes. Values that aren't valid UTF-8 can only be
+    /// read back with `get_bytes`; `get` fails on them with `InvalidUtf8`.
+    pub fn set_bytes(&self, key: String, value: Vec<u8>) -> Result<(), DcsError> {
+        self.evict_if_expired(&key)?;
+        self.check_budget(&key, value.len())?;
+        let len = value.len();
+        let mut store = self.write_typed(KeyType::String, &self.store, &key)?;
+        self.log(|r| r.arg("SETBYTES").arg(&key).arg(value::to_hex(&value)))?;
+        self.versions
+            .lock()
+            .or_poisoned("versions")?
+            .bump(&key, true);
+        store.insert(key.clone(), StringValue::from_bytes(value));
+        drop(store);
+        self.track_string(&key, len)?;
+        self.apply_default_ttl(&key)
+    }
+
+    /// Returns the bytes of the string at `key`, whether or not it is UTF-8.
+    pub fn get_bytes(&self, key: &str) -> Result<Option<Vec<u8>>, DcsError> {
+        self.evict_if_expired(key)?;
+        let store = self.store.read(key)?;
+        let value = store.get(key);
+        self.counters.get_one(value.is_some());
+        if value.is_some() {
+            self.touch_string(key)?;
+        }
+        Ok(value.map(|value| value.as_bytes().to_vec()))
+    }
+
+    /// Sets the bit at `offset` of the string at `key` and returns its previous
+    /// value. Bits are numbered from the most significant bit of the first byte,
+    /// and the string is padded with zero bytes to reach `offset`. Fails with
+    /// `IndexOutOfRange` if that would take more than 512MB.
+    pub fn set_bit(&self, key: String, offset: usize, value: bool) -> Result<bool, DcsError> {
+        if offset / 8 >= MAX_STRING_LEN {
+            return Err(DcsError::IndexOutOfRange);
+        }
+        self.evict_if_expired(&key)?;
+        let mut store = self.write_typed(KeyType::String, &self.store, &key)?;
+        let mut bytes = store
+            .get(&key)
+            .map(|current| current.as_bytes().to_vec())
+            .unwrap_or_default();
+        let (byte, mask) = (offset / 8, 0x80 >> (offset % 8));
+        if bytes.len() <= byte {
+            bytes.resize(byte + 1, 0);
+        }
+        let previous = bytes[byte] & mask != 0;
+        if value {
+            bytes[byte] |= mask;
+        } else {
+            bytes[byte] &= !mask;
+        }
+        self.check_budget(&key, bytes.len())?;
+        self.log(|r| r.arg("SETBIT").arg(&key).arg(offset).arg(u8::from(value)))?;
+        self.versions
+            .lock()
+            .or_poisoned("versions")?
+            .bump(&key, true);
+        let len = bytes.len();
+        store.insert(key.clone(), StringValue::from_bytes(bytes));
+        drop(store);
+        self.track_string(&key, len)?;
+        Ok(previous)
+    }
+
+    /// Returns the bit at `offset` of the string at `key`. Bits past the end of
+    /// the string, or of a missing key, are 0.
+    pub fn get_bit(&self, key: &str, offset: usize) -> Result<bool, DcsError> {
+        self.counters.read();
+        self.evict_if_expired(key)?;
+        let store = self.store.read(key)?;
+        let byte = store
+            .get(key)
+            .and_then(|value| value.as_bytes().get(offset / 8).copied())
+            .unwrap_or(0);
+        Ok(byte & (0x80 >> (offset % 8)) != 0)
+    }
+
+    /// Returns the number of set bits in the string at `key`.
+    pub fn bit_count(&self, key: &str) -> Result<usize, DcsError> {
+        self.counters.read();
+        self.evict_if_expired(key)?;
+        let store = self.store.read(key)?;
+        Ok(store.get(key).map_or(0, |value| {
+            value
+                .as_bytes()
+                .iter()
+                .map(|byte| byte.count_ones() as usize)
+                .sum()
+        }))
+    }
+
+    pub fn incr(&self, key: String) -> Result<i64, DcsError> {
+        self.incr_by(key, 1)
+    }
+
+    pub fn decr(&self, key: String) -> Result<i64, DcsError> {
+        self.incr_by(key, -1)
+    }
+
+    pub fn decr_by(&self, key: String, delta: i64) -> Result<i64, DcsError> {
+        let delta = delta.checked_neg().ok_or(DcsError::Overflow)?;
+        self.incr_by(key, delta)
+    }
+
+    /// Adds `delta` to the integer stored at `key`, treating a missing key as 0.
+    pub fn incr_by(&self, key: String, delta: i64) -> Result<i64, DcsError> {
+        self.evict_if_expired(&key)?;
+        let mut store = self.write_typed(KeyType::String, &self.store, &key)?;
+        let current = match store.get(&key) {
+            Some(value) => value.to_integer(&key)?,
+            None => 0,
+        };
+        let new_value = current.checked_add(delta).ok_or(DcsError::Overflow)?;
+        let new_text = new_value.to_string();
+        let len = new_text.len();
+        self.check_budget(&key, len)?;
+        self.log(|r| r.arg("INCRBY").arg(&key).arg(delta))?;
+        self.versions
+            .lock()
+            .or_poisoned("versions")?
+            .bump(&key, true);
+        store.insert(key.clone(), new_text.into());
+        drop(store);
+        self.track_string(&key, len)?;
+        Ok(new_value)
+    }
+
+    /// Appends `value` to the string at `key` and returns its new length in bytes.
+    pub fn append(&self, key: String, value: String) -> Result<usize, DcsError> {
+        self.evict_if_expired(&key)?;
+        let mut store = self.write_typed(KeyType::String, &self.store, &key)?;
+        let mut appended = store
+            .get(&key)
+            .map(|current| current.as_bytes().to_vec())
+            .unwrap_or_default();
+        appended.extend_from_slice(value.as_bytes());
+        let len = appended.len();
+        self.check_budget(&key, len)?;
+        self.log(|r| r.arg("APPEND").arg(&key).arg(&value))?;
+        self.versions
+            .lock()
+            .or_poisoned("versions")?
+            .bump(&key, true);
+        store.insert(key.clone(), StringValue::from_bytes(appended));
+        drop(store);
+        self.track_string(&key, len)?;
+        Ok(len)
+    }
+
+    /// Returns the length in bytes of the string at `key`, 0 if it is missing.
+    pub fn str_len(&self, key: &str) -> Result<usize, DcsError> {
+        self.counters.read();
+        self.evict_if_expired(key)?;
+        let store = self.store.read(key)?;
+        Ok(store.get(key).map_or(0, |value| value.as_bytes().len()))
+    }
+
+    /// Returns the bytes from `start` to `end` inclusive of the string at `key`,
+    /// counting from the end if negative. Fails with `InvalidUtf8` if the range
+    /// splits a character.
+    pub fn get_range(&self, key: &str, start: i64, end: i64) -> Result<String, DcsError> {
+        self.counters.read();
+        self.evict_if_expired(key)?;
+        let store = self.store.read(key)?;
+        let Some(value) = store.get(key) else {
+            return Ok(String::new());
+        };
+        let bytes = value.as_bytes();
+        match resolve_range(start, end, bytes.len()) {
+            Some((start, end)) => {
+                String::from_utf8(bytes[start..=end].to_vec()).map_err(|_| DcsError::InvalidUtf8)
+            }
+            None => Ok(String::new()),
+        }
+    }
+
+    /// Overwrites the string at `key` with `value` starting at byte `offset`,
+    /// padding it with zero bytes if it is shorter than that. Returns the new
+    /// length. An empty `value` leaves the key untouched. Fails with
+    /// `IndexOutOfRange` if the string would grow past 512MB.
+    pub fn set_range(&self, key: String, offset: usize, value: &str) -> Result<usize, DcsError> {
+        let end = offset.checked_add(value.len()).ok_or(DcsError::Overflow)?;
+        if end > MAX_STRING_LEN {
+            return Err(DcsError::IndexOutOfRange);
+        }
+        self.evict_if_expired(&key)?;
+        let mut store = self.write_typed(KeyType::String, &self.store, &key)?;
+        let mut bytes = store
+            .get(&key)
+            .map(|current| current.as_bytes().to_vec())
+            .unwrap_or_default();
+        if value.is_empty() {
+            return Ok(bytes.len());
         }
+        if bytes.len() < end {
+            bytes.resize(end, 0);
+        }
+        bytes[offset..end].copy_from_slice(value.as_bytes());
+        self.check_budget(&key, bytes.len())?;
+        self.log(|r| r.arg("SETRANGE").arg(&key).arg(offset).arg(value))?;
+        self.versions
+            .lock()
+            .or_poisoned("versions")?
+            .bump(&key, true);
+        let len = bytes.len();
+        store.insert(key.clone(), StringValue::from_bytes(bytes));
+        drop(store);
+        self.track_string(&key, len)?;
+        Ok(len)
+    }
+
+    /// Sets `key` to `value` and returns the previous value, if any.
+    pub fn get_set(&self, key: String, value: String) -> Result<Option<String>, DcsError> {
+        self.evict_if_expired(&key)?;
+        self.check_budget(&key, value.len())?;
+        let len = value.len();
+        let mut store = self.write_typed(KeyType::String, &self.store, &key)?;
+        let previous = store.get(&key).map(StringValue::to_text).transpose()?;
+        self.log(|r| r.arg("SET").arg(&key).arg(&value))?;
+        self.versions
+            .lock()
+            .or_poisoned("versions")?
+            .bump(&key, true);
+        store.insert(key.clone(), value.into());
+        drop(store);
+        self.track_string(&key, len)?;
+        self.apply_default_ttl(&key)?;
+        Ok(previous)
+    }
+
+    /// Returns the string at `key` and deletes it, in one step so that two callers
+    /// consuming the same value can't both get it. Only the string is removed; the
+    /// TTL goes too unless another store still holds the key.
+    pub fn get_del(&self, key: &str) -> Result<Option<String>, DcsError> {
+        self.evict_if_expired(key)?;
+        let store = self.store.write(key)?;
+        let Some(value) = store.get(key).map(StringValue::to_text).transpose()? else {
+            return Ok(None);
+        };
+        self.remove_string(store, key, "GETDEL")?;
+        Ok(Some(value))
+    }
+
+    /// Sets `key` only if it is not already present. Returns whether the write happened.
+    pub fn set_nx(&self, key: String, value: String) -> Result<bool, DcsError> {
+        self.evict_if_expired(&key)?;
+        self.check_budget(&key, value.len())?;
+        let len = value.len();
+        let mut store = self.write_typed(KeyType::String, &self.store, &key)?;
+        if store.contains_key(&key) {
+            return Ok(false);
+        }
+        self.log(|r| r.arg("SET").arg(&key).arg(&value))?;
+        self.versions
+            .lock()
+            .or_poisoned("versions")?
+            .bump(&key, true);
+        store.insert(key.clone(), value.into());
+        drop(store);
+        self.track_string(&key, len)?;
+        self.apply_default_ttl(&key)?;
+        Ok(true)
+    }
+
+    /// Returns the string at `key`, or if it is missing sets it to what `f`
+    /// returns and returns that. `f` runs under the write lock of the key, so
+    /// concurrent callers missing the same key run it only once between them.
+    pub fn get_or_set_with<F: FnOnce() -> String>(
+        &self,
+        key: &str,
+        f: F,
+    ) -> Result<String, DcsError> {
+        self.evict_if_expired(key)?;
+        let mut store = self.write_typed(KeyType::String, &self.store, key)?;
+        if let Some(value) = store.get(key) {
+            self.counters.get_one(true);
+            self.touch_string(key)?;
+            return value.to_text();
+        }
+        self.counters.get_one(false);
+        let value = f();
+        self.check_budget(key, value.len())?;
+        self.log(|r| r.arg("SET").arg(key).arg(&value))?;
+        self.versions
+            .lock()
+            .or_poisoned("versions")?
+            .bump(key, true);
+        store.insert(key.to_string(), value.as_str().into());
+        drop(store);
+        self.track_string(key, value.len())?;
+        self.apply_default_ttl(key)?;
+        Ok(value)
+    }
+
+    pub fn mset(&self, pairs: Vec<(String, String)>) -> Result<(), DcsError> {
+        for (key, value) in &pairs {
+            self.evict_if_expired(key)?;
+            self.check_budget(key, value.len())?;
+        }
+        let mut store = self.write_typed_keys(
+            KeyType::String,
+            &self.store,
+            pairs.iter().map(|(key, _)| key),
+        )?;
+        self.log(|r| {
+            r.arg("MSET");
+            for (key, value) in &pairs {
+                r.arg(key).arg(value);
+            }
+            r
+        })?;
+        let mut versions = self.versions.lock().or_poisoned("versions")?;
+        for (key, _) in &pairs {
+            versions.bump(key, true);
+        }
+        drop(versions);
+        let written: Vec<(String, usize)> = pairs
+            .iter()
+            .map(|(key, value)| (key.clone(), value.len()))
+            .collect();
+        store.extend(pairs.into_iter().map(|(key, value)| (key, value.into())));
+        drop(store);
+        for (key, len) in &written {
+            self.track_string(key, *len)?;
+            self.apply_default_ttl(key)?;
+        }
+        Ok(())
+    }
+
+    pub fn mget(&self, keys: &[String]) -> Result<Vec<Option<String>>, DcsError> {
+        for key in keys {
+            self.evict_if_expired(key)?;
+        }
+        let store = self.store.read_keys(keys)?;
+        let hits = keys.iter().filter(|key| store.contains_key(key)).count();
+        self.counters.get(hits as u64, (keys.len() - hits) as u64);
+        for key in keys.iter().filter(|key| store.contains_key(key)) {
+            self.touch_string(key)?;
+        }
+        keys.iter()
+            .map(|key| store.get(key).map(StringValue::to_text).transpose())
+            .collect()
+    }
+
+    /// Like `get`, but also returns the version of `key` for `compare_and_set`.
+    /// A missing key is at version 0.
+    pub fn watch_get(&self, key: &str) -> Result<(Option<String>, u64), DcsError> {
+        self.evict_if_expired(key)?;
+        let store = self.store.read(key)?;
+        self.counters.get_one(store.contains_key(key));
+        if store.contains_key(key) {
+            self.touch_string(key)?;
+        }
+        let value = store.get(key).map(StringValue::to_text).transpose()?;
+        let version = self
+            .versions
+            .lock()
+            .or_poisoned("versions")?
+            .get(key, value.is_some());
+        Ok((value, version))
     }
 
-    pub fn set(
+    /// Sets `key` to `value` only if it is still at `expected_version`, as returned
+    /// by `watch_get`. Returns `false` if the string was written to in between.
+    pub fn compare_and_set(
         &self,
         key: String,
         value: String,
-    ) -> Result<(), PoisonError<RwLockWriteGuard<HashMap<String, String>>>> {
-        let mut store = self.store.write()?;
-        store.insert(key, value);
+        expected_version: u64,
+    ) -> Result<bool, DcsError> {
+        self.evict_if_expired(&key)?;
+        self.check_budget(&key, value.len())?;
+        let len = value.len();
+        let mut store = self.write_typed(KeyType::String, &self.store, &key)?;
+        let mut versions = self.versions.lock().or_poisoned("versions")?;
+        if versions.get(&key, store.contains_key(&key)) != expected_version {
+            return Ok(false);
+        }
+        self.log(|r| r.arg("SET").arg(&key).arg(&value))?;
+        versions.bump(&key, true);
+        drop(versions);
+        store.insert(key.clone(), value.into());
+        drop(store);
+        self.track_string(&key, len)?;
+        Ok(true)
+    }
+
+    /// Removes `key` from every store it appears in, along with any TTL.
+    /// Returns `true` if it was present in at least one of them.
+    pub fn del(&self, key: &str) -> Result<bool, DcsError> {
+        if self.evict_if_expired(key)? {
+            return Ok(false);
+        }
+        let mut expirations = self.expirations.write().or_poisoned("expirations")?;
+        expirations.remove(key);
+        self.remove_from_stores(&[key])
+    }
+
+    /// Returns `true` if `key` is present in any store.
+    pub fn exists(&self, key: &str) -> Result<bool, DcsError> {
+        self.counters.read();
+        self.evict_if_expired(key)?;
+        self.contains_key(key)
+    }
+
+    /// Returns every store `key` currently appears in.
+    pub fn type_of(&self, key: &str) -> Result<Vec<KeyType>, DcsError> {
+        self.counters.read();
+        self.evict_if_expired(key)?;
+        self.stored_types(key)
+    }
+
+    /// Returns the number of distinct keys across all stores. Keys with an elapsed
+    /// TTL that have not been evicted yet are not counted.
+    pub fn db_size(&self) -> Result<usize, DcsError> {
+        self.counters.read();
+        let expirations = self.expirations.read().or_poisoned("expirations")?;
+        let store = self.store.read_all()?;
+        let list_store = self.list_store.read_all()?;
+        let hash_store = self.hash_store.read_all()?;
+        let set_store = self.set_store.read_all()?;
+        let zset_store = self.zset_store.read_all()?;
+        let now = Instant::now();
+        let keys: HashSet<&String> = store
+            .keys()
+            .chain(list_store.keys())
+            .chain(hash_store.keys())
+            .chain(set_store.keys())
+            .chain(zset_store.keys())
+            .filter(|key| expirations.get(*key).is_none_or(|deadline| *deadline > now))
+            .collect();
+        Ok(keys.len())
+    }
+
+    /// Returns the distinct keys across all stores matching the glob `pattern`.
+    /// Supports `*`, `?`, `[...]` classes and `\` escapes. Takes a read lock on
+    /// every store for the duration of the scan.
+    pub fn keys(&self, pattern: &str) -> Result<Vec<String>, DcsError> {
+        self.counters.read();
+        let expirations = self.expirations.read().or_poisoned("expirations")?;
+        let store = self.store.read_all()?;
+        let list_store = self.list_store.read_all()?;
+        let hash_store = self.hash_store.read_all()?;
+        let set_store = self.set_store.read_all()?;
+        let zset_store = self.zset_store.read_all()?;
+        let now = Instant::now();
+        let keys: HashSet<&String> = store
+            .keys()
+            .chain(list_store.keys())
+            .chain(hash_store.keys())
+            .chain(set_store.keys())
+            .chain(zset_store.keys())
+            .filter(|key| expirations.get(*key).is_none_or(|deadline| *deadline > now))
+            .filter(|key| glob_match(pattern, key))
+            .collect();
+        Ok(keys.into_iter().cloned().collect())
+    }
+
+    /// Returns every string key with its value. The pairs are copied out under
+    /// read locks rather than borrowed, so the store can change while the caller
+    /// goes through them. Expired keys and values that aren't valid UTF-8 are
+    /// left out.
+    pub fn iter_strings(&self) -> Result<Vec<(String, String)>, DcsError> {
+        self.counters.read();
+        let expirations = self.expirations.read().or_poisoned("expirations")?;
+        let store = self.store.read_all()?;
+        let now = Instant::now();
+        Ok(store
+            .iter()
+            .filter(|(key, _)| expirations.get(*key).is_none_or(|deadline| *deadline > now))
+            .filter_map(|(key, value)| Some((key.clone(), value.to_text().ok()?)))
+            .collect())
+    }
+
+    /// Incrementally iterates the keyspace. Start with cursor 0 and pass each
+    /// returned cursor back in until 0 is returned again.
+    ///
+    /// Keys are visited in order of a 64-bit hash, and the cursor is the hash to
+    /// resume from. A key present for the whole scan is returned exactly once, keys
+    /// added or removed in between may or may not be. Stores are locked one at a
+    /// time. A batch can hold more than `count` keys if some of them share a hash.
+    pub fn scan(&self, cursor: u64, count: usize) -> Result<(u64, Vec<String>), DcsError> {
+        self.counters.read();
+        let count = count.max(1);
+        let mut batch: BTreeMap<u64, Vec<String>> = BTreeMap::new();
+        let mut truncated = false;
+        let mut visit = |key: &String| {
+            let hash = scan_hash(key);
+            if hash < cursor {
+                return;
+            }
+            if batch.len() >= count && batch.last_key_value().is_some_and(|(last, _)| hash > *last)
+            {
+                truncated = true;
+                return;
+            }
+            let keys = batch.entry(hash).or_default();
+            if !keys.contains(key) {
+                keys.push(key.clone());
+            }
+            if batch.len() > count {
+                batch.pop_last();
+                truncated = true;
+            }
+        };
+        self.store.read_all()?.keys().for_each(&mut visit);
+        self.list_store.read_all()?.keys().for_each(&mut visit);
+        self.hash_store.read_all()?.keys().for_each(&mut visit);
+        self.set_store.read_all()?.keys().for_each(&mut visit);
+        self.zset_store.read_all()?.keys().for_each(&mut visit);
+
+        let next = match batch.last_key_value() {
+            Some((last, _)) if truncated => last.checked_add(1).unwrap_or(0),
+            _ => 0,
+        };
+        let expirations = self.expirations.read().or_poisoned("expirations")?;
+        let now = Instant::now();
+        let keys = batch
+            .into_values()
+            .flatten()
+            .filter(|key| expirations.get(key).is_none_or(|deadline| *deadline > now))
+            .collect();
+        Ok((next, keys))
+    }
+
+    /// Moves the value of `old_key` to `new_key`, replacing whatever `new_key`
+    /// held in any store, and carries the TTL over. Returns `false` if `old_key`
+    /// does not exist.
+    pub fn rename(&self, old_key: &str, new_key: String) -> Result<bool, DcsError> {
+        self.rename_key(old_key, new_key, false)
+    }
+
+    /// Like `rename`, but does nothing and returns `false` if `new_key` exists in
+    /// any store, which includes renaming a key onto itself.
+    pub fn rename_nx(&self, old_key: &str, new_key: String) -> Result<bool, DcsError> {
+        self.rename_key(old_key, new_key, true)
+    }
+
+    fn rename_key(&self, old_key: &str, new_key: String, nx: bool) -> Result<bool, DcsError> {
+        self.evict_if_expired(old_key)?;
+        self.evict_if_expired(&new_key)?;
+        let mut expirations = self.expirations.write().or_poisoned("expirations")?;
+        let mut store = self.store.write_keys([old_key, new_key.as_str()])?;
+        let mut list_store = self.list_store.write_keys([old_key, new_key.as_str()])?;
+        let mut hash_store = self.hash_store.write_keys([old_key, new_key.as_str()])?;
+        let mut set_store = self.set_store.write_keys([old_key, new_key.as_str()])?;
+        let mut zset_store = self.zset_store.write_keys([old_key, new_key.as_str()])?;
+        let moved: Vec<KeyType> = [
+            (KeyType::String, store.contains_key(old_key)),
+            (KeyType::List, list_store.contains_key(old_key)),
+            (KeyType::Hash, hash_store.contains_key(old_key)),
+            (KeyType::Set, set_store.contains_key(old_key)),
+            (KeyType::ZSet, zset_store.contains_key(old_key)),
+        ]
+        .into_iter()
+        .filter_map(|(ty, held)| held.then_some(ty))
+        .collect();
+        let exists = !moved.is_empty();
+        let taken = store.contains_key(&new_key)
+            || list_store.contains_key(&new_key)
+            || hash_store.contains_key(&new_key)
+            || set_store.contains_key(&new_key)
+            || zset_store.contains_key(&new_key);
+        if nx && taken {
+            return Ok(false);
+        }
+        if !exists || old_key == new_key {
+            return Ok(exists);
+        }
+        self.log(|r| r.arg("RENAME").arg(old_key).arg(&new_key))?;
+        store.remove(&new_key);
+        list_store.remove(&new_key);
+        hash_store.remove(&new_key);
+        set_store.remove(&new_key);
+        zset_store.remove(&new_key);
+        self.untrack(&new_key)?;
+        if moved.contains(&KeyType::String) {
+            self.with_lru(|lru| lru.rename(old_key, &new_key))?;
+        }
+        rename_entry(
+            &mut self.last_access.write_keys([old_key, new_key.as_str()])?,
+            old_key,
+            &new_key,
+        );
+        rename_entry(&mut store, old_key, &new_key);
+        rename_entry(&mut list_store, old_key, &new_key);
+        rename_entry(&mut hash_store, old_key, &new_key);
+        rename_entry(&mut set_store, old_key, &new_key);
+        rename_entry(&mut zset_store, old_key, &new_key);
+        for ty in moved {
+            self.notify_keyspace(old_key, ty, KeyOp::RenameFrom)?;
+            self.notify_keyspace(&new_key, ty, KeyOp::RenameTo)?;
+        }
+        let mut versions = self.versions.lock().or_poisoned("versions")?;
+        versions.bump(old_key, false);
+        versions.bump(&new_key, store.contains_key(&new_key));
+        match expirations.remove(old_key) {
+            Some(deadline) => expirations.insert(new_key, deadline),
+            None => expirations.remove(&new_key),
+        };
+        Ok(true)
+    }
+
+    /// Deep-copies `src` into `dst`, along with its TTL. Unless `replace` is set,
+    /// nothing is written if `dst` already exists in any store. Returns whether
+    /// the copy happened; copying a key onto itself never does.
+    pub fn copy(&self, src: &str, dst: String, replace: bool) -> Result<bool, DcsError> {
+        self.evict_if_expired(src)?;
+        self.evict_if_expired(&dst)?;
+        if src == dst {
+            return Ok(false);
+        }
+        let mut expirations = self.expirations.write().or_poisoned("expirations")?;
+        let mut store = self.store.write_keys([src, dst.as_str()])?;
+        let mut list_store = self.list_store.write_keys([src, dst.as_str()])?;
+        let mut hash_store = self.hash_store.write_keys([src, dst.as_str()])?;
+        let mut set_store = self.set_store.write_keys([src, dst.as_str()])?;
+        let mut zset_store = self.zset_store.write_keys([src, dst.as_str()])?;
+        let in_string = store.contains_key(src);
+        let in_list = list_store.contains_key(src);
+        let in_hash = hash_store.contains_key(src);
+        let in_set = set_store.contains_key(src);
+        let in_zset = zset_store.contains_key(src);
+        if !(in_string || in_list || in_hash || in_set || in_zset) {
+            return Ok(false);
+        }
+        let dst_taken = store.contains_key(&dst)
+            || list_store.contains_key(&dst)
+            || hash_store.contains_key(&dst)
+            || set_store.contains_key(&dst)
+            || zset_store.contains_key(&dst);
+        if dst_taken && !replace {
+            return Ok(false);
+        }
+        self.log(|r| r.arg("COPY").arg(src).arg(&dst))?;
+        store.remove(&dst);
+        list_store.remove(&dst);
+        hash_store.remove(&dst);
+        set_store.remove(&dst);
+        zset_store.remove(&dst);
+        self.untrack(&dst)?;
+        copy_entry(&mut store, src, &dst);
+        copy_entry(&mut list_store, src, &dst);
+        copy_entry(&mut hash_store, src, &dst);
+        copy_entry(&mut set_store, src, &dst);
+        copy_entry(&mut zset_store, src, &dst);
+        let copied = [
+            (KeyType::String, in_string),
+            (KeyType::List, in_list),
+            (KeyType::Hash, in_hash),
+            (KeyType::Set, in_set),
+            (KeyType::ZSet, in_zset),
+        ];
+        for (ty, _) in copied.into_iter().filter(|(_, held)| *held) {
+            self.notify_keyspace(&dst, ty, KeyOp::Set)?;
+        }
+        self.versions
+            .lock()
+            .or_poisoned("versions")?
+            .bump(&dst, store.contains_key(&dst));
+        let copied_len = in_string.then(|| store.get(&dst).map(|value| value.as_bytes().len()));
+        match expirations.get(src).copied() {
+            Some(deadline) => expirations.insert(dst.clone(), deadline),
+            None => expirations.remove(&dst),
+        };
+        drop((
+            expirations,
+            store,
+            list_store,
+            hash_store,
+            set_store,
+            zset_store,
+        ));
+        if let Some(Some(len)) = copied_len {
+            self.track_string(&dst, len)?;
+        }
+        Ok(true)
+    }
+
+    fn stored_types(&self, key: &str) -> Result<Vec<KeyType>, DcsError> {
+        let mut types = Vec::new();
+        if self.store.read(key)?.contains_key(key) {
+            types.push(KeyType::String);
+        }
+        if self.list_store.read(key)?.contains_key(key) {
+            types.push(KeyType::List);
+        }
+        if self.hash_store.read(key)?.contains_key(key) {
+            types.push(KeyType::Hash);
+        }
+        if self.set_store.read(key)?.contains_key(key) {
+            types.push(KeyType::Set);
+        }
+        if self.zset_store.read(key)?.contains_key(key) {
+            types.push(KeyType::ZSet);
+        }
+        Ok(types)
+    }
+
+    /// Sets a TTL on `key`. Returns `false` if the key does not exist.
+    pub fn expire(&self, key: &str, ttl: Duration) -> Result<bool, DcsError> {
+        self.expire_at(key, Instant::now() + ttl)
+    }
+
+    fn expire_at(&self, key: &str, deadline: Instant) -> Result<bool, DcsError> {
+        self.evict_if_expired(key)?;
+        let mut expirations = self.expirations.write().or_poisoned("expirations")?;
+        if !self.contains_key(key)? {
+            return Ok(false);
+        }
+        self.set_deadline(&mut expirations, key, deadline)?;
+        Ok(true)
+    }
+
+    /// Gives `key` the default TTL, if there is one and the key has no TTL yet.
+    /// Not applied during replay, where the TTL comes from its own record.
+    fn apply_default_ttl(&self, key: &str) -> Result<(), DcsError> {
+        let Some(ttl) = self.default_ttl else {
+            return Ok(());
+        };
+        if self.replaying {
+            return Ok(());
+        }
+        let mut expirations = self.expirations.write().or_poisoned("expirations")?;
+        if expirations.contains_key(key) || !self.contains_key(key)? {
+            return Ok(());
+        }
+        self.set_deadline(&mut expirations, key, Instant::now() + ttl)
+    }
+
+    /// Fails with `OomRejected` if a string of `len` bytes at `key` would not fit
+    /// in the memory budget even with every other string evicted.
+    fn check_budget(&self, key: &str, len: usize) -> Result<(), DcsError> {
+        let Some(lru) = &self.lru else {
+            return Ok(());
+        };
+        if !self.replaying && key.len() + len > lru.lock().or_poisoned("lru")?.max_bytes() {
+            return Err(DcsError::OomRejected);
+        }
+        Ok(())
+    }
+
+    /// Records the string at `key` as `len` bytes long and just used, then
+    /// deletes the least recently used other strings until the memory budget is
+    /// met again. Must be called without holding any store lock.
+    fn track_string(&self, key: &str, len: usize) -> Result<(), DcsError> {
+        self.record_access(key)?;
+        let Some(lru) = &self.lru else {
+            return Ok(());
+        };
+        lru.lock().or_poisoned("lru")?.record(key, key.len() + len);
+        if self.replaying {
+            return Ok(());
+        }
+        loop {
+            let victim = {
+                let lru = lru.lock().or_poisoned("lru")?;
+                if !lru.over_budget() {
+                    return Ok(());
+                }
+                match lru.oldest_except(key) {
+                    Some(victim) => victim,
+                    None => return Ok(()),
+                }
+            };
+            self.remove_string(self.store.write(&victim)?, &victim, "EVICT")?;
+            lru.lock().or_poisoned("lru")?.remove(&victim);
+        }
+    }
+
+    /// Removes the string at `key` from `store`, leaving the other stores alone, and
+    /// logs it as `command`. The key's TTL and last access go too unless another
+    /// store still holds it. Returns whether there was a string.
+    pub(crate) fn remove_string(
+        &self,
+        mut store: WriteShards<'_, StringValue>,
+        key: &str,
+        command: &str,
+    ) -> Result<bool, DcsError> {
+        if store.remove(key).is_none() {
+            return Ok(false);
+        }
+        self.log(|r| r.arg(command).arg(key))?;
+        self.versions
+            .lock()
+            .or_poisoned("versions")?
+            .bump(key, false);
+        self.notify_keyspace(key, KeyType::String, KeyOp::Del)?;
+        drop(store);
+        self.with_lru(|lru| lru.remove(key))?;
+        self.drop_ttl_if_gone(key)?;
+        Ok(true)
+    }
+
+    fn touch_string(&self, key: &str) -> Result<(), DcsError> {
+        self.record_access(key)?;
+        self.with_lru(|lru| lru.touch(key))
+    }
+
+    /// Forgets the size and last access of `key`, once it is deleted.
+    fn untrack(&self, key: &str) -> Result<(), DcsError> {
+        self.last_access.write(key)?.remove(key);
+        self.with_lru(|lru| lru.remove(key))
+    }
+
+    fn record_access(&self, key: &str) -> Result<(), DcsError> {
+        self.last_access
+            .write(key)?
+            .insert(key.to_string(), Instant::now());
+        Ok(())
+    }
+
+    fn with_lru(&self, f: impl FnOnce(&mut Lru)) -> Result<(), DcsError> {
+        if let Some(lru) = &self.lru {
+            f(&mut *lru.lock().or_poisoned("lru")?);
+        }
+        Ok(())
+    }
+
+    /// Logs and records `deadline` as the TTL of `key`, which must exist.
+    fn set_deadline(
+        &self,
+        expirations: &mut HashMap<String, Instant>,
+        key: &str,
+        deadline: Instant,
+    ) -> Result<(), DcsError> {
+        self.log(|r| {
+            r.arg("PEXPIREAT")
+                .arg(key)
+                .arg(expiry::to_unix_millis(deadline))
+        })?;
+        expirations.insert(key.to_string(), deadline);
+        if self.keyspace_enabled() {
+            for ty in self.stored_types(key)? {
+                self.notify_keyspace(key, ty, KeyOp::Expire)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the time left before `key` expires, or `None` if it has no TTL.
+    pub fn ttl(&self, key: &str) -> Result<Option<Duration>, DcsError> {
+        self.counters.read();
+        self.evict_if_expired(key)?;
+        let expirations = self.expirations.read().or_poisoned("expirations")?;
+        Ok(expirations
+            .get(key)
+            .map(|deadline| deadline.saturating_duration_since(Instant::now())))
+    }
+
+    /// Marks each of `keys` that exists as just accessed, for `idle_time` and the
+    /// `DcsBuilder::max_memory` eviction order. Returns how many exist.
+    pub fn touch(&self, keys: &[String]) -> Result<usize, DcsError> {
+        self.counters.read();
+        let mut touched = 0;
+        for key in keys {
+            self.evict_if_expired(key)?;
+            if self.contains_key(key)? {
+                self.touch_string(key)?;
+                touched += 1;
+            }
+        }
+        Ok(touched)
+    }
+
+    /// Returns how long ago `key` was last read or written through the string
+    /// commands, or passed to `touch`. `None` if it doesn't exist or was never
+    /// accessed that way, like a list only ever written by list commands.
+    pub fn idle_time(&self, key: &str) -> Result<Option<Duration>, DcsError> {
+        self.counters.read();
+        self.evict_if_expired(key)?;
+        if !self.contains_key(key)? {
+            return Ok(None);
+        }
+        let last_access = self.last_access.read(key)?;
+        Ok(last_access.get(key).map(Instant::elapsed))
+    }
+
+    /// Removes the TTL from `key`. Returns `true` if one was cleared.
+    pub fn persist(&self, key: &str) -> Result<bool, DcsError> {
+        self.evict_if_expired(key)?;
+        let mut expirations = self.expirations.write().or_poisoned("expirations")?;
+        if !expirations.contains_key(key) {
+            return Ok(false);
+        }
+        self.log(|r| r.arg("PERSIST").arg(key))?;
+        expirations.remove(key);
+        if self.keyspace_enabled() {
+            for ty in self.stored_types(key)? {
+                self.notify_keyspace(key, ty, KeyOp::Persist)?;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Empties every store and drops all TTLs.
+    pub fn flush_all(&self) -> Result<(), DcsError> {
+        let mut expirations = self.expirations.write().or_poisoned("expirations")?;
+        let mut store = self.store.write_all()?;
+        let mut list_store = self.list_store.write_all()?;
+        let mut hash_store = self.hash_store.write_all()?;
+        let mut set_store = self.set_store.write_all()?;
+        let mut zset_store = self.zset_store.write_all()?;
+        self.log(|r| r.arg("FLUSHALL"))?;
+        if self.keyspace_enabled() {
+            let stores = [
+                (KeyType::String, store.keys().collect::<Vec<_>>()),
+                (KeyType::List, list_store.keys().collect()),
+                (KeyType::Hash, hash_store.keys().collect()),
+                (KeyType::Set, set_store.keys().collect()),
+                (KeyType::ZSet, zset_store.keys().collect()),
+            ];
+            for (ty, keys) in stores {
+                for key in keys {
+                    self.notify_keyspace(key, ty, KeyOp::Del)?;
+                }
+            }
+        }
+        self.versions.lock().or_poisoned("versions")?.clear();
+        self.with_lru(Lru::clear)?;
+        self.last_access.write_all()?.clear();
+        expirations.clear();
+        store.clear();
+        list_store.clear();
+        hash_store.clear();
+        set_store.clear();
+        zset_store.clear();
+        Ok(())
+    }
+
+    /// Empties a single store. TTLs are dropped for keys that no longer exist anywhere.
+    pub fn flush_type(&self, ty: KeyType) -> Result<(), DcsError> {
+        let mut expirations = self.expirations.write().or_poisoned("expirations")?;
+        let flushed = match ty {
+            KeyType::String => self.drain_store(&self.store, ty)?,
+            KeyType::List => self.drain_store(&self.list_store, ty)?,
+            KeyType::Hash => self.drain_store(&self.hash_store, ty)?,
+            KeyType::Set => self.drain_store(&self.set_store, ty)?,
+            KeyType::ZSet => self.drain_store(&self.zset_store, ty)?,
+        };
+        if ty == KeyType::String {
+            self.versions.lock().or_poisoned("versions")?.clear();
+            self.with_lru(Lru::clear)?;
+        }
+        for key in flushed {
+            if !self.contains_key(&key)? {
+                expirations.remove(&key);
+                self.untrack(&key)?;
+            }
+        }
         Ok(())
     }
 
-    pub fn get(
-        &self,
-        key: &str,
-    ) -> Result<Option<String>, PoisonError<RwLockReadGuard<HashMap<String, String>>>> {
-        let store = self.store.read()?;
-        Ok(store.get(key).cloned())
+    /// Empties one store under the write locks of all its shards and returns the
+    /// keys it held.
+    fn drain_store<V>(&self, store: &Sharded<V>, ty: KeyType) -> Result<Vec<String>, DcsError> {
+        let mut store = store.write_all()?;
+        self.log(|r| r.arg("FLUSHSTORE").arg(key_type_name(ty)))?;
+        let keys = store.drain_keys();
+        for key in &keys {
+            self.notify_keyspace(key, ty, KeyOp::Del)?;
+        }
+        Ok(keys)
+    }
+
+    fn contains_key(&self, key: &str) -> Result<bool, DcsError> {
+        Ok(self.store.read(key)?.contains_key(key)
+            || self.list_store.read(key)?.contains_key(key)
+            || self.hash_store.read(key)?.contains_key(key)
+            || self.set_store.read(key)?.contains_key(key)
+            || self.zset_store.read(key)?.contains_key(key))
+    }
+
+    /// Removes each of `keys` from every store, logging a `DEL` for each one found.
+    /// Returns `true` if anything was removed.
+    fn remove_from_stores<K: AsRef<str>>(&self, keys: &[K]) -> Result<bool, DcsError> {
+        let mut store = self.store.write_keys(keys)?;
+        self.remove_from_stores_locked(&mut store, keys)
+    }
+
+    /// Like `remove_from_stores`, for callers already holding the string store
+    /// shards of `keys`.
+    fn remove_from_stores_locked<K: AsRef<str>>(
+        &self,
+        store: &mut WriteShards<'_, StringValue>,
+        keys: &[K],
+    ) -> Result<bool, DcsError> {
+        let mut list_store = self.list_store.write_keys(keys)?;
+        let mut hash_store = self.hash_store.write_keys(keys)?;
+        let mut set_store = self.set_store.write_keys(keys)?;
+        let mut zset_store = self.zset_store.write_keys(keys)?;
+        let mut removed = false;
+        for key in keys {
+            let key = key.as_ref();
+            let found = [
+                (KeyType::String, store.remove(key).is_some()),
+                (KeyType::List, list_store.remove(key).is_some()),
+                (KeyType::Hash, hash_store.remove(key).is_some()),
+                (KeyType::Set, set_store.remove(key).is_some()),
+                (KeyType::ZSet, zset_store.remove(key).is_some()),
+            ];
+            if !found.iter().any(|(_, held)| *held) {
+                continue;
+            }
+            self.log(|r| r.arg("DEL").arg(key))?;
+            self.untrack(key)?;
+            for (ty, _) in found.into_iter().filter(|(_, held)| *held) {
+                if ty == KeyType::String {
+                    self.versions
+                        .lock()
+                        .or_poisoned("versions")?
+                        .bump(key, false);
+                }
+                self.notify_keyspace(key, ty, KeyOp::Del)?;
+            }
+            removed = true;
+        }
+        Ok(removed)
+    }
+
+    fn notify_list_push(&self) -> Result<(), DcsError> {
+        *self.list_pushes.lock().or_poisoned("list_pushes")? += 1;
+        self.list_pushed.notify_all();
+        Ok(())
+    }
+
+    /// Drops the TTL and last access of `key` once it no longer exists in any
+    /// store, so a later write to the same key does not inherit them.
+    fn drop_ttl_if_gone(&self, key: &str) -> Result<(), DcsError> {
+        let mut expirations = self.expirations.write().or_poisoned("expirations")?;
+        if !self.contains_key(key)? {
+            expirations.remove(key);
+            self.untrack(key)?;
+        }
+        Ok(())
+    }
+
+    /// Releases `shards`, first removing `key` from them if its collection was
+    /// emptied, since like in Redis an empty list, hash, set or zset doesn't
+    /// exist. The key's TTL goes too once it is gone from every store.
+    fn remove_if_empty<V>(
+        &self,
+        mut shards: Typed<'_, V>,
+        key: &str,
+        is_empty: fn(&V) -> bool,
+    ) -> Result<(), DcsError> {
+        let emptied = remove_if_empty(&mut shards, key, is_empty);
+        drop(shards);
+        if emptied {
+            self.drop_ttl_if_gone(key)?;
+        }
+        Ok(())
+    }
+
+    /// Write-locks the shard of `key` in `store`, the store of `ty`. Fails with
+    /// `WrongType` if another store holds `key`, see `write_typed_keys`.
+    fn write_typed<'a, V>(
+        &'a self,
+        ty: KeyType,
+        store: &'a Sharded<V>,
+        key: &str,
+    ) -> Result<Typed<'a, V>, DcsError> {
+        self.write_typed_keys(ty, store, [key])
+    }
+
+    /// Write-locks the shards of `keys` in `store`, the store of `ty`. Fails with
+    /// `WrongType` if another store holds one of `keys`, so that a key only ever
+    /// has one type.
+    ///
+    /// The check is made under read locks on the shards of `keys` in every other
+    /// store, taken in field declaration order along with the write lock and
+    /// held until the guard is dropped, so no write through another store can
+    /// take one of `keys` in between. It is skipped during replay, which must
+    /// rebuild files written back when a key could be held by several stores.
+    fn write_typed_keys<'a, V, K: AsRef<str>>(
+        &'a self,
+        ty: KeyType,
+        store: &'a Sharded<V>,
+        keys: impl IntoIterator<Item = K> + Clone,
+    ) -> Result<Typed<'a, V>, DcsError> {
+        let mut shards = None;
+        let mut write = |held: KeyType| -> Result<(), DcsError> {
+            if held == ty {
+                shards = Some(store.write_keys(keys.clone())?);
+            }
+            Ok(())
+        };
+        let strings = read_other(&self.store, ty == KeyType::String, keys.clone())?;
+        write(KeyType::String)?;
+        let lists = read_other(&self.list_store, ty == KeyType::List, keys.clone())?;
+        write(KeyType::List)?;
+        let hashes = read_other(&self.hash_store, ty == KeyType::Hash, keys.clone())?;
+        write(KeyType::Hash)?;
+        let sets = read_other(&self.set_store, ty == KeyType::Set, keys.clone())?;
+        write(KeyType::Set)?;
+        let zsets = read_other(&self.zset_store, ty == KeyType::ZSet, keys.clone())?;
+        write(KeyType::ZSet)?;
+        let claim = Claim {
+            strings,
+            lists,
+            hashes,
+            sets,
+            zsets,
+        };
+        if !self.replaying {
+            for key in keys {
+                if let Some(found) = claim.found(key.as_ref()) {
+                    return Err(wrong_type(key.as_ref(), ty, found));
+                }
+            }
+        }
+        Ok(Typed {
+            shards: shards.expect("ty is the type of one of the stores"),
+            _claim: claim,
+        })
+    }
+
+    /// Removes `key` from every store if its TTL has passed. Returns `true` if
+    /// it was evicted.
+    fn evict_if_expired(&self, key: &str) -> Result<bool, DcsError> {
+        if self.replaying {
+            return Ok(false);
+        }
+        let now = Instant::now();
+        let expired = self
+            .expirations
+            .read()
+            .or_poisoned("expirations")?
+            .get(key)
+            .is_some_and(|deadline| *deadline <= now);
+        if !expired {
+            return Ok(false);
+        }
+        let mut expirations = self.expirations.write().or_poisoned("expirations")?;
+        // The TTL may have been changed while no lock was held.
+        match expirations.get(key) {
+            Some(deadline) if *deadline <= now => {
+                expirations.remove(key);
+                self.remove_from_stores(&[key])?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    pub fn list_push(&self, key: String, value: String) -> Result<usize, DcsError> {
+        self.evict_if_expired(&key)?;
+        let mut list_store = self.write_typed(KeyType::List, &self.list_store, &key)?;
+        self.log(|r| r.arg("RPUSH").arg(&key).arg(&value))?;
+        let list = list_store.get_or_default(key);
+        list.push(value);
+        let len = list.len();
+        drop(list_store);
+        self.notify_list_push()?;
+        Ok(len)
+    }
+
+    pub fn list_push_multi(&self, key: String, values: Vec<String>) -> Result<usize, DcsError> {
+        self.evict_if_expired(&key)?;
+        let mut list_store = self.write_typed(KeyType::List, &self.list_store, &key)?;
+        if values.is_empty() {
+            return Ok(list_store.get(&key).map_or(0, Vec::len));
+        }
+        self.log(|r| r.arg("RPUSH").arg(&key).args(&values))?;
+        let list = list_store.get_or_default(key);
+        list.extend(values);
+        let len = list.len();
+        drop(list_store);
+        self.notify_list_push()?;
+        Ok(len)
+    }
+
+    pub fn list_pop(&self, key: &str) -> Result<Option<String>, DcsError> {
+        self.evict_if_expired(key)?;
+        let mut list_store = self.write_typed(KeyType::List, &self.list_store, key)?;
+        let popped = match list_store.get_mut(key) {
+            Some(list) if !list.is_empty() => {
+                self.log(|r| r.arg("RPOP").arg(key))?;
+                list.pop()
+            }
+            _ => return Ok(None),
+        };
+        self.remove_if_empty(list_store, key, Vec::is_empty)?;
+        Ok(popped)
+    }
+
+    /// Pops up to `count` elements off the tail of the list under one lock and
+    /// returns them in pop order, last element first.
+    pub fn list_pop_multi(&self, key: &str, count: usize) -> Result<Vec<String>, DcsError> {
+        self.evict_if_expired(key)?;
+        let mut list_store = self.write_typed(KeyType::List, &self.list_store, key)?;
+        match list_store.get_mut(key) {
+            Some(list) if !list.is_empty() && count > 0 => {
+                let count = count.min(list.len());
+                self.log(|r| r.arg("RPOP").arg(key).arg(count))?;
+                let mut popped = list.split_off(list.len() - count);
+                popped.reverse();
+                self.remove_if_empty(list_store, key, Vec::is_empty)?;
+                Ok(popped)
+            }
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    /// Pushes `value` onto the head of the list. This shifts every element, so it
+    /// is O(n) in the list length.
+    pub fn list_push_front(&self, key: String, value: String) -> Result<usize, DcsError> {
+        self.evict_if_expired(&key)?;
+        let mut list_store = self.write_typed(KeyType::List, &self.list_store, &key)?;
+        self.log(|r| r.arg("LPUSH").arg(&key).arg(&value))?;
+        let list = list_store.get_or_default(key);
+        list.insert(0, value);
+        let len = list.len();
+        drop(list_store);
+        self.notify_list_push()?;
+        Ok(len)
+    }
+
+    /// Pushes every value onto the head of the list in order, so the last one ends
+    /// up first, like Redis `LPUSH`. Returns the new length.
+    pub fn list_push_front_multi(
+        &self,
+        key: String,
+        values: Vec<String>,
+    ) -> Result<usize, DcsError> {
+        self.evict_if_expired(&key)?;
+        let mut list_store = self.write_typed(KeyType::List, &self.list_store, &key)?;
+        if values.is_empty() {
+            return Ok(list_store.get(&key).map_or(0, Vec::len));
+        }
+        self.log(|r| r.arg("LPUSH").arg(&key).args(&values))?;
+        let list = list_store.get_or_default(key);
+        list.splice(0..0, values.into_iter().rev());
+        let len = list.len();
+        drop(list_store);
+        self.notify_list_push()?;
+        Ok(len)
+    }
+
+    /// Pops the head of the list. This shifts every element, so it is O(n) in the
+    /// list length.
+    pub fn list_pop_front(&self, key: &str) -> Result<Option<String>, DcsError> {
+        self.evict_if_expired(key)?;
+        let mut list_store = self.write_typed(KeyType::List, &self.list_store, key)?;
+        let popped = match list_store.get_mut(key) {
+            Some(list) if !list.is_empty() => {
+                self.log(|r| r.arg("LPOP").arg(key))?;
+                list.remove(0)
+            }
+            _ => return Ok(None),
+        };
+        self.remove_if_empty(list_store, key, Vec::is_empty)?;
+        Ok(Some(popped))
+    }
+
+    pub fn list_len(&self, key: &str) -> Result<usize, DcsError> {
+        self.counters.read();
+        self.evict_if_expired(key)?;
+        let list_store = self.list_store.read(key)?;
+        Ok(list_store.get(key).map_or(0, |list| list.len()))
+    }
+
+    /// Returns the elements between `start` and `stop` inclusive. Negative indices
+    /// count from the end of the list, and out-of-range bounds are clamped.
+    pub fn list_range(&self, key: &str, start: i64, stop: i64) -> Result<Vec<String>, DcsError> {
+        self.counters.read();
+        self.evict_if_expired(key)?;
+        let list_store = self.list_store.read(key)?;
+        let Some(list) = list_store.get(key) else {
+            return Ok(Vec::new());
+        };
+        match resolve_range(start, stop, list.len()) {
+            Some((start, stop)) => Ok(list[start..=stop].to_vec()),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Returns the element at `index`, counting from the end if negative.
+    pub fn list_index(&self, key: &str, index: i64) -> Result<Option<String>, DcsError> {
+        self.counters.read();
+        self.evict_if_expired(key)?;
+        let list_store = self.list_store.read(key)?;
+        Ok(list_store
+            .get(key)
+            .and_then(|list| resolve_index(index, list.len()).map(|i| list[i].clone())))
+    }
+
+    /// Overwrites the element at `index`, counting from the end if negative. Fails
+    /// with `IndexOutOfRange` if there is no such element.
+    pub fn list_set(&self, key: &str, index: i64, value: String) -> Result<(), DcsError> {
+        self.evict_if_expired(key)?;
+        let mut list_store = self.write_typed(KeyType::List, &self.list_store, key)?;
+        let list = list_store.get_mut(key).ok_or(DcsError::IndexOutOfRange)?;
+        let index = resolve_index(index, list.len()).ok_or(DcsError::IndexOutOfRange)?;
+        self.log(|r| r.arg("LSET").arg(key).arg(index).arg(&value))?;
+        list[index] = value;
+        Ok(())
+    }
+
+    /// Keeps only the elements between `start` and `stop` inclusive, with negative
+    /// indices counting from the end. The key is removed if nothing is left.
+    pub fn list_trim(&self, key: &str, start: i64, stop: i64) -> Result<(), DcsError> {
+        self.evict_if_expired(key)?;
+        let mut list_store = self.write_typed(KeyType::List, &self.list_store, key)?;
+        let Some(list) = list_store.get_mut(key) else {
+            return Ok(());
+        };
+        self.log(|r| r.arg("LTRIM").arg(key).arg(start).arg(stop))?;
+        match resolve_range(start, stop, list.len()) {
+            Some((start, stop)) => {
+                list.truncate(stop + 1);
+                list.drain(..start);
+            }
+            None => {
+                list_store.remove(key);
+                drop(list_store);
+                self.drop_ttl_if_gone(key)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes elements equal to `value` and returns how many were removed. A
+    /// positive `count` removes that many from the head, a negative one from the
+    /// tail, and zero removes every match.
+    pub fn list_remove(&self, key: &str, count: i64, value: &str) -> Result<usize, DcsError> {
+        self.evict_if_expired(key)?;
+        let mut list_store = self.write_typed(KeyType::List, &self.list_store, key)?;
+        let Some(list) = list_store.get_mut(key) else {
+            return Ok(0);
+        };
+        if !list.iter().any(|item| item == value) {
+            return Ok(0);
+        }
+        self.log(|r| r.arg("LREM").arg(key).arg(count).arg(value))?;
+        let limit = match count {
+            0 => usize::MAX,
+            count => usize::try_from(count.unsigned_abs()).unwrap_or(usize::MAX),
+        };
+        let from_tail = count < 0;
+        if from_tail {
+            list.reverse();
+        }
+        let mut removed = 0;
+        list.retain(|item| {
+            if removed < limit && item == value {
+                removed += 1;
+                false
+            } else {
+                true
+            }
+        });
+        if from_tail {
+            list.reverse();
+        }
+        self.remove_if_empty(list_store, key, Vec::is_empty)?;
+        Ok(removed)
+    }
+
+    /// Inserts `value` before or after the first element equal to `pivot` and
+    /// returns the new length. Returns -1 if `pivot` is not found and 0 if the key
+    /// does not exist.
+    pub fn list_insert(
+        &self,
+        key: &str,
+        before: bool,
+        pivot: &str,
+        value: String,
+    ) -> Result<i64, DcsError> {
+        self.evict_if_expired(key)?;
+        let mut list_store = self.write_typed(KeyType::List, &self.list_store, key)?;
+        let Some(list) = list_store.get_mut(key) else {
+            return Ok(0);
+        };
+        let Some(position) = list.iter().position(|item| item == pivot) else {
+            return Ok(-1);
+        };
+        self.log(|r| {
+            r.arg("LINSERT")
+                .arg(key)
+                .arg(if before { "BEFORE" } else { "AFTER" })
+                .arg(pivot)
+                .arg(&value)
+        })?;
+        let index = if before { position } else { position + 1 };
+        list.insert(index, value);
+        let len = list.len() as i64;
+        drop(list_store);
+        self.notify_list_push()?;
+        Ok(len)
+    }
+
+    /// Pops the tail of `src` and pushes it onto the head of `dst` under one lock,
+    /// like Redis `RPOPLPUSH`. With `src == dst` this rotates the list. Returns the
+    /// moved element, or `None` if `src` is empty.
+    pub fn list_move(&self, src: &str, dst: String) -> Result<Option<String>, DcsError> {
+        self.evict_if_expired(src)?;
+        self.evict_if_expired(&dst)?;
+        let mut list_store =
+            self.write_typed_keys(KeyType::List, &self.list_store, [src, dst.as_str()])?;
+        let Some(value) = list_store.get(src).and_then(|list| list.last()).cloned() else {
+            return Ok(None);
+        };
+        self.log(|r| r.arg("RPOPLPUSH").arg(src).arg(&dst))?;
+        if let Some(list) = list_store.get_mut(src) {
+            list.pop();
+        }
+        list_store.get_or_default(dst).insert(0, value.clone());
+        self.remove_if_empty(list_store, src, Vec::is_empty)?;
+        self.notify_list_push()?;
+        Ok(Some(value))
+    }
+
+    /// Pops the tail of the list, waiting up to `timeout` for an element to be
+    /// pushed if it is empty. Returns `None` on timeout. A zero `timeout` waits
+    /// forever, like Redis `BRPOP`.
+    ///
+    /// Waiters share one condition variable, so a push to any list wakes them all
+    /// up and those waiting on other keys go back to sleep.
+    pub fn list_bpop(&self, key: &str, timeout: Duration) -> Result<Option<String>, DcsError> {
+        let deadline = (!timeout.is_zero()).then(|| Instant::now() + timeout);
+        loop {
+            // Read the counter before trying to pop so that a push landing in
+            // between is not missed.
+            let seen = *self.list_pushes.lock().or_poisoned("list_pushes")?;
+            if let Some(value) = self.list_pop(key)? {
+                return Ok(Some(value));
+            }
+            let mut pushes = self.list_pushes.lock().or_poisoned("list_pushes")?;
+            while *pushes == seen {
+                let Some(deadline) = deadline else {
+                    pushes = self.list_pushed.wait(pushes).or_poisoned("list_pushes")?;
+                    continue;
+                };
+                let now = Instant::now();
+                if now >= deadline {
+                    return Ok(None);
+                }
+                pushes = self
+                    .list_pushed
+                    .wait_timeout(pushes, deadline - now)
+                    .or_poisoned("list_pushes")?
+                    .0;
+            }
+        }
+    }
+
+    pub fn hash_set(&self, key: String, field: String, value: String) -> Result<(), DcsError> {
+        self.evict_if_expired(&key)?;
+        let mut hash_store = self.write_typed(KeyType::Hash, &self.hash_store, &key)?;
+        self.log(|r| r.arg("HSET").arg(&key).arg(&field).arg(&value))?;
+        let hash = hash_store.get_or_default(key);
+        hash.insert(field, value);
+        Ok(())
+    }
+
+    pub fn hash_get(&self, key: &str, field: &str) -> Result<Option<String>, DcsError> {
+        self.counters.read();
+        self.evict_if_expired(key)?;
+        let hash_store = self.hash_store.read(key)?;
+        if let Some(hash) = hash_store.get(key) {
+            Ok(hash.get(field).cloned())
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn hash_del(&self, key: String, field: String) -> Result<bool, DcsError> {
+        self.evict_if_expired(&key)?;
+        let mut hash_store = self.write_typed(KeyType::Hash, &self.hash_store, &key)?;
+        match hash_store.get_mut(&key) {
+            Some(hash) if hash.contains_key(&field) => {
+                self.log(|r| r.arg("HDEL").arg(&key).arg(&field))?;
+                hash.remove(&field);
+                self.remove_if_empty(hash_store, &key, HashMap::is_empty)?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Removes every field and returns how many were present.
+    pub fn hash_del_multi(&self, key: String, fields: Vec<String>) -> Result<usize, DcsError> {
+        self.evict_if_expired(&key)?;
+        let mut hash_store = self.write_typed(KeyType::Hash, &self.hash_store, &key)?;
+        match hash_store.get_mut(&key) {
+            Some(hash) => {
+                self.log(|r| r.arg("HDEL").arg(&key).args(&fields))?;
+                let removed = fields
+                    .iter()
+                    .filter(|field| hash.remove(*field).is_some())
+                    .count();
+                self.remove_if_empty(hash_store, &key, HashMap::is_empty)?;
+                Ok(removed)
+            }
+            None => Ok(0),
+        }
+    }
+
+    pub fn hash_get_all(&self, key: &str) -> Result<HashMap<String, String>, DcsError> {
+        self.counters.read();
+        self.evict_if_expired(key)?;
+        let hash_store = self.hash_store.read(key)?;
+        Ok(hash_store.get(key).cloned().unwrap_or_default())
+    }
+
+    pub fn hash_keys(&self, key: &str) -> Result<Vec<String>, DcsError> {
+        self.counters.read();
+        self.evict_if_expired(key)?;
+        let hash_store = self.hash_store.read(key)?;
+        Ok(hash_store
+            .get(key)
+            .map_or_else(Vec::new, |hash| hash.keys().cloned().collect()))
+    }
+
+    pub fn hash_vals(&self, key: &str) -> Result<Vec<String>, DcsError> {
+        self.counters.read();
+        self.evict_if_expired(key)?;
+        let hash_store = self.hash_store.read(key)?;
+        Ok(hash_store
+            .get(key)
+            .map_or_else(Vec::new, |hash| hash.values().cloned().collect()))
+    }
+
+    /// Returns random fields of the hash at `key` without removing them: up to
+    /// `count` distinct ones if `count` is positive, or exactly `-count` that may
+    /// repeat if it is negative. Order is not guaranteed.
+    pub fn hash_rand_field(&self, key: &str, count: i64) -> Result<Vec<String>, DcsError> {
+        self.counters.read();
+        self.evict_if_expired(key)?;
+        let hash_store = self.hash_store.read(key)?;
+        let Some(hash) = hash_store.get(key) else {
+            return Ok(Vec::new());
+        };
+        let mut fields: Vec<&String> = hash.keys().collect();
+        let sample = self.rng.lock().or_poisoned("rng")?.pick(&mut fields, count);
+        Ok(sample.into_iter().cloned().collect())
+    }
+
+    pub fn hash_len(&self, key: &str) -> Result<usize, DcsError> {
+        self.counters.read();
+        self.evict_if_expired(key)?;
+        let hash_store = self.hash_store.read(key)?;
+        Ok(hash_store.get(key).map_or(0, |hash| hash.len()))
+    }
+
+    pub fn hash_exists(&self, key: &str, field: &str) -> Result<bool, DcsError> {
+        self.counters.read();
+        self.evict_if_expired(key)?;
+        let hash_store = self.hash_store.read(key)?;
+        Ok(hash_store
+            .get(key)
+            .is_some_and(|hash| hash.contains_key(field)))
+    }
+
+    /// Adds `delta` to the integer stored in `field`, treating a missing field as 0.
+    pub fn hash_incr_by(&self, key: String, field: String, delta: i64) -> Result<i64, DcsError> {
+        self.evict_if_expired(&key)?;
+        let mut hash_store = self.write_typed(KeyType::Hash, &self.hash_store, &key)?;
+        let current = match hash_store.get(&key).and_then(|hash| hash.get(&field)) {
+            Some(value) => value
+                .parse::<i64>()
+                .map_err(|_| DcsError::NotAnInteger { key: key.clone() })?,
+            None => 0,
+        };
+        let new_value = current.checked_add(delta).ok_or(DcsError::Overflow)?;
+        self.log(|r| r.arg("HINCRBY").arg(&key).arg(&field).arg(delta))?;
+        hash_store
+            .get_or_default(key)
+            .insert(field, new_value.to_string());
+        Ok(new_value)
+    }
+
+    pub fn hash_mset(&self, key: String, pairs: Vec<(String, String)>) -> Result<(), DcsError> {
+        self.hash_set_multi(key, pairs).map(drop)
+    }
+
+    /// Sets every field, like `hash_mset`, and returns how many were new. `pairs`
+    /// can be any collection of field and value pairs, such as a `HashMap`.
+    pub fn hash_set_multi(
+        &self,
+        key: String,
+        pairs: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<usize, DcsError> {
+        let pairs: Vec<(String, String)> = pairs.into_iter().collect();
+        self.evict_if_expired(&key)?;
+        let mut hash_store = self.write_typed(KeyType::Hash, &self.hash_store, &key)?;
+        if pairs.is_empty() {
+            return Ok(0);
+        }
+        self.log(|r| {
+            r.arg("HMSET").arg(&key);
+            for (field, value) in &pairs {
+                r.arg(field).arg(value);
+            }
+            r
+        })?;
+        let hash = hash_store.get_or_default(key);
+        Ok(pairs
+            .into_iter()
+            .filter(|(field, value)| hash.insert(field.clone(), value.clone()).is_none())
+            .count())
+    }
+
+    pub fn hash_mget(&self, key: &str, fields: &[String]) -> Result<Vec<Option<String>>, DcsError> {
+        self.counters.read();
+        self.evict_if_expired(key)?;
+        let hash_store = self.hash_store.read(key)?;
+        let hash = hash_store.get(key);
+        Ok(fields
+            .iter()
+            .map(|field| hash.and_then(|hash| hash.get(field).cloned()))
+            .collect())
+    }
+
+    /// Sets `field` only if it does not exist yet. Returns whether the write happened.
+    pub fn hash_set_nx(&self, key: String, field: String, value: String) -> Result<bool, DcsError> {
+        self.evict_if_expired(&key)?;
+        let mut hash_store = self.write_typed(KeyType::Hash, &self.hash_store, &key)?;
+        if hash_store
+            .get(&key)
+            .is_some_and(|hash| hash.contains_key(&field))
+        {
+            return Ok(false);
+        }
+        self.log(|r| r.arg("HSET").arg(&key).arg(&field).arg(&value))?;
+        hash_store.get_or_default(key).insert(field, value);
+        Ok(true)
+    }
+
+    pub fn set_add(&self, key: String, value: String) -> Result<(), DcsError> {
+        self.evict_if_expired(&key)?;
+        let mut set_store = self.write_typed(KeyType::Set, &self.set_store, &key)?;
+        self.log(|r| r.arg("SADD").arg(&key).arg(&value))?;
+        let set = set_store.get_or_default(key);
+        set.insert(value);
+        Ok(())
+    }
+
+    pub fn set_is_member(&self, key: &str, value: &str) -> Result<bool, DcsError> {
+        self.counters.read();
+        self.evict_if_expired(key)?;
+        let set_store = self.set_store.read(key)?;
+        if let Some(set) = set_store.get(key) {
+            Ok(set.contains(value))
+        } else {
+            Ok(false)
+        }
+    }
+
+    pub fn set_is_member_multi(&self, key: &str, values: &[String]) -> Result<Vec<bool>, DcsError> {
+        self.counters.read();
+        self.evict_if_expired(key)?;
+        let set_store = self.set_store.read(key)?;
+        let set = set_store.get(key);
+        Ok(values
+            .iter()
+            .map(|value| set.is_some_and(|set| set.contains(value)))
+            .collect())
+    }
+
+    pub fn set_remove(&self, key: String, value: String) -> Result<bool, DcsError> {
+        self.evict_if_expired(&key)?;
+        let mut set_store = self.write_typed(KeyType::Set, &self.set_store, &key)?;
+        match set_store.get_mut(&key) {
+            Some(set) if set.contains(&value) => {
+                self.log(|r| r.arg("SREM").arg(&key).arg(&value))?;
+                set.remove(&value);
+                self.remove_if_empty(set_store, &key, HashSet::is_empty)?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    pub fn set_card(&self, key: &str) -> Result<usize, DcsError> {
+        self.counters.read();
+        self.evict_if_expired(key)?;
+        let set_store = self.set_store.read(key)?;
+        Ok(set_store.get(key).map_or(0, |set| set.len()))
+    }
+
+    /// Returns every member of the set, in no particular order.
+    pub fn set_members(&self, key: &str) -> Result<Vec<String>, DcsError> {
+        self.counters.read();
+        self.evict_if_expired(key)?;
+        let set_store = self.set_store.read(key)?;
+        Ok(set_store
+            .get(key)
+            .map_or_else(Vec::new, |set| set.iter().cloned().collect()))
+    }
+
+    /// Adds every value and returns how many were not already members.
+    pub fn set_add_multi(&self, key: String, values: Vec<String>) -> Result<usize, DcsError> {
+        self.evict_if_expired(&key)?;
+        let mut set_store = self.write_typed(KeyType::Set, &self.set_store, &key)?;
+        if values.is_empty() {
+            return Ok(0);
+        }
+        self.log(|r| r.arg("SADD").arg(&key).args(&values))?;
+        let set = set_store.get_or_default(key);
+        Ok(values
+            .into_iter()
+            .filter(|value| set.insert(value.clone()))
+            .count())
+    }
+
+    /// Removes every value and returns how many were members.
+    pub fn set_remove_multi(&self, key: String, values: Vec<String>) -> Result<usize, DcsError> {
+        self.evict_if_expired(&key)?;
+        let mut set_store = self.write_typed(KeyType::Set, &self.set_store, &key)?;
+        match set_store.get_mut(&key) {
+            Some(set) => {
+                self.log(|r| r.arg("SREM").arg(&key).args(&values))?;
+                let removed = values.iter().filter(|value| set.remove(*value)).count();
+                self.remove_if_empty(set_store, &key, HashSet::is_empty)?;
+                Ok(removed)
+            }
+            None => Ok(0),
+        }
+    }
+
+    /// Members present in every one of `keys`. A missing key counts as an empty set.
+    pub fn set_intersect<K: AsRef<str>>(&self, keys: &[K]) -> Result<HashSet<String>, DcsError> {
+        self.counters.read();
+        self.combine_sets(keys, SetOp::Inter)
+    }
+
+    /// Members present in any of `keys`.
+    pub fn set_union<K: AsRef<str>>(&self, keys: &[K]) -> Result<HashSet<String>, DcsError> {
+        self.counters.read();
+        self.combine_sets(keys, SetOp::Union)
+    }
+
+    /// Members of the first key that are in none of the others.
+    pub fn set_diff<K: AsRef<str>>(&self, keys: &[K]) -> Result<HashSet<String>, DcsError> {
+        self.counters.read();
+        self.combine_sets(keys, SetOp::Diff)
+    }
+
+    fn combine_sets<K: AsRef<str>>(
+        &self,
+        keys: &[K],
+        op: SetOp,
+    ) -> Result<HashSet<String>, DcsError> {
+        for key in keys {
+            self.evict_if_expired(key.as_ref())?;
+        }
+        let set_store = self.set_store.read_keys(keys)?;
+        Ok(op.apply(&set_store, keys))
+    }
+
+    /// Stores the intersection of `keys` at `dest` and returns its cardinality.
+    pub fn set_intersect_store<K: AsRef<str>>(
+        &self,
+        dest: String,
+        keys: &[K],
+    ) -> Result<usize, DcsError> {
+        self.store_combined_sets(dest, keys, SetOp::Inter)
+    }
+
+    /// Stores the union of `keys` at `dest` and returns its cardinality.
+    pub fn set_union_store<K: AsRef<str>>(
+        &self,
+        dest: String,
+        keys: &[K],
+    ) -> Result<usize, DcsError> {
+        self.store_combined_sets(dest, keys, SetOp::Union)
+    }
+
+    /// Stores the difference of `keys` at `dest` and returns its cardinality.
+    pub fn set_diff_store<K: AsRef<str>>(
+        &self,
+        dest: String,
+        keys: &[K],
+    ) -> Result<usize, DcsError> {
+        self.store_combined_sets(dest, keys, SetOp::Diff)
+    }
+
+    /// Overwrites `dest`, dropping its TTL. An empty result removes `dest`.
+    fn store_combined_sets<K: AsRef<str>>(
+        &self,
+        dest: String,
+        keys: &[K],
+        op: SetOp,
+    ) -> Result<usize, DcsError> {
+        let keys: Vec<&str> = keys.iter().map(AsRef::as_ref).collect();
+        for key in &keys {
+            self.evict_if_expired(key)?;
+        }
+        self.evict_if_expired(&dest)?;
+        let mut expirations = self.expirations.write().or_poisoned("expirations")?;
+        let mut set_store = self.write_typed_keys(
+            KeyType::Set,
+            &self.set_store,
+            keys.iter().copied().chain([dest.as_str()]),
+        )?;
+        let command = match op {
+            SetOp::Inter => "SINTERSTORE",
+            SetOp::Union => "SUNIONSTORE",
+            SetOp::Diff => "SDIFFSTORE",
+        };
+        self.log(|r| r.arg(command).arg(&dest).args(&keys))?;
+        let result = op.apply(&set_store, &keys);
+        let len = result.len();
+        expirations.remove(&dest);
+        if result.is_empty() {
+            set_store.remove(&dest);
+        } else {
+            set_store.insert(dest, result);
+        }
+        Ok(len)
+    }
+
+    /// Moves `value` from `src` to `dst` under one lock. Returns `false`, changing
+    /// nothing, if `value` is not a member of `src`.
+    pub fn set_move(&self, src: &str, dst: String, value: &str) -> Result<bool, DcsError> {
+        self.evict_if_expired(src)?;
+        self.evict_if_expired(&dst)?;
+        let mut set_store =
+            self.write_typed_keys(KeyType::Set, &self.set_store, [src, dst.as_str()])?;
+        if !set_store.get(src).is_some_and(|set| set.contains(value)) {
+            return Ok(false);
+        }
+        self.log(|r| r.arg("SMOVE").arg(src).arg(&dst).arg(value))?;
+        if let Some(set) = set_store.get_mut(src) {
+            set.remove(value);
+        }
+        set_store.get_or_default(dst).insert(value.to_string());
+        self.remove_if_empty(set_store, src, HashSet::is_empty)?;
+        Ok(true)
+    }
+
+    /// Removes and returns up to `count` random members. Order is not guaranteed.
+    pub fn set_pop(&self, key: &str, count: usize) -> Result<Vec<String>, DcsError> {
+        self.evict_if_expired(key)?;
+        let mut set_store = self.write_typed(KeyType::Set, &self.set_store, key)?;
+        let Some(set) = set_store.get_mut(key) else {
+            return Ok(Vec::new());
+        };
+        let mut members: Vec<String> = set.iter().cloned().collect();
+        let popped = self
+            .rng
+            .lock()
+            .or_poisoned("rng")?
+            .sample(&mut members, count)
+            .to_vec();
+        if !popped.is_empty() {
+            self.log(|r| r.arg("SREM").arg(key).args(&popped))?;
+        }
+        for member in &popped {
+            set.remove(member);
+        }
+        self.remove_if_empty(set_store, key, HashSet::is_empty)?;
+        Ok(popped)
+    }
+
+    /// Returns up to `count` distinct random members without removing them. Order
+    /// is not guaranteed.
+    pub fn set_rand_member(&self, key: &str, count: usize) -> Result<Vec<String>, DcsError> {
+        self.counters.read();
+        self.evict_if_expired(key)?;
+        let set_store = self.set_store.read(key)?;
+        let Some(set) = set_store.get(key) else {
+            return Ok(Vec::new());
+        };
+        let mut members: Vec<&String> = set.iter().collect();
+        let sample = self
+            .rng
+            .lock()
+            .or_poisoned("rng")?
+            .sample(&mut members, count)
+            .to_vec();
+        Ok(sample.into_iter().cloned().collect())
+    }
+
+    /// Sets the score of `value`, adding it if needed. Fails with `InvalidScore`
+    /// if `score` is NaN.
+    pub fn zset_add(&self, key: String, score: f64, value: String) -> Result<(), DcsError> {
+        if score.is_nan() {
+            return Err(DcsError::InvalidScore);
+        }
+        self.evict_if_expired(&key)?;
+        let mut zset_store = self.write_typed(KeyType::ZSet, &self.zset_store, &key)?;
+        self.log(|r| r.arg("ZADD").arg(&key).arg(score).arg(&value))?;
+        let zset = zset_store.get_or_default(key);
+        zset.insert(value, score);
+        Ok(())
+    }
+
+    /// Sets the score of every member, adding them if needed, and returns how
+    /// many were new. Fails with `InvalidScore`, writing nothing, if a score is
+    /// NaN.
+    pub fn zset_add_multi(
+        &self,
+        key: String,
+        pairs: Vec<(f64, String)>,
+    ) -> Result<usize, DcsError> {
+        if pairs.iter().any(|(score, _)| score.is_nan()) {
+            return Err(DcsError::InvalidScore);
+        }
+        self.evict_if_expired(&key)?;
+        let mut zset_store = self.write_typed(KeyType::ZSet, &self.zset_store, &key)?;
+        if pairs.is_empty() {
+            return Ok(0);
+        }
+        self.log(|r| {
+            r.arg("ZADD").arg(&key);
+            for (score, member) in &pairs {
+                r.arg(score).arg(member);
+            }
+            r
+        })?;
+        let zset = zset_store.get_or_default(key);
+        Ok(pairs
+            .into_iter()
+            .filter(|(score, member)| zset.insert(member.clone(), *score).is_none())
+            .count())
+    }
+
+    /// Like `zset_add`, but only writes if the conditions in `opts` hold. Returns
+    /// whether the member was added or its score changed.
+    pub fn zset_add_opts(
+        &self,
+        key: String,
+        score: f64,
+        member: String,
+        opts: ZAddOpts,
+    ) -> Result<bool, DcsError> {
+        if score.is_nan() {
+            return Err(DcsError::InvalidScore);
+        }
+        self.evict_if_expired(&key)?;
+        let mut zset_store = self.write_typed(KeyType::ZSet, &self.zset_store, &key)?;
+        let current = zset_store.get(&key).and_then(|zset| zset.score(&member));
+        let write = match current {
+            None => !opts.xx,
+            Some(old) => {
+                !opts.nx && old != score && (!opts.gt || score > old) && (!opts.lt || score < old)
+            }
+        };
+        if write {
+            self.log(|r| r.arg("ZADD").arg(&key).arg(score).arg(&member))?;
+            zset_store.get_or_default(key).insert(member, score);
+        }
+        Ok(write)
+    }
+
+    pub fn zset_score(&self, key: &str, value: &str) -> Result<Option<f64>, DcsError> {
+        self.counters.read();
+        self.evict_if_expired(key)?;
+        let zset_store = self.zset_store.read(key)?;
+        if let Some(zset) = zset_store.get(key) {
+            Ok(zset.score(value))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Returns random members of the sorted set at `key`, like `hash_rand_field`,
+    /// along with their scores if `with_scores` is set.
+    pub fn zset_rand_member(
+        &self,
+        key: &str,
+        count: i64,
+        with_scores: bool,
+    ) -> Result<Vec<(String, Option<f64>)>, DcsError> {
+        self.counters.read();
+        self.evict_if_expired(key)?;
+        let zset_store = self.zset_store.read(key)?;
+        let Some(zset) = zset_store.get(key) else {
+            return Ok(Vec::new());
+        };
+        let mut members: Vec<(&String, f64)> = zset.iter().collect();
+        let sample = self
+            .rng
+            .lock()
+            .or_poisoned("rng")?
+            .pick(&mut members, count);
+        Ok(sample
+            .into_iter()
+            .map(|(member, score)| (member.clone(), with_scores.then_some(score)))
+            .collect())
+    }
+
+    pub fn zset_score_multi(
+        &self,
+        key: &str,
+        members: &[String],
+    ) -> Result<Vec<Option<f64>>, DcsError> {
+        self.counters.read();
+        self.evict_if_expired(key)?;
+        let zset_store = self.zset_store.read(key)?;
+        let zset = zset_store.get(key);
+        Ok(members
+            .iter()
+            .map(|member| zset.and_then(|zset| zset.score(member)))
+            .collect())
+    }
+
+    pub fn zset_remove(&self, key: String, value: String) -> Result<bool, DcsError> {
+        self.evict_if_expired(&key)?;
+        let mut zset_store = self.write_typed(KeyType::ZSet, &self.zset_store, &key)?;
+        match zset_store.get_mut(&key) {
+            Some(zset) if zset.score(&value).is_some() => {
+                self.log(|r| r.arg("ZREM").arg(&key).arg(&value))?;
+                zset.remove(&value);
+                self.remove_if_empty(zset_store, &key, ZSet::is_empty)?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Removes every member and returns how many were present.
+    pub fn zset_remove_multi(&self, key: String, members: Vec<String>) -> Result<usize, DcsError> {
+        self.evict_if_expired(&key)?;
+        let mut zset_store = self.write_typed(KeyType::ZSet, &self.zset_store, &key)?;
+        match zset_store.get_mut(&key) {
+            Some(zset) => {
+                self.log(|r| r.arg("ZREM").arg(&key).args(&members))?;
+                let removed = members
+                    .iter()
+                    .filter(|member| zset.remove(member).is_some())
+                    .count();
+                self.remove_if_empty(zset_store, &key, ZSet::is_empty)?;
+                Ok(removed)
+            }
+            None => Ok(0),
+        }
+    }
+
+    /// Returns members ranked `start..=stop` by ascending score, ties broken by
+    /// member. Negative ranks count from the end. Scores are included if
+    /// `with_scores` is set.
+    pub fn zset_range(
+        &self,
+        key: &str,
+        start: i64,
+        stop: i64,
+        with_scores: bool,
+    ) -> Result<Vec<(String, Option<f64>)>, DcsError> {
+        self.counters.read();
+        self.evict_if_expired(key)?;
+        let zset_store = self.zset_store.read(key)?;
+        let Some(zset) = zset_store.get(key) else {
+            return Ok(Vec::new());
+        };
+        let Some((start, stop)) = resolve_range(start, stop, zset.len()) else {
+            return Ok(Vec::new());
+        };
+        Ok(zset
+            .range_by_rank(start, stop)
+            .into_iter()
+            .map(|(member, score)| (member.clone(), with_scores.then_some(score)))
+            .collect())
+    }
+
+    /// Returns the 0-based position of `member` by ascending score, ties broken by member.
+    pub fn zset_rank(&self, key: &str, member: &str) -> Result<Option<usize>, DcsError> {
+        self.counters.read();
+        self.evict_if_expired(key)?;
+        let zset_store = self.zset_store.read(key)?;
+        Ok(zset_store.get(key).and_then(|zset| zset.rank(member)))
+    }
+
+    /// Returns the 0-based position of `member` by descending score.
+    pub fn zset_rev_rank(&self, key: &str, member: &str) -> Result<Option<usize>, DcsError> {
+        self.counters.read();
+        self.evict_if_expired(key)?;
+        let zset_store = self.zset_store.read(key)?;
+        Ok(zset_store
+            .get(key)
+            .and_then(|zset| zset.rank(member).map(|rank| zset.len() - 1 - rank)))
+    }
+
+    /// Adds `delta` to the score of `member`, treating a missing member as 0, and
+    /// returns the new score. Fails with `InvalidScore` if the result is NaN.
+    pub fn zset_incr_by(&self, key: String, delta: f64, member: String) -> Result<f64, DcsError> {
+        self.evict_if_expired(&key)?;
+        let mut zset_store = self.write_typed(KeyType::ZSet, &self.zset_store, &key)?;
+        let current = zset_store
+            .get(&key)
+            .and_then(|zset| zset.score(&member))
+            .unwrap_or(0.0);
+        let score = current + delta;
+        if score.is_nan() {
+            return Err(DcsError::InvalidScore);
+        }
+        self.log(|r| r.arg("ZADD").arg(&key).arg(score).arg(&member))?;
+        zset_store.get_or_default(key).insert(member, score);
+        Ok(score)
+    }
+
+    pub fn zset_card(&self, key: &str) -> Result<usize, DcsError> {
+        self.counters.read();
+        self.evict_if_expired(key)?;
+        let zset_store = self.zset_store.read(key)?;
+        Ok(zset_store.get(key).map_or(0, |zset| zset.len()))
+    }
+
+    /// Counts members with a score in `min..=max`. Infinite bounds are allowed.
+    pub fn zset_count(&self, key: &str, min: f64, max: f64) -> Result<usize, DcsError> {
+        self.counters.read();
+        self.evict_if_expired(key)?;
+        let zset_store = self.zset_store.read(key)?;
+        Ok(zset_store
+            .get(key)
+            .map_or(0, |zset| zset.range_by_score(min, max).count()))
+    }
+
+    /// Returns members with a score in `min..=max` by ascending score, ties broken
+    /// by member. `limit` takes an `(offset, count)` slice of the matches.
+    pub fn zset_range_by_score(
+        &self,
+        key: &str,
+        min: f64,
+        max: f64,
+        with_scores: bool,
+        limit: Option<(usize, usize)>,
+    ) -> Result<Vec<(String, Option<f64>)>, DcsError> {
+        self.counters.read();
+        self.evict_if_expired(key)?;
+        let zset_store = self.zset_store.read(key)?;
+        let Some(zset) = zset_store.get(key) else {
+            return Ok(Vec::new());
+        };
+        let (offset, count) = limit.unwrap_or((0, usize::MAX));
+        Ok(zset
+            .range_by_score(min, max)
+            .skip(offset)
+            .take(count)
+            .map(|(member, score)| (member.clone(), with_scores.then_some(score)))
+            .collect())
+    }
+
+    /// Removes and returns up to `count` members with the lowest scores, lowest first.
+    pub fn zset_pop_min(&self, key: &str, count: usize) -> Result<Vec<(String, f64)>, DcsError> {
+        self.zset_pop(key, count, ZSet::pop_first)
+    }
+
+    /// Removes and returns up to `count` members with the highest scores, highest first.
+    pub fn zset_pop_max(&self, key: &str, count: usize) -> Result<Vec<(String, f64)>, DcsError> {
+        self.zset_pop(key, count, ZSet::pop_last)
+    }
+
+    fn zset_pop(
+        &self,
+        key: &str,
+        count: usize,
+        pop: fn(&mut ZSet) -> Option<(String, f64)>,
+    ) -> Result<Vec<(String, f64)>, DcsError> {
+        self.evict_if_expired(key)?;
+        let mut zset_store = self.write_typed(KeyType::ZSet, &self.zset_store, key)?;
+        let Some(zset) = zset_store.get_mut(key) else {
+            return Ok(Vec::new());
+        };
+        let popped: Vec<(String, f64)> = (0..count).map_while(|_| pop(zset)).collect();
+        if !popped.is_empty() {
+            self.log(|r| {
+                r.arg("ZREM")
+                    .arg(key)
+                    .args(popped.iter().map(|(member, _)| member))
+            })?;
+        }
+        self.remove_if_empty(zset_store, key, ZSet::is_empty)?;
+        Ok(popped)
+    }
+
+    /// Removes members with a score in `min..=max` and returns how many were removed.
+    pub fn zset_remove_range_by_score(
+        &self,
+        key: &str,
+        min: f64,
+        max: f64,
+    ) -> Result<usize, DcsError> {
+        self.evict_if_expired(key)?;
+        let mut zset_store = self.write_typed(KeyType::ZSet, &self.zset_store, key)?;
+        let Some(zset) = zset_store.get_mut(key) else {
+            return Ok(0);
+        };
+        let doomed: Vec<String> = zset
+            .range_by_score(min, max)
+            .map(|(member, _)| member.clone())
+            .collect();
+        if !doomed.is_empty() {
+            self.log(|r| r.arg("ZREM").arg(key).args(&doomed))?;
+        }
+        for member in &doomed {
+            zset.remove(member);
+        }
+        self.remove_if_empty(zset_store, key, ZSet::is_empty)?;
+        Ok(doomed.len())
+    }
+
+    /// Removes members ranked `start..=stop` by ascending score and returns how many
+    /// were removed. Negative ranks count from the end.
+    pub fn zset_remove_range_by_rank(
+        &self,
+        key: &str,
+        start: i64,
+        stop: i64,
+    ) -> Result<usize, DcsError> {
+        self.evict_if_expired(key)?;
+        let mut zset_store = self.write_typed(KeyType::ZSet, &self.zset_store, key)?;
+        let Some(zset) = zset_store.get_mut(key) else {
+            return Ok(0);
+        };
+        let Some((start, stop)) = resolve_range(start, stop, zset.len()) else {
+            return Ok(0);
+        };
+        let doomed: Vec<String> = zset
+            .range_by_rank(start, stop)
+            .into_iter()
+            .map(|(member, _)| member.clone())
+            .collect();
+        if !doomed.is_empty() {
+            self.log(|r| r.arg("ZREM").arg(key).args(&doomed))?;
+        }
+        for member in &doomed {
+            zset.remove(member);
+        }
+        self.remove_if_empty(zset_store, key, ZSet::is_empty)?;
+        Ok(doomed.len())
+    }
+
+    /// Stores the union of the zsets at `keys` at `dest` and returns its cardinality.
+    /// Each source's scores are multiplied by its entry in `weights` (1.0 by
+    /// default) and summed for members found in several sources.
+    pub fn zset_union_store(
+        &self,
+        dest: String,
+        keys: &[String],
+        weights: Option<&[f64]>,
+    ) -> Result<usize, DcsError> {
+        self.store_combined_zsets(dest, keys, weights, SetOp::Union)
+    }
+
+    /// Like `zset_union_store`, but keeps only members present in every source.
+    pub fn zset_inter_store(
+        &self,
+        dest: String,
+        keys: &[String],
+        weights: Option<&[f64]>,
+    ) -> Result<usize, DcsError> {
+        self.store_combined_zsets(dest, keys, weights, SetOp::Inter)
+    }
+
+    /// Overwrites `dest`, dropping its TTL. An empty result removes `dest`.
+    fn store_combined_zsets(
+        &self,
+        dest: String,
+        keys: &[String],
+        weights: Option<&[f64]>,
+        op: SetOp,
+    ) -> Result<usize, DcsError> {
+        let default_weights = vec![1.0; keys.len()];
+        let weights = weights.unwrap_or(&default_weights);
+        if weights.len() != keys.len() {
+            return Err(DcsError::WeightsMismatch);
+        }
+        for key in keys {
+            self.evict_if_expired(key)?;
+        }
+        self.evict_if_expired(&dest)?;
+        let mut expirations = self.expirations.write().or_poisoned("expirations")?;
+        let mut zset_store =
+            self.write_typed_keys(KeyType::ZSet, &self.zset_store, keys.iter().chain([&dest]))?;
+        let command = match op {
+            SetOp::Union => "ZUNIONSTORE",
+            _ => "ZINTERSTORE",
+        };
+        self.log(|r| {
+            r.arg(command)
+                .arg(&dest)
+                .arg(keys.len())
+                .args(keys)
+                .args(weights)
+        })?;
+        let result = op.apply_zsets(&zset_store, keys, weights);
+        let len = result.len();
+        expirations.remove(&dest);
+        if result.is_empty() {
+            zset_store.remove(&dest);
+        } else {
+            zset_store.insert(dest, result);
+        }
+        Ok(len)
+    }
+}
+
+/// The write lock on a store taken by `DCS::write_typed`, usable like the
+/// store itself, along with the read locks keeping the other stores from taking
+/// its keys meanwhile.
+struct Typed<'a, V> {
+    shards: WriteShards<'a, V>,
+    _claim: Claim<'a>,
+}
+
+impl<'a, V> Deref for Typed<'a, V> {
+    type Target = WriteShards<'a, V>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.shards
+    }
+}
+
+impl<V> DerefMut for Typed<'_, V> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.shards
+    }
+}
+
+/// Read locks on some shards of every store but the one a write goes through.
+struct Claim<'a> {
+    strings: Option<ReadShards<'a, StringValue>>,
+    lists: Option<ReadShards<'a, Vec<String>>>,
+    hashes: Option<ReadShards<'a, HashMap<String, String>>>,
+    sets: Option<ReadShards<'a, HashSet<String>>>,
+    zsets: Option<ReadShards<'a, ZSet>>,
+}
+
+impl Claim<'_> {
+    /// Returns the first of the locked stores holding `key`.
+    fn found(&self, key: &str) -> Option<KeyType> {
+        [
+            (KeyType::String, holds(&self.strings, key)),
+            (KeyType::List, holds(&self.lists, key)),
+            (KeyType::Hash, holds(&self.hashes, key)),
+            (KeyType::Set, holds(&self.sets, key)),
+            (KeyType::ZSet, holds(&self.zsets, key)),
+        ]
+        .into_iter()
+        .find_map(|(ty, held)| held.then_some(ty))
+    }
+}
+
+fn holds<V>(shards: &Option<ReadShards<'_, V>>, key: &str) -> bool {
+    shards
+        .as_ref()
+        .is_some_and(|shards| shards.contains_key(key))
+}
+
+/// Read-locks the shards of `keys` in `store`, unless it is `skip`ped because
+/// it's the store being written.
+fn read_other<V, K: AsRef<str>>(
+    store: &Sharded<V>,
+    skip: bool,
+    keys: impl IntoIterator<Item = K>,
+) -> Result<Option<ReadShards<'_, V>>, DcsError> {
+    if skip {
+        return Ok(None);
+    }
+    store.read_keys(keys).map(Some)
+}
+
+#[derive(Clone, Copy)]
+enum SetOp {
+    Inter,
+    Union,
+    Diff,
+}
+
+impl SetOp {
+    fn apply<K: AsRef<str>, G: Deref<Target = Map<HashSet<String>>>>(
+        self,
+        set_store: &Shards<'_, HashSet<String>, G>,
+        keys: &[K],
+    ) -> HashSet<String> {
+        let empty = HashSet::new();
+        let mut sets = keys
+            .iter()
+            .map(|key| set_store.get(key.as_ref()).unwrap_or(&empty));
+        let Some(first) = sets.next() else {
+            return HashSet::new();
+        };
+        let mut result = first.clone();
+        for set in sets {
+            match self {
+                SetOp::Inter => result.retain(|member| set.contains(member)),
+                SetOp::Union => result.extend(set.iter().cloned()),
+                SetOp::Diff => result.retain(|member| !set.contains(member)),
+            }
+        }
+        result
+    }
+
+    /// Combines sorted sets, scaling each source's scores by its weight and summing
+    /// the scores of members found in more than one source.
+    fn apply_zsets<G: Deref<Target = Map<ZSet>>>(
+        self,
+        zset_store: &Shards<'_, ZSet, G>,
+        keys: &[String],
+        weights: &[f64],
+    ) -> ZSet {
+        let empty = ZSet::new();
+        let mut zsets = keys
+            .iter()
+            .zip(weights)
+            .map(|(key, weight)| (zset_store.get(key).unwrap_or(&empty), *weight));
+        let Some((first, weight)) = zsets.next() else {
+            return ZSet::new();
+        };
+        let mut scores: HashMap<String, f64> = first
+            .iter()
+            .map(|(member, score)| (member.clone(), weighted(score, weight)))
+            .collect();
+        for (zset, weight) in zsets {
+            match self {
+                SetOp::Inter => scores.retain(|member, score| match zset.score(member) {
+                    Some(other) => {
+                        *score = weighted_sum(*score, weighted(other, weight));
+                        true
+                    }
+                    None => false,
+                }),
+                SetOp::Union => {
+                    for (member, other) in zset.iter() {
+                        let other = weighted(other, weight);
+                        scores
+                            .entry(member.clone())
+                            .and_modify(|score| *score = weighted_sum(*score, other))
+                            .or_insert(other);
+                    }
+                }
+                SetOp::Diff => scores.retain(|member, _| zset.score(member).is_none()),
+            }
+        }
+        let mut result = ZSet::new();
+        for (member, score) in scores {
+            result.insert(member, score);
+        }
+        result
+    }
+}
+
+// Like Redis, an undefined product or sum such as `0 * inf` or `inf + -inf`
+// counts as 0 rather than storing a NaN score.
+fn weighted(score: f64, weight: f64) -> f64 {
+    let product = score * weight;
+    if product.is_nan() {
+        0.0
+    } else {
+        product
+    }
+}
+
+fn weighted_sum(a: f64, b: f64) -> f64 {
+    let sum = a + b;
+    if sum.is_nan() {
+        0.0
+    } else {
+        sum
+    }
+}
+
+/// Resolves Redis-style inclusive indices, where negative values count from the
+/// end, against a collection of `len` elements. Returns `None` for an empty range.
+fn resolve_range(start: i64, stop: i64, len: usize) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 {
+        (len + start).max(0)
+    } else {
+        start
+    };
+    let stop = if stop < 0 {
+        len + stop
+    } else {
+        stop.min(len - 1)
+    };
+    if start > stop || start >= len {
+        None
+    } else {
+        Some((start as usize, stop as usize))
+    }
+}
+
+/// Resolves a single index, negative counting from the end, against `len` elements.
+fn resolve_index(index: i64, len: usize) -> Option<usize> {
+    let index = if index < 0 { len as i64 + index } else { index };
+    if index < 0 || index >= len as i64 {
+        None
+    } else {
+        Some(index as usize)
+    }
+}
+
+fn cloned<'a, V: Clone + 'a, G: Deref<Target = Map<V>>>(
+    shards: &'a Shards<'_, V, G>,
+) -> impl Iterator<Item = (String, V)> + 'a {
+    shards
+        .iter()
+        .map(|(key, value)| (key.clone(), value.clone()))
+}
+
+fn rename_entry<V>(map: &mut WriteShards<'_, V>, from: &str, to: &str) {
+    if let Some(value) = map.remove(from) {
+        map.insert(to.to_string(), value);
+    }
+}
+
+/// Removes `key` from `map` if it holds an empty collection. Returns whether it did.
+pub(crate) fn remove_if_empty<V>(
+    map: &mut WriteShards<'_, V>,
+    key: &str,
+    is_empty: fn(&V) -> bool,
+) -> bool {
+    let empty = map.get(key).is_some_and(is_empty);
+    if empty {
+        map.remove(key);
+    }
+    empty
+}
+
+pub(crate) fn wrong_type(key: &str, expected: KeyType, found: KeyType) -> DcsError {
+    DcsError::WrongType {
+        key: key.to_string(),
+        expected,
+        found,
+    }
+}
+
+fn copy_entry<V: Clone>(map: &mut WriteShards<'_, V>, from: &str, to: &str) {
+    if let Some(value) = map.get(from).cloned() {
+        map.insert(to.to_string(), value);
+    }
+}
+
+/// Hash used to order keys for `scan`. `DefaultHasher::new` is unkeyed, so it is
+/// stable for the lifetime of the process.
+fn scan_hash(key: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
+mod tests {
+    use super::{DcsError, KeyType, ZAddOpts, DCS};
+    use std::collections::{HashMap, HashSet};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    /// Runs `f` without the type check, like replaying a file written when a key
+    /// could be held by several stores.
+    fn legacy(dcs: &mut DCS, f: impl FnOnce(&DCS)) {
+        dcs.replaying = true;
+        f(dcs);
+        dcs.replaying = false;
+    }
+
+    #[test]
+    fn test_set_get() {
+        let dcs = DCS::new();
+        dcs.set("key1".to_string(), "value1".to_string()).unwrap();
+        assert_eq!(dcs.get("key1").unwrap(), Some("value1".to_string()));
+    }
+
+    #[test]
+    fn test_get_shared_returns_same_allocation() {
+        let dcs = DCS::new();
+        dcs.set("key".to_string(), "x".repeat(1024)).unwrap();
+        let first = dcs.get_shared("key").unwrap().unwrap();
+        let second = dcs.get_shared("key").unwrap().unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(&*first, "x".repeat(1024));
+        assert_eq!(dcs.get_shared("missing").unwrap(), None);
+    }
+
+    #[test]
+    fn test_set_get_bytes() {
+        let dcs = DCS::new();
+        let bytes = vec![0x00, 0xff, b'a', 0x00, 0xc3, 0x28];
+        dcs.set_bytes("blob".to_string(), bytes.clone()).unwrap();
+        assert_eq!(dcs.get_bytes("blob").unwrap(), Some(bytes.clone()));
+        assert!(matches!(dcs.get("blob"), Err(DcsError::InvalidUtf8)));
+        assert_eq!(dcs.append("blob".to_string(), "!".to_string()).unwrap(), 7);
+        assert_eq!(
+            dcs.get_bytes("blob").unwrap(),
+            Some([&bytes[..], b"!"].concat())
+        );
+        dcs.set_bytes("text".to_string(), b"a\0b".to_vec()).unwrap();
+        assert_eq!(dcs.get("text").unwrap(), Some("a\0b".to_string()));
+        assert_eq!(dcs.get_bytes("missing").unwrap(), None);
+    }
+
+    #[test]
+    fn test_set_bit_grows_string() {
+        let dcs = DCS::new();
+        assert!(!dcs.set_bit("bits".to_string(), 100, true).unwrap());
+        assert_eq!(dcs.get_bytes("bits").unwrap().unwrap().len(), 13);
+        assert!(dcs.get_bit("bits", 100).unwrap());
+        assert!(!dcs.get_bit("bits", 99).unwrap());
+        assert!(!dcs.get_bit("bits", 10_000).unwrap());
+        assert!(dcs.set_bit("bits".to_string(), 100, true).unwrap());
+        assert!(dcs.set_bit("bits".to_string(), 100, false).unwrap());
+        assert!(!dcs.get_bit("bits", 100).unwrap());
+        assert!(!dcs.get_bit("missing", 0).unwrap());
+        assert!(matches!(
+            dcs.set_bit("bits".to_string(), usize::MAX, true),
+            Err(DcsError::IndexOutOfRange)
+        ));
+        assert!(matches!(
+            dcs.set_bit("huge".to_string(), 1 << 32, true),
+            Err(DcsError::IndexOutOfRange)
+        ));
+        assert!(!dcs.exists("huge").unwrap());
+    }
+
+    #[test]
+    fn test_bit_count() {
+        let dcs = DCS::new();
+        dcs.set("key".to_string(), "a".to_string()).unwrap();
+        assert_eq!(dcs.bit_count("key").unwrap(), 3);
+        assert!(dcs.get_bit("key", 1).unwrap());
+        for offset in [0, 7, 15, 64] {
+            dcs.set_bit("key".to_string(), offset, true).unwrap();
+        }
+        assert_eq!(dcs.bit_count("key").unwrap(), 6);
+        assert_eq!(dcs.bit_count("missing").unwrap(), 0);
+    }
+
+    #[test]
+    fn test_get_del() {
+        let dcs = DCS::new();
+        dcs.set("token".to_string(), "once".to_string()).unwrap();
+        dcs.expire("token", Duration::from_secs(60)).unwrap();
+        assert_eq!(dcs.get_del("token").unwrap(), Some("once".to_string()));
+        assert_eq!(dcs.get_del("token").unwrap(), None);
+        assert!(!dcs.exists("token").unwrap());
+        dcs.set("token".to_string(), "again".to_string()).unwrap();
+        assert_eq!(dcs.ttl("token").unwrap(), None);
+    }
+
+    #[test]
+    fn test_get_del_keeps_other_stores() {
+        let mut dcs = DCS::new();
+        legacy(&mut dcs, |dcs| {
+            dcs.set("key1".to_string(), "value1".to_string()).unwrap();
+            dcs.list_push("key1".to_string(), "item".to_string())
+                .unwrap();
+        });
+        dcs.expire("key1", Duration::from_secs(60)).unwrap();
+        assert_eq!(dcs.get_del("key1").unwrap(), Some("value1".to_string()));
+        assert_eq!(dcs.get("key1").unwrap(), None);
+        assert_eq!(dcs.type_of("key1").unwrap(), vec![KeyType::List]);
+        assert_eq!(dcs.list_index("key1", 0).unwrap(), Some("item".to_string()));
+        assert!(dcs.ttl("key1").unwrap().is_some());
+    }
+
+    #[test]
+    fn test_get_range_negative_indices() {
+        let dcs = DCS::new();
+        dcs.set("key".to_string(), "Hello, world".to_string())
+            .unwrap();
+        assert_eq!(dcs.str_len("key").unwrap(), 12);
+        assert_eq!(dcs.get_range("key", 0, 4).unwrap(), "Hello");
+        assert_eq!(dcs.get_range("key", -5, -1).unwrap(), "world");
+        assert_eq!(dcs.get_range("key", -100, 100).unwrap(), "Hello, world");
+        assert_eq!(dcs.get_range("key", 5, 2).unwrap(), "");
+        assert_eq!(dcs.get_range("missing", 0, -1).unwrap(), "");
+        assert_eq!(dcs.str_len("missing").unwrap(), 0);
+    }
+
+    #[test]
+    fn test_set_range_extends_value() {
+        let dcs = DCS::new();
+        dcs.set("key".to_string(), "Hello World".to_string())
+            .unwrap();
+        assert_eq!(dcs.set_range("key".to_string(), 6, "Redis").unwrap(), 11);
+        assert_eq!(dcs.get("key").unwrap(), Some("Hello Redis".to_string()));
+        assert_eq!(dcs.set_range("key".to_string(), 14, "!").unwrap(), 15);
+        assert_eq!(
+            dcs.get("key").unwrap(),
+            Some("Hello Redis\0\0\0!".to_string())
+        );
+        assert_eq!(dcs.set_range("missing".to_string(), 3, "").unwrap(), 0);
+        assert!(!dcs.exists("missing").unwrap());
+        assert!(matches!(
+            dcs.set_range("key".to_string(), usize::MAX, "!"),
+            Err(DcsError::Overflow)
+        ));
+        assert!(matches!(
+            dcs.set_range("key".to_string(), 512 * 1024 * 1024, "!"),
+            Err(DcsError::IndexOutOfRange)
+        ));
+        assert_eq!(dcs.str_len("key").unwrap(), 15);
+    }
+
+    #[test]
+    fn test_iter_strings() {
+        let dcs = DCS::new();
+        for (key, value) in [("a", "1"), ("b", "2"), ("c", "3")] {
+            dcs.set(key.to_string(), value.to_string()).unwrap();
+        }
+        dcs.list_push("queue".to_string(), "x".to_string()).unwrap();
+        dcs.set_bytes("blob".to_string(), vec![0xff]).unwrap();
+        let pairs: HashSet<(String, String)> = dcs.iter_strings().unwrap().into_iter().collect();
+        let expected: HashSet<(String, String)> = [("a", "1"), ("b", "2"), ("c", "3")]
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        assert_eq!(pairs, expected);
+    }
+
+    #[test]
+    fn test_max_memory_evicts_least_recently_used() {
+        let dcs = DCS::builder().max_memory(20).build().unwrap();
+        dcs.set("a".to_string(), "12345678".to_string()).unwrap();
+        dcs.set("b".to_string(), "12345678".to_string()).unwrap();
+        dcs.get("a").unwrap();
+        dcs.set("c".to_string(), "12345678".to_string()).unwrap();
+        assert_eq!(dcs.get("b").unwrap(), None);
+        assert_eq!(dcs.get("a").unwrap(), Some("12345678".to_string()));
+        assert_eq!(dcs.get("c").unwrap(), Some("12345678".to_string()));
+        assert!(matches!(
+            dcs.set("big".to_string(), "x".repeat(20)),
+            Err(DcsError::OomRejected)
+        ));
+        assert_eq!(dcs.db_size().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_max_memory_evicts_only_the_string() {
+        let mut dcs = DCS::builder().max_memory(20).build().unwrap();
+        legacy(&mut dcs, |dcs| {
+            dcs.set("a".to_string(), "12345678".to_string()).unwrap();
+            dcs.list_push("a".to_string(), "item".to_string()).unwrap();
+        });
+        dcs.set("b".to_string(), "12345678".to_string()).unwrap();
+        dcs.set("c".to_string(), "12345678".to_string()).unwrap();
+        assert_eq!(dcs.get("a").unwrap(), None);
+        assert_eq!(dcs.list_index("a", 0).unwrap(), Some("item".to_string()));
+        assert_eq!(dcs.type_of("a").unwrap(), vec![KeyType::List]);
+    }
+
+    #[test]
+    fn test_max_memory_rejects_growing_past_budget() {
+        let dcs = DCS::builder().max_memory(10).build().unwrap();
+        dcs.set("a".to_string(), "1234".to_string()).unwrap();
+        assert!(matches!(
+            dcs.append("a".to_string(), "x".repeat(8)),
+            Err(DcsError::OomRejected)
+        ));
+        assert!(matches!(
+            dcs.set_range("a".to_string(), 12, "x"),
+            Err(DcsError::OomRejected)
+        ));
+        assert!(matches!(
+            dcs.set_bit("a".to_string(), 100, true),
+            Err(DcsError::OomRejected)
+        ));
+        assert_eq!(dcs.get("a").unwrap(), Some("1234".to_string()));
+        dcs.set("n".to_string(), "1".to_string()).unwrap();
+        assert!(matches!(
+            dcs.incr_by("n".to_string(), i64::MAX - 1),
+            Err(DcsError::OomRejected)
+        ));
+        assert_eq!(dcs.get("n").unwrap(), Some("1".to_string()));
+    }
+
+    #[test]
+    fn test_touch_resets_idle_time() {
+        let dcs = DCS::new();
+        dcs.set("a".to_string(), "1".to_string()).unwrap();
+        dcs.set("b".to_string(), "2".to_string()).unwrap();
+        dcs.list_push("c".to_string(), "3".to_string()).unwrap();
+        assert_eq!(dcs.idle_time("c").unwrap(), None);
+        thread::sleep(Duration::from_millis(50));
+        let keys = ["a", "c", "missing"].map(String::from);
+        assert_eq!(dcs.touch(&keys).unwrap(), 2);
+        assert!(dcs.idle_time("a").unwrap().unwrap() < Duration::from_millis(50));
+        assert!(dcs.idle_time("c").unwrap().unwrap() < Duration::from_millis(50));
+        assert!(dcs.idle_time("b").unwrap().unwrap() >= Duration::from_millis(50));
+        assert_eq!(dcs.idle_time("missing").unwrap(), None);
+        dcs.del("a").unwrap();
+        assert_eq!(dcs.idle_time("a").unwrap(), None);
+    }
+
+    #[test]
+    fn test_list_pop_multi() {
+        let dcs = DCS::new();
+        let values = ["a", "b", "c"].map(String::from).to_vec();
+        dcs.list_push_multi("queue".to_string(), values).unwrap();
+        assert_eq!(dcs.list_pop_multi("queue", 2).unwrap(), vec!["c", "b"]);
+        assert_eq!(dcs.list_pop_multi("queue", 5).unwrap(), vec!["a"]);
+        assert_eq!(dcs.list_len("queue").unwrap(), 0);
+        assert!(dcs.list_pop_multi("queue", 5).unwrap().is_empty());
+        assert!(dcs.list_pop_multi("missing", 5).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_emptied_collections_are_removed() {
+        let dcs = DCS::new();
+        dcs.list_push("list".to_string(), "a".to_string()).unwrap();
+        dcs.expire("list", Duration::from_secs(60)).unwrap();
+        dcs.list_pop("list").unwrap();
+        assert!(!dcs.exists("list").unwrap());
+        dcs.list_push("list".to_string(), "a".to_string()).unwrap();
+        assert_eq!(dcs.ttl("list").unwrap(), None);
+        dcs.list_pop_front("list").unwrap();
+        assert!(!dcs.exists("list").unwrap());
+        dcs.hash_set("hash".to_string(), "f".to_string(), "v".to_string())
+            .unwrap();
+        dcs.hash_del("hash".to_string(), "f".to_string()).unwrap();
+        assert!(!dcs.exists("hash").unwrap());
+        dcs.set_add_multi("set".to_string(), vec!["a".to_string(), "b".to_string()])
+            .unwrap();
+        dcs.set_remove("set".to_string(), "a".to_string()).unwrap();
+        assert!(dcs.exists("set").unwrap());
+        dcs.set_remove_multi("set".to_string(), vec!["b".to_string()])
+            .unwrap();
+        assert!(!dcs.exists("set").unwrap());
+        dcs.zset_add("zset".to_string(), 1.0, "a".to_string())
+            .unwrap();
+        dcs.zset_remove("zset".to_string(), "a".to_string())
+            .unwrap();
+        assert!(!dcs.exists("zset").unwrap());
+        dcs.zset_add("zset".to_string(), 1.0, "a".to_string())
+            .unwrap();
+        dcs.zset_pop_min("zset", 5).unwrap();
+        assert!(!dcs.exists("zset").unwrap());
+        assert_eq!(dcs.db_size().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_get_or_set_with_runs_once() {
+        let dcs = DCS::new();
+        let mut calls = 0;
+        let mut compute = || {
+            calls += 1;
+            "computed".to_string()
+        };
+        assert_eq!(
+            dcs.get_or_set_with("memo", &mut compute).unwrap(),
+            "computed"
+        );
+        assert_eq!(
+            dcs.get_or_set_with("memo", &mut compute).unwrap(),
+            "computed"
+        );
+        assert_eq!(calls, 1);
+        assert_eq!(dcs.get("memo").unwrap(), Some("computed".to_string()));
+    }
+
+    #[test]
+    fn test_poisoned_lock_is_named() {
+        let dcs = Arc::new(DCS::new());
+        let poisoner = Arc::clone(&dcs);
+        thread::spawn(move || {
+            let _expirations = poisoner.expirations.write().unwrap();
+            panic!("poisoning the TTL lock");
+        })
+        .join()
+        .unwrap_err();
+        let err = dcs.get("key").unwrap_err();
+        assert!(matches!(&err, DcsError::Poisoned(name) if name == "expirations"));
+        let boxed: Box<dyn std::error::Error + Send + Sync> = Box::new(err);
+        assert_eq!(boxed.to_string(), "lock on expirations poisoned");
+    }
+
+    #[test]
+    fn test_from_pairs() {
+        let dcs = DCS::from_pairs([
+            ("a".to_string(), "1".to_string()),
+            ("b".to_string(), "2".to_string()),
+            ("a".to_string(), "3".to_string()),
+        ]);
+        assert_eq!(dcs.get("a").unwrap(), Some("3".to_string()));
+        assert_eq!(dcs.get("b").unwrap(), Some("2".to_string()));
+        assert_eq!(dcs.db_size().unwrap(), 2);
+        let dcs: DCS = (0..3).map(|i| (i.to_string(), i.to_string())).collect();
+        assert_eq!(dcs.get("2").unwrap(), Some("2".to_string()));
+    }
+
+    #[test]
+    fn test_multi_writes_count_new_entries() {
+        let dcs = DCS::new();
+        dcs.list_push("list1".to_string(), "x".to_string()).unwrap();
+        assert_eq!(
+            dcs.list_push_front_multi("list1".to_string(), vec!["a".to_string(), "b".to_string()])
+                .unwrap(),
+            3
+        );
+        assert_eq!(dcs.list_range("list1", 0, -1).unwrap(), vec!["b", "a", "x"]);
+
+        dcs.hash_set("hash1".to_string(), "f1".to_string(), "v1".to_string())
+            .unwrap();
+        let pairs = vec![
+            ("f1".to_string(), "w1".to_string()),
+            ("f2".to_string(), "w2".to_string()),
+        ];
+        assert_eq!(dcs.hash_set_multi("hash1".to_string(), pairs).unwrap(), 1);
+        assert_eq!(dcs.hash_get("hash1", "f1").unwrap(), Some("w1".to_string()));
+
+        dcs.zset_add("zset1".to_string(), 1.0, "a".to_string())
+            .unwrap();
+        let pairs = vec![(2.0, "a".to_string()), (3.0, "b".to_string())];
+        assert_eq!(dcs.zset_add_multi("zset1".to_string(), pairs).unwrap(), 1);
+        assert_eq!(dcs.zset_score("zset1", "a").unwrap(), Some(2.0));
+        let pairs = vec![(4.0, "c".to_string()), (f64::NAN, "d".to_string())];
+        assert!(matches!(
+            dcs.zset_add_multi("zset1".to_string(), pairs),
+            Err(DcsError::InvalidScore)
+        ));
+        assert_eq!(dcs.zset_score("zset1", "c").unwrap(), None);
+
+        let fields = vec!["f1".to_string(), "missing".to_string()];
+        assert_eq!(dcs.hash_del_multi("hash1".to_string(), fields).unwrap(), 1);
+        let members = vec!["a".to_string(), "b".to_string()];
+        assert_eq!(
+            dcs.zset_remove_multi("zset1".to_string(), members).unwrap(),
+            2
+        );
+        assert!(!dcs.exists("zset1").unwrap());
+    }
+
+    #[test]
+    fn test_get_nonexistent() {
+        let dcs = DCS::new();
+        assert_eq!(dcs.get("nonexistent_key").unwrap(), None);
+    }
+
+    #[test]
+    fn test_list_push_pop() {
+        let dcs = DCS::new();
+        dcs.list_push("list1".to_string(), "value1".to_string())
+            .unwrap();
+        dcs.list_push("list1".to_string(), "value2".to_string())
+            .unwrap();
+        assert_eq!(dcs.list_pop("list1").unwrap(), Some("value2".to_string()));
+        assert_eq!(dcs.list_pop("list1").unwrap(), Some("value1".to_string()));
+        assert_eq!(dcs.list_pop("list1").unwrap(), None);
+    }
+
+    #[test]
+    fn test_list_len() {
+        let dcs = DCS::new();
+        assert_eq!(dcs.list_len("list1").unwrap(), 0);
+        dcs.list_push("list1".to_string(), "value1".to_string())
+            .unwrap();
+        dcs.list_push("list1".to_string(), "value2".to_string())
+            .unwrap();
+        assert_eq!(dcs.list_len("list1").unwrap(), 2);
+    }
+
+    #[test]
+    fn test_list_push_multi() {
+        let dcs = DCS::new();
+        let values = vec![
+            "value1".to_string(),
+            "value2".to_string(),
+            "value3".to_string(),
+        ];
+        dcs.list_push_multi("list1".to_string(), values).unwrap();
+        assert_eq!(dcs.list_len("list1").unwrap(), 3);
+    }
+
+    #[test]
+    fn test_hash_set_get() {
+        let dcs = DCS::new();
+        dcs.hash_set(
+            "hash1".to_string(),
+            "field1".to_string(),
+            "value1".to_string(),
+        )
+        .unwrap();
+        assert_eq!(
+            dcs.hash_get("hash1", "field1").unwrap(),
+            Some("value1".to_string())
+        );
+    }
+
+    #[test]
+    fn test_hash_del() {
+        let dcs = DCS::new();
+        dcs.hash_set(
+            "hash1".to_string(),
+            "field1".to_string(),
+            "value1".to_string(),
+        )
+        .unwrap();
+        assert_eq!(
+            dcs.hash_del("hash1".to_string(), "field1".to_string())
+                .unwrap(),
+            true
+        );
+        assert_eq!(dcs.hash_get("hash1", "field1").unwrap(), None);
+    }
+
+    #[test]
+    fn test_set_add_is_member() {
+        let dcs = DCS::new();
+        dcs.set_add("set1".to_string(), "value1".to_string())
+            .unwrap();
+        assert_eq!(dcs.set_is_member("set1", "value1").unwrap(), true);
+        assert_eq!(dcs.set_is_member("set1", "value2").unwrap(), false);
+    }
+
+    #[test]
+    fn test_set_remove() {
+        let dcs = DCS::new();
+        dcs.set_add("set1".to_string(), "value1".to_string())
+            .unwrap();
+        assert_eq!(
+            dcs.set_remove("set1".to_string(), "value1".to_string())
+                .unwrap(),
+            true
+        );
+        assert_eq!(dcs.set_is_member("set1", "value1").unwrap(), false);
+    }
+
+    #[test]
+    fn test_zset_add_score() {
+        let dcs = DCS::new();
+        dcs.zset_add("zset1".to_string(), 1.0, "value1".to_string())
+            .unwrap();
+        assert_eq!(dcs.zset_score("zset1", "value1").unwrap(), Some(1.0));
+        assert_eq!(dcs.zset_score("zset1", "value2").unwrap(), None);
+    }
+
+    #[test]
+    fn test_zset_remove() {
+        let dcs = DCS::new();
+        dcs.zset_add("zset1".to_string(), 1.0, "value1".to_string())
+            .unwrap();
+        assert_eq!(
+            dcs.zset_remove("zset1".to_string(), "value1".to_string())
+                .unwrap(),
+            true
+        );
+        assert_eq!(dcs.zset_score("zset1", "value1").unwrap(), None);
+    }
+
+    #[test]
+    fn test_del_single_store() {
+        let dcs = DCS::new();
+        dcs.set("key1".to_string(), "value1".to_string()).unwrap();
+        assert!(dcs.del("key1").unwrap());
+        assert_eq!(dcs.get("key1").unwrap(), None);
+    }
+
+    #[test]
+    fn test_del_multiple_stores() {
+        let mut dcs = DCS::new();
+        legacy(&mut dcs, |dcs| {
+            dcs.set("key1".to_string(), "value1".to_string()).unwrap();
+            dcs.list_push("key1".to_string(), "value1".to_string())
+                .unwrap();
+            dcs.zset_add("key1".to_string(), 1.0, "value1".to_string())
+                .unwrap();
+        });
+        assert!(dcs.del("key1").unwrap());
+        assert_eq!(dcs.get("key1").unwrap(), None);
+        assert_eq!(dcs.list_len("key1").unwrap(), 0);
+        assert_eq!(dcs.zset_score("key1", "value1").unwrap(), None);
+    }
+
+    #[test]
+    fn test_del_nonexistent() {
+        let dcs = DCS::new();
+        assert!(!dcs.del("nonexistent_key").unwrap());
+    }
+
+    #[test]
+    fn test_exists() {
+        let dcs = DCS::new();
+        assert!(!dcs.exists("zset1").unwrap());
+        dcs.zset_add("zset1".to_string(), 1.0, "value1".to_string())
+            .unwrap();
+        assert!(dcs.exists("zset1").unwrap());
+    }
+
+    #[test]
+    fn test_exists_in_two_stores() {
+        let mut dcs = DCS::new();
+        legacy(&mut dcs, |dcs| {
+            dcs.list_push("key1".to_string(), "value1".to_string())
+                .unwrap();
+            dcs.hash_set("key1".to_string(), "f".to_string(), "v".to_string())
+                .unwrap();
+        });
+        assert!(dcs.exists("key1").unwrap());
+        assert!(dcs.del("key1").unwrap());
+        assert!(dcs.list_len("key1").unwrap() == 0 && dcs.hash_len("key1").unwrap() == 0);
+        assert!(!dcs.exists("key1").unwrap());
+        assert!(!dcs.del("key1").unwrap());
+    }
+
+    #[test]
+    fn test_incr_decr() {
+        let dcs = DCS::new();
+        assert_eq!(dcs.incr("counter".to_string()).unwrap(), 1);
+        assert_eq!(dcs.incr_by("counter".to_string(), 10).unwrap(), 11);
+        assert_eq!(dcs.decr("counter".to_string()).unwrap(), 10);
+        assert_eq!(dcs.decr_by("counter".to_string(), 15).unwrap(), -5);
+        assert_eq!(dcs.get("counter").unwrap(), Some("-5".to_string()));
+    }
+
+    #[test]
+    fn test_incr_not_an_integer() {
+        let dcs = DCS::new();
+        dcs.set("key1".to_string(), "value1".to_string()).unwrap();
+        assert!(matches!(
+            dcs.incr("key1".to_string()),
+            Err(DcsError::NotAnInteger { key }) if key == "key1"
+        ));
+    }
+
+    #[test]
+    fn test_incr_concurrent() {
+        let dcs = Arc::new(DCS::new());
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let dcs = Arc::clone(&dcs);
+                thread::spawn(move || {
+                    for _ in 0..500 {
+                        dcs.incr("counter".to_string()).unwrap();
+                        dcs.incr_by("counter".to_string(), 2).unwrap();
+                        dcs.decr("counter".to_string()).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(dcs.get("counter").unwrap(), Some("8000".to_string()));
+    }
+
+    #[test]
+    fn test_incr_overflow() {
+        let dcs = DCS::new();
+        dcs.set("counter".to_string(), i64::MAX.to_string())
+            .unwrap();
+        assert!(matches!(
+            dcs.incr("counter".to_string()),
+            Err(DcsError::Overflow)
+        ));
+        assert_eq!(dcs.get("counter").unwrap(), Some(i64::MAX.to_string()));
+    }
+
+    #[test]
+    fn test_append() {
+        let dcs = DCS::new();
+        assert_eq!(
+            dcs.append("key1".to_string(), "foo".to_string()).unwrap(),
+            3
+        );
+        assert_eq!(
+            dcs.append("key1".to_string(), "bar".to_string()).unwrap(),
+            6
+        );
+        assert_eq!(dcs.get("key1").unwrap(), Some("foobar".to_string()));
+    }
+
+    #[test]
+    fn test_get_set() {
+        let dcs = DCS::new();
+        assert_eq!(
+            dcs.get_set("key1".to_string(), "value1".to_string())
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            dcs.get_set("key1".to_string(), "value2".to_string())
+                .unwrap(),
+            Some("value1".to_string())
+        );
+        assert_eq!(dcs.get("key1").unwrap(), Some("value2".to_string()));
+    }
+
+    #[test]
+    fn test_set_nx() {
+        let dcs = DCS::new();
+        assert!(dcs
+            .set_nx("key1".to_string(), "value1".to_string())
+            .unwrap());
+        assert!(!dcs
+            .set_nx("key1".to_string(), "value2".to_string())
+            .unwrap());
+        assert_eq!(dcs.get("key1").unwrap(), Some("value1".to_string()));
+    }
+
+    #[test]
+    fn test_set_nx_race() {
+        let dcs = Arc::new(DCS::new());
+        let handles: Vec<_> = (0..16)
+            .map(|i| {
+                let dcs = Arc::clone(&dcs);
+                thread::spawn(move || dcs.set_nx("lock".to_string(), i.to_string()).unwrap())
+            })
+            .collect();
+        let winners: Vec<usize> = handles
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, handle)| handle.join().unwrap().then_some(i))
+            .collect();
+        assert_eq!(winners.len(), 1);
+        assert_eq!(dcs.get("lock").unwrap(), Some(winners[0].to_string()));
+    }
+
+    #[test]
+    fn test_mset_mget() {
+        let dcs = DCS::new();
+        dcs.mset(vec![
+            ("key1".to_string(), "value1".to_string()),
+            ("key2".to_string(), "value2".to_string()),
+        ])
+        .unwrap();
+        let keys = vec![
+            "key1".to_string(),
+            "nonexistent_key".to_string(),
+            "key2".to_string(),
+        ];
+        assert_eq!(
+            dcs.mget(&keys).unwrap(),
+            vec![Some("value1".to_string()), None, Some("value2".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_expire() {
+        let dcs = DCS::new();
+        dcs.set("key1".to_string(), "value1".to_string()).unwrap();
+        assert!(dcs.expire("key1", Duration::from_millis(50)).unwrap());
+        assert_eq!(dcs.get("key1").unwrap(), Some("value1".to_string()));
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(dcs.get("key1").unwrap(), None);
+        assert!(!dcs.exists("key1").unwrap());
+    }
+
+    #[test]
+    fn test_expire_list() {
+        let dcs = DCS::new();
+        dcs.list_push("list1".to_string(), "value1".to_string())
+            .unwrap();
+        assert!(dcs.expire("list1", Duration::from_millis(50)).unwrap());
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(dcs.list_len("list1").unwrap(), 0);
+    }
+
+    #[test]
+    fn test_expire_hash_set_zset() {
+        let dcs = DCS::new();
+        dcs.hash_set("hash1".to_string(), "f".to_string(), "v".to_string())
+            .unwrap();
+        dcs.set_add("set1".to_string(), "a".to_string()).unwrap();
+        dcs.zset_add("zset1".to_string(), 1.0, "a".to_string())
+            .unwrap();
+        for key in ["hash1", "set1", "zset1"] {
+            assert!(dcs.expire(key, Duration::from_millis(50)).unwrap());
+        }
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(dcs.hash_get("hash1", "f").unwrap(), None);
+        assert!(!dcs.set_is_member("set1", "a").unwrap());
+        assert_eq!(dcs.zset_score("zset1", "a").unwrap(), None);
+        assert_eq!(dcs.ttl("hash1").unwrap(), None);
+        assert_eq!(dcs.db_size().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_expire_nonexistent() {
+        let dcs = DCS::new();
+        assert!(!dcs
+            .expire("nonexistent_key", Duration::from_secs(10))
+            .unwrap());
+        assert_eq!(dcs.ttl("nonexistent_key").unwrap(), None);
+    }
+
+    #[test]
+    fn test_ttl() {
+        let dcs = DCS::new();
+        dcs.set("key1".to_string(), "value1".to_string()).unwrap();
+        assert_eq!(dcs.ttl("key1").unwrap(), None);
+        dcs.expire("key1", Duration::from_secs(10)).unwrap();
+        let ttl = dcs.ttl("key1").unwrap().unwrap();
+        assert!(ttl <= Duration::from_secs(10));
+        assert!(ttl > Duration::from_secs(9));
+    }
+
+    #[test]
+    fn test_set_after_expiry() {
+        let dcs = DCS::new();
+        dcs.set("key1".to_string(), "value1".to_string()).unwrap();
+        dcs.expire("key1", Duration::from_millis(50)).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        dcs.set("key1".to_string(), "value2".to_string()).unwrap();
+        assert_eq!(dcs.get("key1").unwrap(), Some("value2".to_string()));
+        assert_eq!(dcs.ttl("key1").unwrap(), None);
+    }
+
+    #[test]
+    fn test_persist() {
+        let dcs = DCS::new();
+        dcs.set("key1".to_string(), "value1".to_string()).unwrap();
+        dcs.expire("key1", Duration::from_millis(50)).unwrap();
+        assert!(dcs.persist("key1").unwrap());
+        assert!(!dcs.persist("key1").unwrap());
+        assert_eq!(dcs.ttl("key1").unwrap(), None);
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(dcs.get("key1").unwrap(), Some("value1".to_string()));
+    }
+
+    #[test]
+    fn test_hash_set_multi_from_map() {
+        let dcs = DCS::new();
+        dcs.hash_set("hash1".to_string(), "f1".to_string(), "v1".to_string())
+            .unwrap();
+        let fields = HashMap::from([
+            ("f1".to_string(), "w1".to_string()),
+            ("f2".to_string(), "w2".to_string()),
+        ]);
+        assert_eq!(dcs.hash_set_multi("hash1".to_string(), fields).unwrap(), 1);
+        assert_eq!(dcs.hash_get("hash1", "f1").unwrap(), Some("w1".to_string()));
+        assert_eq!(dcs.hash_get("hash1", "f2").unwrap(), Some("w2".to_string()));
+        assert_eq!(
+            dcs.hash_set_multi("hash1".to_string(), HashMap::new())
+                .unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_type_of() {
+        let dcs = DCS::new();
+        dcs.set("key1".to_string(), "value1".to_string()).unwrap();
+        assert_eq!(dcs.type_of("key1").unwrap(), vec![KeyType::String]);
+        dcs.zset_add("key2".to_string(), 1.0, "value1".to_string())
+            .unwrap();
+        assert_eq!(dcs.type_of("key2").unwrap(), vec![KeyType::ZSet]);
+    }
+
+    #[test]
+    fn test_type_of_no_store() {
+        let dcs = DCS::new();
+        assert_eq!(dcs.type_of("key1").unwrap(), vec![]);
+        dcs.list_push("key1".to_string(), "value1".to_string())
+            .unwrap();
+        dcs.list_pop("key1").unwrap();
+        assert_eq!(dcs.type_of("key1").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_type_of_multiple_stores() {
+        let mut dcs = DCS::new();
+        legacy(&mut dcs, |dcs| {
+            dcs.zset_add("key1".to_string(), 1.0, "value1".to_string())
+                .unwrap();
+            dcs.set_add("key1".to_string(), "value1".to_string())
+                .unwrap();
+            dcs.set("key1".to_string(), "value1".to_string()).unwrap();
+        });
+        assert_eq!(
+            dcs.type_of("key1").unwrap(),
+            vec![KeyType::String, KeyType::Set, KeyType::ZSet]
+        );
+        legacy(&mut dcs, |dcs| {
+            dcs.hash_set("key1".to_string(), "f".to_string(), "v".to_string())
+                .unwrap();
+            dcs.list_push("key1".to_string(), "value1".to_string())
+                .unwrap();
+        });
+        assert_eq!(
+            dcs.type_of("key1").unwrap(),
+            vec![
+                KeyType::String,
+                KeyType::List,
+                KeyType::Hash,
+                KeyType::Set,
+                KeyType::ZSet
+            ]
+        );
+    }
+
+    #[test]
+    fn test_wrong_type() {
+        let dcs = DCS::new();
+        dcs.set("key1".to_string(), "value1".to_string()).unwrap();
+        let err = dcs
+            .list_push("key1".to_string(), "value1".to_string())
+            .unwrap_err();
+        assert!(matches!(
+            &err,
+            DcsError::WrongType {
+                key,
+                expected: KeyType::List,
+                found: KeyType::String,
+            } if key == "key1"
+        ));
+        assert_eq!(err.to_string(), "key1 holds a string, not a list");
+        assert!(matches!(
+            dcs.zset_add("key1".to_string(), 1.0, "value1".to_string()),
+            Err(DcsError::WrongType { .. })
+        ));
+        assert_eq!(dcs.type_of("key1").unwrap(), vec![KeyType::String]);
+        dcs.set("key1".to_string(), "value2".to_string()).unwrap();
+        dcs.del("key1").unwrap();
+        dcs.list_push("key1".to_string(), "value1".to_string())
+            .unwrap();
+        assert_eq!(dcs.type_of("key1").unwrap(), vec![KeyType::List]);
+    }
+
+    #[test]
+    fn test_racing_writers_cannot_both_take_key() {
+        for _ in 0..200 {
+            let dcs = DCS::new();
+            let barrier = std::sync::Barrier::new(2);
+            let (set, pushed) = thread::scope(|scope| {
+                let set = scope.spawn(|| {
+                    barrier.wait();
+                    dcs.set("key1".to_string(), "value1".to_string())
+                });
+                barrier.wait();
+                let pushed = dcs.list_push("key1".to_string(), "value1".to_string());
+                (set.join().unwrap(), pushed)
+            });
+            assert!(set.is_ok() != pushed.is_ok());
+            assert_eq!(dcs.type_of("key1").unwrap().len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_set_then_list_push_errors() {
+        let dcs = DCS::new();
+        dcs.set("key1".to_string(), "value1".to_string()).unwrap();
+        assert!(matches!(
+            dcs.list_push("key1".to_string(), "value1".to_string()),
+            Err(DcsError::WrongType {
+                expected: KeyType::List,
+                found: KeyType::String,
+                ..
+            })
+        ));
+        assert_eq!(dcs.type_of("key1").unwrap(), vec![KeyType::String]);
+        assert_eq!(dcs.list_len("key1").unwrap(), 0);
+        assert_eq!(dcs.get("key1").unwrap(), Some("value1".to_string()));
+    }
+
+    #[test]
+    fn test_flush_all() {
+        let dcs = DCS::new();
+        dcs.set("key1".to_string(), "value1".to_string()).unwrap();
+        dcs.list_push("list1".to_string(), "value1".to_string())
+            .unwrap();
+        dcs.hash_set(
+            "hash1".to_string(),
+            "field1".to_string(),
+            "value1".to_string(),
+        )
+        .unwrap();
+        dcs.set_add("set1".to_string(), "value1".to_string())
+            .unwrap();
+        dcs.zset_add("zset1".to_string(), 1.0, "value1".to_string())
+            .unwrap();
+        dcs.expire("key1", Duration::from_secs(10)).unwrap();
+        assert_eq!(dcs.len(), 5);
+        dcs.flush_all().unwrap();
+        assert_eq!(dcs.len(), 0);
+        assert_eq!(dcs.db_size().unwrap(), 0);
+        for key in ["key1", "list1", "hash1", "set1", "zset1"] {
+            assert!(!dcs.exists(key).unwrap());
+        }
+        assert_eq!(dcs.ttl("key1").unwrap(), None);
+    }
+
+    #[test]
+    fn test_snapshot_is_independent() {
+        let dcs = DCS::default();
+        dcs.set("name".to_string(), "dcs".to_string()).unwrap();
+        dcs.list_push("queue".to_string(), "a".to_string()).unwrap();
+        dcs.zset_add("board".to_string(), 1.0, "alice".to_string())
+            .unwrap();
+        dcs.expire("name", Duration::from_secs(60)).unwrap();
+        let copy = dcs.snapshot().unwrap();
+        dcs.set("name".to_string(), "changed".to_string()).unwrap();
+        dcs.list_push("queue".to_string(), "b".to_string()).unwrap();
+        dcs.zset_remove("board".to_string(), "alice".to_string())
+            .unwrap();
+        dcs.flush_all().unwrap();
+        assert_eq!(copy.get("name").unwrap(), Some("dcs".to_string()));
+        assert!(copy.ttl("name").unwrap().is_some());
+        assert_eq!(copy.list_range("queue", 0, -1).unwrap(), vec!["a"]);
+        assert_eq!(copy.zset_score("board", "alice").unwrap(), Some(1.0));
+        assert_eq!(copy.db_size().unwrap(), 3);
+    }
+
+    #[test]
+    fn test_flush_type() {
+        let mut dcs = DCS::new();
+        legacy(&mut dcs, |dcs| {
+            dcs.set("key1".to_string(), "value1".to_string()).unwrap();
+            dcs.set_add("key1".to_string(), "value1".to_string())
+                .unwrap();
+        });
+        dcs.set("key2".to_string(), "value2".to_string()).unwrap();
+        dcs.expire("key2", Duration::from_secs(10)).unwrap();
+        dcs.flush_type(KeyType::String).unwrap();
+        assert_eq!(dcs.type_of("key1").unwrap(), vec![KeyType::Set]);
+        assert!(!dcs.exists("key2").unwrap());
+        assert_eq!(dcs.ttl("key2").unwrap(), None);
+    }
+
+    #[test]
+    fn test_flush_type_every_store() {
+        let dcs = DCS::new();
+        dcs.set("key1".to_string(), "value1".to_string()).unwrap();
+        dcs.list_push("list1".to_string(), "value1".to_string())
+            .unwrap();
+        dcs.hash_set("hash1".to_string(), "f".to_string(), "v".to_string())
+            .unwrap();
+        dcs.set_add("set1".to_string(), "value1".to_string())
+            .unwrap();
+        dcs.zset_add("zset1".to_string(), 1.0, "value1".to_string())
+            .unwrap();
+        dcs.expire("zset1", Duration::from_secs(10)).unwrap();
+        assert_eq!(dcs.len(), 5);
+        dcs.flush_type(KeyType::List).unwrap();
+        assert_eq!(dcs.len(), 4);
+        assert!(!dcs.exists("list1").unwrap());
+        assert!(dcs.exists("hash1").unwrap());
+        for ty in [KeyType::String, KeyType::Hash, KeyType::Set, KeyType::ZSet] {
+            dcs.flush_type(ty).unwrap();
+        }
+        assert_eq!(dcs.len(), 0);
+        assert!(dcs.is_empty());
+        assert_eq!(dcs.ttl("zset1").unwrap(), None);
+    }
+
+    #[test]
+    fn test_db_size() {
+        let mut dcs = DCS::new();
+        assert_eq!(dcs.db_size().unwrap(), 0);
+        legacy(&mut dcs, |dcs| {
+            dcs.set("key1".to_string(), "value1".to_string()).unwrap();
+            dcs.list_push("key1".to_string(), "value1".to_string())
+                .unwrap();
+        });
+        dcs.set_add("set1".to_string(), "value1".to_string())
+            .unwrap();
+        assert_eq!(dcs.db_size().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_keys() {
+        let mut dcs = DCS::new();
+        dcs.set("user:1".to_string(), "value1".to_string()).unwrap();
+        legacy(&mut dcs, |dcs| {
+            dcs.list_push("user:2".to_string(), "value2".to_string())
+                .unwrap();
+            dcs.set_add("user:2".to_string(), "value2".to_string())
+                .unwrap();
+        });
+        dcs.set("item:1".to_string(), "value1".to_string()).unwrap();
+        dcs.set("item:10".to_string(), "value10".to_string())
+            .unwrap();
+        dcs.set("a*b".to_string(), "value".to_string()).unwrap();
+        dcs.set("axb".to_string(), "value".to_string()).unwrap();
+
+        let mut keys = dcs.keys("user:*").unwrap();
+        keys.sort();
+        assert_eq!(keys, vec!["user:1".to_string(), "user:2".to_string()]);
+        assert_eq!(dcs.keys("item:?").unwrap(), vec!["item:1".to_string()]);
+        assert_eq!(dcs.keys("a\\*b").unwrap(), vec!["a*b".to_string()]);
+        assert_eq!(dcs.keys("item:10").unwrap(), vec!["item:10".to_string()]);
+        assert_eq!(dcs.keys("a[xy]b").unwrap(), vec!["axb".to_string()]);
+        assert!(dcs.keys("item:2").unwrap().is_empty());
+        assert_eq!(dcs.keys("*").unwrap().len(), 6);
+    }
+
+    #[test]
+    fn test_scan() {
+        let mut dcs = DCS::new();
+        for i in 0..25 {
+            dcs.set(format!("key{}", i), "value".to_string()).unwrap();
+            dcs.list_push(format!("list{}", i), "value".to_string())
+                .unwrap();
+        }
+        legacy(&mut dcs, |dcs| {
+            dcs.set_add("key0".to_string(), "value".to_string())
+                .unwrap();
+        });
+
+        let mut scanned = Vec::new();
+        let mut cursor = 0;
+        loop {
+            let (next, keys) = dcs.scan(cursor, 4).unwrap();
+            scanned.extend(keys);
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        let mut expected = dcs.keys("*").unwrap();
+        expected.sort();
+        scanned.sort();
+        assert_eq!(scanned, expected);
+    }
+
+    #[test]
+    fn test_rename_list() {
+        let dcs = DCS::new();
+        dcs.list_push("list1".to_string(), "value1".to_string())
+            .unwrap();
+        dcs.list_push("list2".to_string(), "value2".to_string())
+            .unwrap();
+        dcs.list_push("list2".to_string(), "value3".to_string())
+            .unwrap();
+        dcs.expire("list1", Duration::from_secs(10)).unwrap();
+        assert!(dcs.rename("list1", "list2".to_string()).unwrap());
+        assert!(!dcs.exists("list1").unwrap());
+        assert_eq!(dcs.list_len("list2").unwrap(), 1);
+        assert!(dcs.ttl("list2").unwrap().is_some());
+        assert_eq!(dcs.list_pop("list2").unwrap(), Some("value1".to_string()));
+    }
+
+    #[test]
+    fn test_rename_same_key() {
+        let dcs = DCS::new();
+        dcs.set("key1".to_string(), "value1".to_string()).unwrap();
+        assert!(dcs.rename("key1", "key1".to_string()).unwrap());
+        assert_eq!(dcs.get("key1").unwrap(), Some("value1".to_string()));
+    }
+
+    #[test]
+    fn test_rename_nonexistent() {
+        let dcs = DCS::new();
+        dcs.set("key2".to_string(), "value2".to_string()).unwrap();
+        assert!(!dcs.rename("key1", "key2".to_string()).unwrap());
+        assert_eq!(dcs.get("key2").unwrap(), Some("value2".to_string()));
+    }
+
+    #[test]
+    fn test_rename_nx() {
+        let dcs = DCS::new();
+        dcs.set("key1".to_string(), "value1".to_string()).unwrap();
+        dcs.list_push("key2".to_string(), "value2".to_string())
+            .unwrap();
+        assert!(!dcs.rename_nx("key1", "key2".to_string()).unwrap());
+        assert!(!dcs.rename_nx("key1", "key1".to_string()).unwrap());
+        assert_eq!(dcs.get("key1").unwrap(), Some("value1".to_string()));
+        assert!(!dcs.rename_nx("missing", "key3".to_string()).unwrap());
+        dcs.expire("key1", Duration::from_secs(10)).unwrap();
+        assert!(dcs.rename_nx("key1", "key3".to_string()).unwrap());
+        assert!(!dcs.exists("key1").unwrap());
+        assert_eq!(dcs.get("key3").unwrap(), Some("value1".to_string()));
+        assert!(dcs.ttl("key3").unwrap().is_some());
+    }
+
+    #[test]
+    fn test_copy_replace() {
+        let dcs = DCS::new();
+        dcs.hash_set(
+            "hash1".to_string(),
+            "field1".to_string(),
+            "value1".to_string(),
+        )
+        .unwrap();
+        dcs.hash_set(
+            "hash2".to_string(),
+            "field2".to_string(),
+            "value2".to_string(),
+        )
+        .unwrap();
+        assert!(dcs.copy("hash1", "hash2".to_string(), true).unwrap());
+        assert_eq!(
+            dcs.hash_get("hash2", "field1").unwrap(),
+            Some("value1".to_string())
+        );
+        assert_eq!(dcs.hash_get("hash2", "field2").unwrap(), None);
+        assert_eq!(
+            dcs.hash_get("hash1", "field1").unwrap(),
+            Some("value1".to_string())
+        );
+    }
+
+    #[test]
+    fn test_copy_no_replace() {
+        let mut dcs = DCS::new();
+        legacy(&mut dcs, |dcs| {
+            dcs.set("key1".to_string(), "value1".to_string()).unwrap();
+            dcs.zset_add("key1".to_string(), 1.5, "value1".to_string())
+                .unwrap();
+        });
+        dcs.zset_add("key2".to_string(), 2.0, "value2".to_string())
+            .unwrap();
+        assert!(!dcs.copy("key1", "key2".to_string(), false).unwrap());
+        assert_eq!(dcs.get("key2").unwrap(), None);
+        assert_eq!(dcs.zset_score("key2", "value1").unwrap(), None);
+
+        assert!(dcs.copy("key1", "key3".to_string(), false).unwrap());
+        assert_eq!(dcs.get("key3").unwrap(), Some("value1".to_string()));
+        assert_eq!(dcs.zset_score("key3", "value1").unwrap(), Some(1.5));
+    }
+
+    #[test]
+    fn test_list_range() {
+        let dcs = DCS::new();
+        assert!(dcs.list_range("list1", 0, -1).unwrap().is_empty());
+        let values: Vec<String> = (1..=5).map(|i| format!("value{}", i)).collect();
+        dcs.list_push_multi("list1".to_string(), values.clone())
+            .unwrap();
+        assert_eq!(dcs.list_range("list1", 0, -1).unwrap(), values);
+        assert_eq!(dcs.list_range("list1", -2, -1).unwrap(), values[3..]);
+        assert_eq!(dcs.list_range("list1", 1, 2).unwrap(), values[1..3]);
+        assert_eq!(dcs.list_range("list1", -100, 100).unwrap(), values);
+        assert!(dcs.list_range("list1", 3, 1).unwrap().is_empty());
+        assert!(dcs.list_range("list1", 5, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_list_index() {
+        let dcs = DCS::new();
+        dcs.list_push("list1".to_string(), "value1".to_string())
+            .unwrap();
+        dcs.list_push("list1".to_string(), "value2".to_string())
+            .unwrap();
+        assert_eq!(
+            dcs.list_index("list1", 0).unwrap(),
+            Some("value1".to_string())
+        );
+        assert_eq!(
+            dcs.list_index("list1", -1).unwrap(),
+            Some("value2".to_string())
+        );
+        assert_eq!(dcs.list_index("list1", 2).unwrap(), None);
+        assert_eq!(dcs.list_index("list1", -3).unwrap(), None);
+    }
+
+    #[test]
+    fn test_list_set() {
+        let dcs = DCS::new();
+        dcs.list_push("list1".to_string(), "value1".to_string())
+            .unwrap();
+        dcs.list_push("list1".to_string(), "value2".to_string())
+            .unwrap();
+        dcs.list_set("list1", -2, "value3".to_string()).unwrap();
+        assert_eq!(
+            dcs.list_index("list1", 0).unwrap(),
+            Some("value3".to_string())
+        );
+        assert!(matches!(
+            dcs.list_set("list1", 2, "value4".to_string()),
+            Err(DcsError::IndexOutOfRange)
+        ));
+        assert!(matches!(
+            dcs.list_set("list2", 0, "value4".to_string()),
+            Err(DcsError::IndexOutOfRange)
+        ));
+    }
+
+    #[test]
+    fn test_list_push_pop_front() {
+        let dcs = DCS::new();
+        dcs.list_push("list1".to_string(), "value1".to_string())
+            .unwrap();
+        assert_eq!(
+            dcs.list_push_front("list1".to_string(), "value0".to_string())
+                .unwrap(),
+            2
+        );
+        dcs.list_push("list1".to_string(), "value2".to_string())
+            .unwrap();
+        assert_eq!(
+            dcs.list_pop_front("list1").unwrap(),
+            Some("value0".to_string())
+        );
+        assert_eq!(dcs.list_pop("list1").unwrap(), Some("value2".to_string()));
+        assert_eq!(
+            dcs.list_pop_front("list1").unwrap(),
+            Some("value1".to_string())
+        );
+        assert_eq!(dcs.list_pop_front("list1").unwrap(), None);
+    }
+
+    #[test]
+    fn test_list_trim() {
+        let dcs = DCS::new();
+        let values: Vec<String> = (1..=5).map(|i| format!("value{}", i)).collect();
+        dcs.list_push_multi("list1".to_string(), values.clone())
+            .unwrap();
+        dcs.list_trim("list1", -2, -1).unwrap();
+        assert_eq!(dcs.list_range("list1", 0, -1).unwrap(), values[3..]);
+    }
+
+    #[test]
+    fn test_list_trim_to_empty() {
+        let dcs = DCS::new();
+        dcs.list_push("list1".to_string(), "value1".to_string())
+            .unwrap();
+        dcs.list_trim("list1", 1, 0).unwrap();
+        assert!(!dcs.exists("list1").unwrap());
+    }
+
+    #[test]
+    fn test_list_remove() {
+        let values: Vec<String> = ["a", "b", "a", "c", "a"]
+            .iter()
+            .map(|v| v.to_string())
+            .collect();
+        let dcs = DCS::new();
+        dcs.list_push_multi("list1".to_string(), values.clone())
+            .unwrap();
+        assert_eq!(dcs.list_remove("list1", 0, "a").unwrap(), 3);
+        assert_eq!(dcs.list_range("list1", 0, -1).unwrap(), vec!["b", "c"]);
+
+        dcs.list_push_multi("list2".to_string(), values.clone())
+            .unwrap();
+        assert_eq!(dcs.list_remove("list2", 1, "a").unwrap(), 1);
+        assert_eq!(
+            dcs.list_range("list2", 0, -1).unwrap(),
+            vec!["b", "a", "c", "a"]
+        );
+
+        dcs.list_push_multi("list3".to_string(), values).unwrap();
+        assert_eq!(dcs.list_remove("list3", -1, "a").unwrap(), 1);
+        assert_eq!(
+            dcs.list_range("list3", 0, -1).unwrap(),
+            vec!["a", "b", "a", "c"]
+        );
+        assert_eq!(dcs.list_remove("list4", 0, "a").unwrap(), 0);
+    }
+
+    #[test]
+    fn test_list_insert() {
+        let dcs = DCS::new();
+        dcs.list_push_multi("list1".to_string(), vec!["a".to_string(), "c".to_string()])
+            .unwrap();
+        assert_eq!(
+            dcs.list_insert("list1", true, "c", "b".to_string())
+                .unwrap(),
+            3
+        );
+        assert_eq!(
+            dcs.list_insert("list1", false, "c", "d".to_string())
+                .unwrap(),
+            4
+        );
+        assert_eq!(
+            dcs.list_range("list1", 0, -1).unwrap(),
+            vec!["a", "b", "c", "d"]
+        );
+        assert_eq!(
+            dcs.list_insert("list1", true, "x", "y".to_string())
+                .unwrap(),
+            -1
+        );
+        assert_eq!(
+            dcs.list_insert("list2", true, "x", "y".to_string())
+                .unwrap(),
+            0
+        );
+        assert!(!dcs.exists("list2").unwrap());
+    }
+
+    #[test]
+    fn test_list_move() {
+        let dcs = DCS::new();
+        dcs.list_push_multi(
+            "queue".to_string(),
+            vec!["job1".to_string(), "job2".to_string()],
+        )
+        .unwrap();
+        assert_eq!(
+            dcs.list_move("queue", "processing".to_string()).unwrap(),
+            Some("job2".to_string())
+        );
+        assert_eq!(dcs.list_len("queue").unwrap(), 1);
+        assert_eq!(dcs.list_range("processing", 0, -1).unwrap(), vec!["job2"]);
+        assert_eq!(dcs.list_remove("processing", 1, "job2").unwrap(), 1);
+        assert_eq!(
+            dcs.list_move("empty", "processing".to_string()).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_list_move_rotate() {
+        let dcs = DCS::new();
+        dcs.list_push_multi(
+            "list1".to_string(),
+            vec!["a".to_string(), "b".to_string(), "c".to_string()],
+        )
+        .unwrap();
+        assert_eq!(
+            dcs.list_move("list1", "list1".to_string()).unwrap(),
+            Some("c".to_string())
+        );
+        assert_eq!(dcs.list_range("list1", 0, -1).unwrap(), vec!["c", "a", "b"]);
+    }
+
+    #[test]
+    fn test_list_bpop() {
+        let dcs = Arc::new(DCS::new());
+        let pusher = Arc::clone(&dcs);
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            pusher
+                .list_push("list1".to_string(), "value1".to_string())
+                .unwrap();
+        });
+        let start = Instant::now();
+        assert_eq!(
+            dcs.list_bpop("list1", Duration::from_secs(5)).unwrap(),
+            Some("value1".to_string())
+        );
+        assert!(start.elapsed() < Duration::from_secs(1));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_list_bpop_zero_timeout_waits() {
+        let dcs = Arc::new(DCS::new());
+        let pusher = Arc::clone(&dcs);
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            pusher
+                .list_push("list1".to_string(), "value1".to_string())
+                .unwrap();
+        });
+        assert_eq!(
+            dcs.list_bpop("list1", Duration::ZERO).unwrap(),
+            Some("value1".to_string())
+        );
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_list_bpop_timeout() {
+        let dcs = DCS::new();
+        let start = Instant::now();
+        assert_eq!(
+            dcs.list_bpop("list1", Duration::from_millis(50)).unwrap(),
+            None
+        );
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_hash_get_all() {
+        let dcs = DCS::new();
+        assert!(dcs.hash_get_all("hash1").unwrap().is_empty());
+        for i in 1..=3 {
+            dcs.hash_set(
+                "hash1".to_string(),
+                format!("field{}", i),
+                format!("value{}", i),
+            )
+            .unwrap();
+        }
+        let all = dcs.hash_get_all("hash1").unwrap();
+        assert_eq!(all.len(), 3);
+        assert_eq!(all.get("field2"), Some(&"value2".to_string()));
+
+        let mut keys = dcs.hash_keys("hash1").unwrap();
+        keys.sort();
+        let mut expected_keys: Vec<String> = all.keys().cloned().collect();
+        expected_keys.sort();
+        assert_eq!(keys, expected_keys);
+
+        let mut vals = dcs.hash_vals("hash1").unwrap();
+        vals.sort();
+        let mut expected_vals: Vec<String> = all.values().cloned().collect();
+        expected_vals.sort();
+        assert_eq!(vals, expected_vals);
+    }
+
+    #[test]
+    fn test_hash_len_exists() {
+        let dcs = DCS::new();
+        assert_eq!(dcs.hash_len("hash1").unwrap(), 0);
+        assert!(!dcs.hash_exists("hash1", "field1").unwrap());
+        dcs.hash_set(
+            "hash1".to_string(),
+            "field1".to_string(),
+            "value1".to_string(),
+        )
+        .unwrap();
+        dcs.hash_set(
+            "hash1".to_string(),
+            "field2".to_string(),
+            "value2".to_string(),
+        )
+        .unwrap();
+        assert_eq!(dcs.hash_len("hash1").unwrap(), 2);
+        assert!(dcs.hash_exists("hash1", "field1").unwrap());
+        assert!(!dcs.hash_exists("hash1", "field3").unwrap());
+    }
+
+    #[test]
+    fn test_hash_incr_by() {
+        let dcs = DCS::new();
+        assert_eq!(
+            dcs.hash_incr_by("hash1".to_string(), "clicks".to_string(), 5)
+                .unwrap(),
+            5
+        );
+        assert_eq!(
+            dcs.hash_incr_by("hash1".to_string(), "clicks".to_string(), -2)
+                .unwrap(),
+            3
+        );
+        assert_eq!(
+            dcs.hash_get("hash1", "clicks").unwrap(),
+            Some("3".to_string())
+        );
+    }
+
+    #[test]
+    fn test_hash_incr_by_not_an_integer() {
+        let dcs = DCS::new();
+        dcs.hash_set(
+            "hash1".to_string(),
+            "field1".to_string(),
+            "value1".to_string(),
+        )
+        .unwrap();
+        assert!(matches!(
+            dcs.hash_incr_by("hash1".to_string(), "field1".to_string(), 1),
+            Err(DcsError::NotAnInteger { key }) if key == "hash1"
+        ));
+    }
+
+    #[test]
+    fn test_hash_mset_mget() {
+        let dcs = DCS::new();
+        dcs.hash_mset(
+            "hash1".to_string(),
+            vec![
+                ("field1".to_string(), "value1".to_string()),
+                ("field2".to_string(), "value2".to_string()),
+                ("field3".to_string(), "value3".to_string()),
+            ],
+        )
+        .unwrap();
+        let fields = vec![
+            "field3".to_string(),
+            "field4".to_string(),
+            "field1".to_string(),
+        ];
+        assert_eq!(
+            dcs.hash_mget("hash1", &fields).unwrap(),
+            vec![Some("value3".to_string()), None, Some("value1".to_string())]
+        );
+        assert_eq!(dcs.hash_mget("hash2", &fields).unwrap(), vec![None; 3]);
+    }
+
+    #[test]
+    fn test_hash_set_nx() {
+        let dcs = DCS::new();
+        assert!(dcs
+            .hash_set_nx(
+                "hash1".to_string(),
+                "field1".to_string(),
+                "value1".to_string()
+            )
+            .unwrap());
+        assert!(!dcs
+            .hash_set_nx(
+                "hash1".to_string(),
+                "field1".to_string(),
+                "value2".to_string()
+            )
+            .unwrap());
+        assert_eq!(
+            dcs.hash_get("hash1", "field1").unwrap(),
+            Some("value1".to_string())
+        );
+    }
+
+    #[test]
+    fn test_set_card_members() {
+        let dcs = DCS::new();
+        assert_eq!(dcs.set_card("set1").unwrap(), 0);
+        assert!(dcs.set_members("set1").unwrap().is_empty());
+        for value in ["value1", "value2", "value1", "value3"] {
+            dcs.set_add("set1".to_string(), value.to_string()).unwrap();
+        }
+        let mut members = dcs.set_members("set1").unwrap();
+        members.sort();
+        assert_eq!(members, vec!["value1", "value2", "value3"]);
+        assert_eq!(dcs.set_card("set1").unwrap(), members.len());
+    }
+
+    #[test]
+    fn test_set_add_remove_multi() {
+        let dcs = DCS::new();
+        dcs.set_add("set1".to_string(), "value1".to_string())
+            .unwrap();
+        let values = vec![
+            "value1".to_string(),
+            "value2".to_string(),
+            "value3".to_string(),
+            "value2".to_string(),
+        ];
+        assert_eq!(dcs.set_add_multi("set1".to_string(), values).unwrap(), 2);
+        assert_eq!(dcs.set_card("set1").unwrap(), 3);
+        let removed = vec!["value1".to_string(), "value4".to_string()];
+        assert_eq!(
+            dcs.set_remove_multi("set1".to_string(), removed).unwrap(),
+            1
+        );
+        assert_eq!(dcs.set_card("set1").unwrap(), 2);
+    }
+
+    fn add_members(dcs: &DCS, key: &str, members: &[&str]) {
+        for member in members {
+            dcs.set_add(key.to_string(), member.to_string()).unwrap();
+        }
+    }
+
+    fn sorted(set: HashSet<String>) -> Vec<String> {
+        let mut members: Vec<String> = set.into_iter().collect();
+        members.sort();
+        members
+    }
+
+    #[test]
+    fn test_set_intersect() {
+        let dcs = DCS::new();
+        add_members(&dcs, "set1", &["a", "b", "c", "d"]);
+        add_members(&dcs, "set2", &["b", "c", "d"]);
+        add_members(&dcs, "set3", &["a", "c", "d"]);
+        let keys = vec!["set1".to_string(), "set2".to_string(), "set3".to_string()];
+        assert_eq!(sorted(dcs.set_intersect(&keys).unwrap()), vec!["c", "d"]);
+        let keys = vec!["set1".to_string(), "missing".to_string()];
+        assert!(dcs.set_intersect(&keys).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_set_union() {
+        let dcs = DCS::new();
+        add_members(&dcs, "set1", &["a", "b"]);
+        add_members(&dcs, "set2", &["b", "c"]);
+        let keys = vec![
+            "set1".to_string(),
+            "set2".to_string(),
+            "missing".to_string(),
+        ];
+        assert_eq!(sorted(dcs.set_union(&keys).unwrap()), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_set_diff() {
+        let dcs = DCS::new();
+        add_members(&dcs, "set1", &["a", "b", "c"]);
+        add_members(&dcs, "set2", &["b"]);
+        let keys = vec![
+            "set1".to_string(),
+            "set2".to_string(),
+            "missing".to_string(),
+        ];
+        assert_eq!(sorted(dcs.set_diff(&keys).unwrap()), vec!["a", "c"]);
+        let keys = vec!["missing".to_string(), "set1".to_string()];
+        assert!(dcs.set_diff(&keys).unwrap().is_empty());
+        assert_eq!(
+            sorted(dcs.set_diff(&["set1", "missing"]).unwrap()),
+            vec!["a", "b", "c"]
+        );
+    }
+
+    #[test]
+    fn test_set_intersect_store() {
+        let dcs = DCS::new();
+        add_members(&dcs, "set1", &["a", "b", "c"]);
+        add_members(&dcs, "set2", &["b", "c", "d"]);
+        add_members(&dcs, "dest", &["x"]);
+        let keys = vec!["set1".to_string(), "set2".to_string()];
+        assert_eq!(
+            dcs.set_intersect_store("dest".to_string(), &keys).unwrap(),
+            2
+        );
+        let mut members = dcs.set_members("dest").unwrap();
+        members.sort();
+        assert_eq!(members, vec!["b", "c"]);
+    }
+
+    #[test]
+    fn test_set_store_variants() {
+        let dcs = DCS::new();
+        add_members(&dcs, "set1", &["a", "b"]);
+        add_members(&dcs, "set2", &["b", "c"]);
+        let keys = vec!["set1".to_string(), "set2".to_string()];
+        assert_eq!(dcs.set_union_store("union".to_string(), &keys).unwrap(), 3);
+        assert_eq!(dcs.set_diff_store("diff".to_string(), &keys).unwrap(), 1);
+        assert_eq!(dcs.set_members("diff").unwrap(), vec!["a"]);
+        let keys = vec!["set1".to_string(), "missing".to_string()];
+        assert_eq!(
+            dcs.set_intersect_store("union".to_string(), &keys).unwrap(),
+            0
+        );
+        assert!(!dcs.exists("union").unwrap());
+    }
+
+    #[test]
+    fn test_set_store_variants_with_missing_key() {
+        let dcs = DCS::new();
+        add_members(&dcs, "set1", &["a", "b"]);
+        add_members(&dcs, "inter", &["x"]);
+        assert_eq!(
+            dcs.set_intersect_store("inter".to_string(), &["set1", "missing"])
+                .unwrap(),
+            0
+        );
+        assert!(!dcs.exists("inter").unwrap());
+        assert_eq!(
+            dcs.set_diff_store("diff".to_string(), &["set1", "missing"])
+                .unwrap(),
+            2
+        );
+        assert_eq!(
+            sorted(dcs.set_diff(&["set1", "missing"]).unwrap()),
+            vec!["a", "b"]
+        );
+        assert!(dcs.set_is_member("diff", "b").unwrap());
+        assert_eq!(
+            dcs.set_union_store("union".to_string(), &["missing", "set1"])
+                .unwrap(),
+            2
+        );
+        assert!(dcs.set_intersect(&["set1", "missing"]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_set_move() {
+        let dcs = DCS::new();
+        add_members(&dcs, "pending", &["job1", "job2"]);
+        assert!(dcs.set_move("pending", "done".to_string(), "job1").unwrap());
+        assert!(!dcs.set_is_member("pending", "job1").unwrap());
+        assert!(dcs.set_is_member("done", "job1").unwrap());
+        assert!(!dcs.set_move("pending", "done".to_string(), "job3").unwrap());
+        assert_eq!(dcs.set_card("done").unwrap(), 1);
     }
 
-    pub fn list_push(
-        &self,
-        key: String,
-        value: String,
-    ) -> Result<usize, PoisonError<RwLockWriteGuard<HashMap<String, Vec<String>>>>> {
-        let mut list_store = self.list_store.write()?;
-        let list = list_store.entry(key).or_insert_with(Vec::new);
-        list.push(value);
-        Ok(list.len())
+    #[test]
+    fn test_set_pop() {
+        let dcs = DCS::new();
+        add_members(&dcs, "set1", &["a", "b", "c"]);
+        let popped = dcs.set_pop("set1", 1).unwrap();
+        assert_eq!(popped.len(), 1);
+        assert!(!dcs.set_is_member("set1", &popped[0]).unwrap());
+        let mut rest = dcs.set_pop("set1", 10).unwrap();
+        rest.extend(popped);
+        rest.sort();
+        assert_eq!(rest, vec!["a", "b", "c"]);
+        assert_eq!(dcs.set_card("set1").unwrap(), 0);
+        assert!(dcs.set_pop("set1", 1).unwrap().is_empty());
     }
 
-    pub fn list_push_multi(
-        &self,
-        key: String,
-        values: Vec<String>,
-    ) -> Result<usize, PoisonError<RwLockWriteGuard<HashMap<String, Vec<String>>>>> {
-        let mut list_store = self.list_store.write()?;
-        let list = list_store.entry(key).or_insert_with(Vec::new);
-        list.extend(values);
-        Ok(list.len())
+    #[test]
+    fn test_set_rand_member() {
+        let dcs = DCS::new();
+        add_members(&dcs, "set1", &["a", "b", "c"]);
+        let mut sample = dcs.set_rand_member("set1", 2).unwrap();
+        sample.sort();
+        sample.dedup();
+        assert_eq!(sample.len(), 2);
+        assert_eq!(dcs.set_rand_member("set1", 10).unwrap().len(), 3);
+        assert_eq!(dcs.set_card("set1").unwrap(), 3);
     }
 
-    pub fn list_pop(
-        &self,
-        key: &str,
-    ) -> Result<Option<String>, PoisonError<RwLockWriteGuard<HashMap<String, Vec<String>>>>> {
-        let mut list_store = self.list_store.write()?;
-        match list_store.get_mut(key) {
-            Some(list) => Ok(list.pop()),
-            None => Ok(None),
+    #[test]
+    fn test_hash_rand_field() {
+        let dcs = DCS::new();
+        for field in ["a", "b", "c"] {
+            dcs.hash_set("h".to_string(), field.to_string(), "v".to_string())
+                .unwrap();
         }
+        let mut sample = dcs.hash_rand_field("h", 2).unwrap();
+        sample.sort();
+        sample.dedup();
+        assert_eq!(sample.len(), 2);
+        assert_eq!(dcs.hash_rand_field("h", 10).unwrap().len(), 3);
+        let repeated = dcs.hash_rand_field("h", -7).unwrap();
+        assert_eq!(repeated.len(), 7);
+        assert!(repeated
+            .iter()
+            .all(|field| ["a", "b", "c"].contains(&field.as_str())));
+        assert!(dcs.hash_rand_field("missing", -2).unwrap().is_empty());
     }
 
-    pub fn list_len(
-        &self,
-        key: &str,
-    ) -> Result<usize, PoisonError<RwLockReadGuard<HashMap<String, Vec<String>>>>> {
-        let list_store = self.list_store.read()?;
-        Ok(list_store.get(key).map_or(0, |list| list.len()))
+    #[test]
+    fn test_zset_rand_member() {
+        let dcs = DCS::new();
+        dcs.zset_add("z".to_string(), 1.0, "a".to_string()).unwrap();
+        dcs.zset_add("z".to_string(), 2.0, "b".to_string()).unwrap();
+        let mut sample = dcs.zset_rand_member("z", 5, true).unwrap();
+        sample.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            sample,
+            vec![("a".to_string(), Some(1.0)), ("b".to_string(), Some(2.0))]
+        );
+        let repeated = dcs.zset_rand_member("z", -4, false).unwrap();
+        assert_eq!(repeated.len(), 4);
+        assert!(repeated.iter().all(|(_, score)| score.is_none()));
     }
 
-    pub fn hash_set(
-        &self,
-        key: String,
-        field: String,
-        value: String,
-    ) -> Result<(), PoisonError<RwLockWriteGuard<HashMap<String, HashMap<String, String>>>>> {
-        let mut hash_store = self.hash_store.write()?;
-        let hash = hash_store.entry(key).or_insert_with(HashMap::new);
-        hash.insert(field, value);
-        Ok(())
+    #[test]
+    fn test_set_is_member_multi() {
+        let dcs = DCS::new();
+        add_members(&dcs, "set1", &["a", "c"]);
+        let values = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        assert_eq!(
+            dcs.set_is_member_multi("set1", &values).unwrap(),
+            vec![true, false, true]
+        );
+        assert_eq!(
+            dcs.set_is_member_multi("missing", &values).unwrap(),
+            vec![false; 3]
+        );
     }
 
-    pub fn hash_get(
-        &self,
-        key: &str,
-        field: &str,
-    ) -> Result<
-        Option<String>,
-        PoisonError<RwLockReadGuard<HashMap<String, HashMap<String, String>>>>,
-    > {
-        let hash_store = self.hash_store.read()?;
-        if let Some(hash) = hash_store.get(key) {
-            Ok(hash.get(field).cloned())
-        } else {
-            Ok(None)
+    fn leaderboard(dcs: &DCS) {
+        for (member, score) in [
+            ("alice", 30.0),
+            ("bob", 10.0),
+            ("carol", 20.0),
+            ("dave", 50.0),
+            ("erin", 40.0),
+        ] {
+            dcs.zset_add("board".to_string(), score, member.to_string())
+                .unwrap();
         }
     }
 
-    pub fn hash_del(
-        &self,
-        key: String,
-        field: String,
-    ) -> Result<bool, PoisonError<RwLockWriteGuard<HashMap<String, HashMap<String, String>>>>> {
-        let mut hash_store = self.hash_store.write()?;
-        if let Some(hash) = hash_store.get_mut(&key) {
-            Ok(hash.remove(&field).is_some())
-        } else {
-            Ok(false)
-        }
+    #[test]
+    fn test_zset_range() {
+        let dcs = DCS::new();
+        leaderboard(&dcs);
+        assert_eq!(
+            dcs.zset_range("board", 0, 2, true).unwrap(),
+            vec![
+                ("bob".to_string(), Some(10.0)),
+                ("carol".to_string(), Some(20.0)),
+                ("alice".to_string(), Some(30.0)),
+            ]
+        );
+        assert_eq!(
+            dcs.zset_range("board", -3, -1, false).unwrap(),
+            vec![
+                ("alice".to_string(), None),
+                ("erin".to_string(), None),
+                ("dave".to_string(), None),
+            ]
+        );
+        assert_eq!(dcs.zset_range("board", 0, -1, false).unwrap().len(), 5);
+        assert_eq!(dcs.zset_range("board", -100, 100, false).unwrap().len(), 5);
+        assert!(dcs.zset_range("board", 10, 20, false).unwrap().is_empty());
+        assert!(dcs.zset_range("missing", 0, -1, false).unwrap().is_empty());
     }
 
-    pub fn set_add(
-        &self,
-        key: String,
-        value: String,
-    ) -> Result<(), PoisonError<RwLockWriteGuard<HashMap<String, HashSet<String>>>>> {
-        let mut set_store = self.set_store.write()?;
-        let set = set_store.entry(key).or_insert_with(HashSet::new);
-        set.insert(value);
-        Ok(())
+    #[test]
+    fn test_zset_range_ties() {
+        let dcs = DCS::new();
+        for member in ["c", "a", "b"] {
+            dcs.zset_add("zset1".to_string(), 1.0, member.to_string())
+                .unwrap();
+        }
+        let members: Vec<String> = dcs
+            .zset_range("zset1", 0, -1, false)
+            .unwrap()
+            .into_iter()
+            .map(|(member, _)| member)
+            .collect();
+        assert_eq!(members, vec!["a", "b", "c"]);
     }
 
-    pub fn set_is_member(
-        &self,
-        key: &str,
-        value: &str,
-    ) -> Result<bool, PoisonError<RwLockReadGuard<HashMap<String, HashSet<String>>>>> {
-        let set_store = self.set_store.read()?;
-        if let Some(set) = set_store.get(key) {
-            Ok(set.contains(value))
-        } else {
-            Ok(false)
-        }
+    #[test]
+    fn test_zset_rank() {
+        let dcs = DCS::new();
+        leaderboard(&dcs);
+        assert_eq!(dcs.zset_rank("board", "bob").unwrap(), Some(0));
+        assert_eq!(dcs.zset_rank("board", "dave").unwrap(), Some(4));
+        assert_eq!(dcs.zset_rev_rank("board", "dave").unwrap(), Some(0));
+        assert_eq!(dcs.zset_rev_rank("board", "bob").unwrap(), Some(4));
+        assert_eq!(dcs.zset_rank("board", "missing").unwrap(), None);
+        assert_eq!(dcs.zset_rank("missing", "bob").unwrap(), None);
     }
 
-    pub fn set_remove(
-        &self,
-        key: String,
-        value: String,
-    ) -> Result<bool, PoisonError<RwLockWriteGuard<HashMap<String, HashSet<String>>>>> {
-        let mut set_store = self.set_store.write()?;
-        if let Some(set) = set_store.get_mut(&key) {
-            Ok(set.remove(&value))
-        } else {
-            Ok(false)
+    #[test]
+    fn test_zset_rank_ties() {
+        let dcs = DCS::new();
+        for member in ["c", "a", "b"] {
+            dcs.zset_add("zset1".to_string(), 1.0, member.to_string())
+                .unwrap();
         }
+        assert_eq!(dcs.zset_rank("zset1", "a").unwrap(), Some(0));
+        assert_eq!(dcs.zset_rank("zset1", "c").unwrap(), Some(2));
+        assert_eq!(dcs.zset_rev_rank("zset1", "c").unwrap(), Some(0));
     }
 
-    pub fn zset_add(
-        &self,
-        key: String,
-        score: f64,
-        value: String,
-    ) -> Result<(), PoisonError<RwLockWriteGuard<HashMap<String, BTreeMap<String, f64>>>>> {
-        let mut zset_store = self.zset_store.write()?;
-        let zset = zset_store.entry(key).or_insert_with(BTreeMap::new);
-        zset.insert(value, score);
-        Ok(())
+    #[test]
+    fn test_zset_incr_by() {
+        let dcs = DCS::new();
+        assert_eq!(
+            dcs.zset_incr_by("zset1".to_string(), 2.5, "value1".to_string())
+                .unwrap(),
+            2.5
+        );
+        assert_eq!(
+            dcs.zset_incr_by("zset1".to_string(), -1.0, "value1".to_string())
+                .unwrap(),
+            1.5
+        );
+        assert_eq!(dcs.zset_score("zset1", "value1").unwrap(), Some(1.5));
     }
 
-    pub fn zset_score(
-        &self,
-        key: &str,
-        value: &str,
-    ) -> Result<Option<f64>, PoisonError<RwLockReadGuard<HashMap<String, BTreeMap<String, f64>>>>>
-    {
-        let zset_store = self.zset_store.read()?;
-        if let Some(zset) = zset_store.get(key) {
-            Ok(zset.get(value).cloned())
-        } else {
-            Ok(None)
-        }
+    #[test]
+    fn test_zset_incr_by_nan() {
+        let dcs = DCS::new();
+        dcs.zset_add("zset1".to_string(), f64::INFINITY, "value1".to_string())
+            .unwrap();
+        assert!(matches!(
+            dcs.zset_incr_by("zset1".to_string(), f64::NEG_INFINITY, "value1".to_string()),
+            Err(DcsError::InvalidScore)
+        ));
+        assert_eq!(
+            dcs.zset_score("zset1", "value1").unwrap(),
+            Some(f64::INFINITY)
+        );
+        assert!(matches!(
+            dcs.zset_incr_by("zset2".to_string(), f64::NAN, "value1".to_string()),
+            Err(DcsError::InvalidScore)
+        ));
+        assert!(!dcs.exists("zset2").unwrap());
     }
 
-    pub fn zset_remove(
-        &self,
-        key: String,
-        value: String,
-    ) -> Result<bool, PoisonError<RwLockWriteGuard<HashMap<String, BTreeMap<String, f64>>>>> {
-        let mut zset_store = self.zset_store.write()?;
-        if let Some(zset) = zset_store.get_mut(&key) {
-            Ok(zset.remove(&value).is_some())
-        } else {
-            Ok(false)
-        }
+    #[test]
+    fn test_zset_add_nan() {
+        let dcs = DCS::new();
+        assert!(matches!(
+            dcs.zset_add("zset1".to_string(), f64::NAN, "value1".to_string()),
+            Err(DcsError::InvalidScore)
+        ));
+        assert!(!dcs.exists("zset1").unwrap());
+        dcs.zset_add("zset1".to_string(), 1.0, "value1".to_string())
+            .unwrap();
+        assert!(matches!(
+            dcs.zset_add_opts(
+                "zset1".to_string(),
+                f64::NAN,
+                "value1".to_string(),
+                ZAddOpts::default()
+            ),
+            Err(DcsError::InvalidScore)
+        ));
+        assert_eq!(dcs.zset_score("zset1", "value1").unwrap(), Some(1.0));
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::DCS;
 
     #[test]
-    fn test_set_get() {
+    fn test_zset_card_count() {
         let dcs = DCS::new();
-        dcs.set("key1".to_string(), "value1".to_string()).unwrap();
-        assert_eq!(dcs.get("key1").unwrap(), Some("value1".to_string()));
+        assert_eq!(dcs.zset_card("board").unwrap(), 0);
+        leaderboard(&dcs);
+        assert_eq!(dcs.zset_card("board").unwrap(), 5);
+        assert_eq!(dcs.zset_count("board", 20.0, 40.0).unwrap(), 3);
+        assert_eq!(dcs.zset_count("board", 20.5, 39.5).unwrap(), 1);
+        assert_eq!(dcs.zset_count("board", f64::NEG_INFINITY, 20.0).unwrap(), 2);
+        assert_eq!(dcs.zset_count("board", 40.0, f64::INFINITY).unwrap(), 2);
+        assert_eq!(dcs.zset_count("missing", 0.0, 1.0).unwrap(), 0);
     }
 
     #[test]
-    fn test_get_nonexistent() {
+    fn test_zset_range_by_score() {
         let dcs = DCS::new();
-        assert_eq!(dcs.get("nonexistent_key").unwrap(), None);
+        leaderboard(&dcs);
+        assert_eq!(
+            dcs.zset_range_by_score("board", 15.0, 35.0, true, None)
+                .unwrap(),
+            vec![
+                ("carol".to_string(), Some(20.0)),
+                ("alice".to_string(), Some(30.0)),
+            ]
+        );
+        assert_eq!(
+            dcs.zset_range_by_score("board", f64::NEG_INFINITY, f64::INFINITY, false, None)
+                .unwrap()
+                .len(),
+            5
+        );
+        assert!(dcs
+            .zset_range_by_score("board", 35.0, 15.0, false, None)
+            .unwrap()
+            .is_empty());
+        dcs.zset_add("board".to_string(), 20.0, "ann".to_string())
+            .unwrap();
+        assert_eq!(
+            dcs.zset_range_by_score("board", 20.0, 20.0, false, None)
+                .unwrap(),
+            vec![("ann".to_string(), None), ("carol".to_string(), None)]
+        );
+        assert!(dcs
+            .zset_range_by_score("missing", f64::NEG_INFINITY, f64::INFINITY, false, None)
+            .unwrap()
+            .is_empty());
     }
 
     #[test]
-    fn test_list_push_pop() {
+    fn test_zset_range_by_score_limit() {
         let dcs = DCS::new();
-        dcs.list_push("list1".to_string(), "value1".to_string())
-            .unwrap();
-        dcs.list_push("list1".to_string(), "value2".to_string())
-            .unwrap();
-        assert_eq!(dcs.list_pop("list1").unwrap(), Some("value2".to_string()));
-        assert_eq!(dcs.list_pop("list1").unwrap(), Some("value1".to_string()));
-        assert_eq!(dcs.list_pop("list1").unwrap(), None);
+        leaderboard(&dcs);
+        assert_eq!(
+            dcs.zset_range_by_score("board", 10.0, 50.0, false, Some((1, 2)))
+                .unwrap(),
+            vec![("carol".to_string(), None), ("alice".to_string(), None)]
+        );
+        assert!(dcs
+            .zset_range_by_score("board", 10.0, 50.0, false, Some((5, 2)))
+            .unwrap()
+            .is_empty());
     }
 
     #[test]
-    fn test_list_len() {
+    fn test_zset_pop_min() {
         let dcs = DCS::new();
-        assert_eq!(dcs.list_len("list1").unwrap(), 0);
-        dcs.list_push("list1".to_string(), "value1".to_string())
-            .unwrap();
-        dcs.list_push("list1".to_string(), "value2".to_string())
-            .unwrap();
-        assert_eq!(dcs.list_len("list1").unwrap(), 2);
+        leaderboard(&dcs);
+        let mut popped = Vec::new();
+        while let Some((_, score)) = dcs.zset_pop_min("board", 1).unwrap().pop() {
+            popped.push(score);
+        }
+        assert_eq!(popped, vec![10.0, 20.0, 30.0, 40.0, 50.0]);
+        assert_eq!(dcs.zset_card("board").unwrap(), 0);
     }
 
     #[test]
-    fn test_list_push_multi() {
+    fn test_zset_pop_max() {
         let dcs = DCS::new();
-        let values = vec![
-            "value1".to_string(),
-            "value2".to_string(),
-            "value3".to_string(),
-        ];
-        dcs.list_push_multi("list1".to_string(), values).unwrap();
-        assert_eq!(dcs.list_len("list1").unwrap(), 3);
+        leaderboard(&dcs);
+        assert_eq!(
+            dcs.zset_pop_max("board", 2).unwrap(),
+            vec![("dave".to_string(), 50.0), ("erin".to_string(), 40.0)]
+        );
+        assert_eq!(dcs.zset_pop_max("board", 10).unwrap().len(), 3);
+        assert!(dcs.zset_pop_max("missing", 1).unwrap().is_empty());
     }
 
     #[test]
-    fn test_hash_set_get() {
+    fn test_zset_remove_range_by_score() {
         let dcs = DCS::new();
-        dcs.hash_set(
-            "hash1".to_string(),
-            "field1".to_string(),
-            "value1".to_string(),
-        )
-        .unwrap();
+        leaderboard(&dcs);
         assert_eq!(
-            dcs.hash_get("hash1", "field1").unwrap(),
-            Some("value1".to_string())
+            dcs.zset_remove_range_by_score("board", 20.0, 40.0).unwrap(),
+            3
+        );
+        let left: Vec<String> = dcs
+            .zset_range("board", 0, -1, false)
+            .unwrap()
+            .into_iter()
+            .map(|(member, _)| member)
+            .collect();
+        assert_eq!(left, vec!["bob", "dave"]);
+        assert_eq!(
+            dcs.zset_remove_range_by_score("board", 20.0, 40.0).unwrap(),
+            0
         );
     }
 
     #[test]
-    fn test_hash_del() {
+    fn test_zset_remove_range_by_rank() {
         let dcs = DCS::new();
-        dcs.hash_set(
-            "hash1".to_string(),
-            "field1".to_string(),
-            "value1".to_string(),
-        )
-        .unwrap();
+        leaderboard(&dcs);
+        assert_eq!(dcs.zset_remove_range_by_rank("board", 0, -4).unwrap(), 2);
+        let left: Vec<String> = dcs
+            .zset_range("board", 0, -1, false)
+            .unwrap()
+            .into_iter()
+            .map(|(member, _)| member)
+            .collect();
+        assert_eq!(left, vec!["alice", "erin", "dave"]);
+        assert_eq!(dcs.zset_remove_range_by_rank("missing", 0, -1).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_zset_score_multi() {
+        let dcs = DCS::new();
+        leaderboard(&dcs);
+        let members = vec!["bob".to_string(), "zoe".to_string(), "dave".to_string()];
         assert_eq!(
-            dcs.hash_del("hash1".to_string(), "field1".to_string())
-                .unwrap(),
-            true
+            dcs.zset_score_multi("board", &members).unwrap(),
+            vec![Some(10.0), None, Some(50.0)]
+        );
+        assert_eq!(
+            dcs.zset_score_multi("missing", &members).unwrap(),
+            vec![None; 3]
         );
-        assert_eq!(dcs.hash_get("hash1", "field1").unwrap(), None);
     }
 
     #[test]
-    fn test_set_add_is_member() {
+    fn test_zset_add_opts_nx_xx() {
         let dcs = DCS::new();
-        dcs.set_add("set1".to_string(), "value1".to_string())
-            .unwrap();
-        assert_eq!(dcs.set_is_member("set1", "value1").unwrap(), true);
-        assert_eq!(dcs.set_is_member("set1", "value2").unwrap(), false);
+        leaderboard(&dcs);
+        let nx = ZAddOpts {
+            nx: true,
+            ..ZAddOpts::default()
+        };
+        let xx = ZAddOpts {
+            xx: true,
+            ..ZAddOpts::default()
+        };
+        assert!(!dcs
+            .zset_add_opts("board".to_string(), 99.0, "bob".to_string(), nx)
+            .unwrap());
+        assert!(dcs
+            .zset_add_opts("board".to_string(), 5.0, "zoe".to_string(), nx)
+            .unwrap());
+        assert!(!dcs
+            .zset_add_opts("board".to_string(), 5.0, "yan".to_string(), xx)
+            .unwrap());
+        assert!(dcs
+            .zset_add_opts("board".to_string(), 15.0, "bob".to_string(), xx)
+            .unwrap());
+        assert_eq!(dcs.zset_score("board", "bob").unwrap(), Some(15.0));
+        assert_eq!(dcs.zset_score("board", "yan").unwrap(), None);
+        assert!(!dcs
+            .zset_add_opts("other".to_string(), 1.0, "a".to_string(), xx)
+            .unwrap());
+        assert!(!dcs.exists("other").unwrap());
     }
 
     #[test]
-    fn test_set_remove() {
+    fn test_zset_add_opts_gt_lt() {
         let dcs = DCS::new();
-        dcs.set_add("set1".to_string(), "value1".to_string())
+        leaderboard(&dcs);
+        let gt = ZAddOpts {
+            gt: true,
+            ..ZAddOpts::default()
+        };
+        let lt = ZAddOpts {
+            lt: true,
+            ..ZAddOpts::default()
+        };
+        assert!(!dcs
+            .zset_add_opts("board".to_string(), 5.0, "bob".to_string(), gt)
+            .unwrap());
+        assert_eq!(dcs.zset_score("board", "bob").unwrap(), Some(10.0));
+        assert!(dcs
+            .zset_add_opts("board".to_string(), 60.0, "bob".to_string(), gt)
+            .unwrap());
+        assert_eq!(dcs.zset_score("board", "bob").unwrap(), Some(60.0));
+        assert!(!dcs
+            .zset_add_opts("board".to_string(), 70.0, "dave".to_string(), lt)
+            .unwrap());
+        assert!(dcs
+            .zset_add_opts("board".to_string(), 1.0, "dave".to_string(), lt)
+            .unwrap());
+        assert_eq!(dcs.zset_score("board", "dave").unwrap(), Some(1.0));
+        assert!(dcs
+            .zset_add_opts("board".to_string(), 7.0, "zoe".to_string(), gt)
+            .unwrap());
+    }
+
+    #[test]
+    fn test_zset_union_store_weighted() {
+        let dcs = DCS::new();
+        dcs.zset_add("a".to_string(), 1.0, "x".to_string()).unwrap();
+        dcs.zset_add("a".to_string(), 2.0, "y".to_string()).unwrap();
+        dcs.zset_add("b".to_string(), 10.0, "y".to_string())
             .unwrap();
+        dcs.zset_add("b".to_string(), 20.0, "z".to_string())
+            .unwrap();
+        let keys = vec!["a".to_string(), "b".to_string()];
+        let weights = [2.0, 0.5];
         assert_eq!(
-            dcs.set_remove("set1".to_string(), "value1".to_string())
+            dcs.zset_union_store("out".to_string(), &keys, Some(&weights))
                 .unwrap(),
-            true
+            3
         );
-        assert_eq!(dcs.set_is_member("set1", "value1").unwrap(), false);
+        assert_eq!(
+            dcs.zset_range("out", 0, -1, true).unwrap(),
+            vec![
+                ("x".to_string(), Some(2.0)),
+                ("y".to_string(), Some(9.0)),
+                ("z".to_string(), Some(10.0)),
+            ]
+        );
+        assert!(matches!(
+            dcs.zset_union_store("out".to_string(), &keys, Some(&[1.0])),
+            Err(DcsError::WeightsMismatch)
+        ));
     }
 
     #[test]
-    fn test_zset_add_score() {
+    fn test_zset_inter_store() {
         let dcs = DCS::new();
-        dcs.zset_add("zset1".to_string(), 1.0, "value1".to_string())
+        leaderboard(&dcs);
+        dcs.zset_add("other".to_string(), 1.0, "bob".to_string())
             .unwrap();
-        assert_eq!(dcs.zset_score("zset1", "value1").unwrap(), Some(1.0));
-        assert_eq!(dcs.zset_score("zset1", "value2").unwrap(), None);
+        dcs.zset_add("other".to_string(), 2.0, "zoe".to_string())
+            .unwrap();
+        dcs.zset_add("out".to_string(), 1.0, "stale".to_string())
+            .unwrap();
+        let keys = vec!["board".to_string(), "other".to_string()];
+        assert_eq!(
+            dcs.zset_inter_store("out".to_string(), &keys, None)
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            dcs.zset_range("out", 0, -1, true).unwrap(),
+            vec![("bob".to_string(), Some(11.0))]
+        );
+        let keys = vec!["board".to_string(), "missing".to_string()];
+        assert_eq!(
+            dcs.zset_inter_store("out".to_string(), &keys, None)
+                .unwrap(),
+            0
+        );
+        assert!(!dcs.exists("out").unwrap());
     }
 
     #[test]
-    fn test_zset_remove() {
+    fn test_compare_and_set() {
         let dcs = DCS::new();
-        dcs.zset_add("zset1".to_string(), 1.0, "value1".to_string())
-            .unwrap();
+        let (value, version) = dcs.watch_get("k").unwrap();
+        assert_eq!((value, version), (None, 0));
+        assert!(dcs
+            .compare_and_set("k".to_string(), "1".to_string(), version)
+            .unwrap());
+        let (value, version) = dcs.watch_get("k").unwrap();
+        assert_eq!(value, Some("1".to_string()));
+        assert_ne!(version, 0);
+        assert!(dcs
+            .compare_and_set("k".to_string(), "2".to_string(), version)
+            .unwrap());
+        assert_eq!(dcs.get("k").unwrap(), Some("2".to_string()));
+    }
+
+    #[test]
+    fn test_compare_and_set_stale_version() {
+        let dcs = DCS::new();
+        dcs.set("k".to_string(), "1".to_string()).unwrap();
+        let (_, version) = dcs.watch_get("k").unwrap();
+        dcs.incr("k".to_string()).unwrap();
+        assert!(!dcs
+            .compare_and_set("k".to_string(), "10".to_string(), version)
+            .unwrap());
+        assert_eq!(dcs.get("k").unwrap(), Some("2".to_string()));
+
+        // Writing the same value back still counts as a write.
+        let (_, version) = dcs.watch_get("k").unwrap();
+        dcs.set("k".to_string(), "2".to_string()).unwrap();
+        assert!(!dcs
+            .compare_and_set("k".to_string(), "10".to_string(), version)
+            .unwrap());
+
+        let (_, version) = dcs.watch_get("k").unwrap();
+        dcs.del("k").unwrap();
+        assert!(!dcs
+            .compare_and_set("k".to_string(), "10".to_string(), version)
+            .unwrap());
+        assert_eq!(dcs.get("k").unwrap(), None);
+    }
+
+    #[test]
+    fn test_sharded_concurrent_writers() {
+        let dcs = Arc::new(DCS::with_shards(8));
+        let handles: Vec<_> = (0..8)
+            .map(|t| {
+                let dcs = Arc::clone(&dcs);
+                thread::spawn(move || {
+                    for i in 0..200 {
+                        let key = format!("t{}:{}", t, i);
+                        dcs.set(key.clone(), i.to_string()).unwrap();
+                        dcs.incr(key.clone()).unwrap();
+                        dcs.list_push(format!("log{}", t), key).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(dcs.db_size().unwrap(), 8 * 200 + 8);
+        for t in 0..8 {
+            assert_eq!(dcs.list_len(&format!("log{}", t)).unwrap(), 200);
+            assert_eq!(
+                dcs.get(&format!("t{}:199", t)).unwrap(),
+                Some("200".to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn test_sharded_cross_shard_commands() {
+        let dcs = DCS::with_shards(4);
+        let keys: Vec<String> = (0..6).map(|i| format!("set{}", i)).collect();
+        for key in &keys {
+            add_members(&dcs, key, &["shared", key]);
+        }
+        assert_eq!(sorted(dcs.set_intersect(&keys).unwrap()), vec!["shared"]);
+        assert_eq!(dcs.set_union_store("all".to_string(), &keys).unwrap(), 7);
+        assert!(dcs.rename("all", "everything".to_string()).unwrap());
+        assert_eq!(dcs.set_card("everything").unwrap(), 7);
         assert_eq!(
-            dcs.zset_remove("zset1".to_string(), "value1".to_string())
-                .unwrap(),
-            true
+            sorted(dcs.keys("set*").unwrap().into_iter().collect()),
+            keys
         );
-        assert_eq!(dcs.zset_score("zset1", "value1").unwrap(), None);
     }
 }
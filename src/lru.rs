@@ -0,0 +1,106 @@
+use std::collections::{BTreeMap, HashMap};
+
+/// Approximate sizes and recency of string keys, for evicting the least recently
+/// used ones once they take up more than `max_bytes`. A key's size is the length
+/// of the key plus that of its value.
+#[derive(Debug, Clone)]
+pub(crate) struct Lru {
+    max_bytes: usize,
+    used: usize,
+    /// Incremented on every access, so a lower tick means less recently used.
+    tick: u64,
+    /// Size and tick of the last access of every key.
+    entries: HashMap<String, (usize, u64)>,
+    by_tick: BTreeMap<u64, String>,
+}
+
+impl Lru {
+    pub(crate) fn new(max_bytes: usize) -> Self {
+        Lru {
+            max_bytes,
+            used: 0,
+            tick: 0,
+            entries: HashMap::new(),
+            by_tick: BTreeMap::new(),
+        }
+    }
+
+    pub(crate) fn max_bytes(&self) -> usize {
+        self.max_bytes
+    }
+
+    pub(crate) fn over_budget(&self) -> bool {
+        self.used > self.max_bytes
+    }
+
+    /// Marks `key` as just used, if it is tracked.
+    pub(crate) fn touch(&mut self, key: &str) {
+        if let Some((size, _)) = self.entries.get(key) {
+            let size = *size;
+            self.record(key, size);
+        }
+    }
+
+    /// Records that `key` now takes up `size` bytes and was just used.
+    pub(crate) fn record(&mut self, key: &str, size: usize) {
+        self.remove(key);
+        self.tick += 1;
+        self.used += size;
+        self.entries.insert(key.to_string(), (size, self.tick));
+        self.by_tick.insert(self.tick, key.to_string());
+    }
+
+    pub(crate) fn remove(&mut self, key: &str) {
+        if let Some((size, tick)) = self.entries.remove(key) {
+            self.used -= size;
+            self.by_tick.remove(&tick);
+        }
+    }
+
+    /// Moves the entry of `from` to `to`, keeping its recency and adjusting its
+    /// size for the new key.
+    pub(crate) fn rename(&mut self, from: &str, to: &str) {
+        let Some((size, _)) = self.entries.get(from) else {
+            return;
+        };
+        let size = size - from.len() + to.len();
+        self.remove(from);
+        self.record(to, size);
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.used = 0;
+        self.entries.clear();
+        self.by_tick.clear();
+    }
+
+    /// Returns the least recently used key other than `keep`.
+    pub(crate) fn oldest_except(&self, keep: &str) -> Option<String> {
+        self.by_tick.values().find(|key| *key != keep).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Lru;
+
+    #[test]
+    fn test_lru_order_and_accounting() {
+        let mut lru = Lru::new(10);
+        lru.record("a", 4);
+        lru.record("b", 4);
+        lru.record("c", 4);
+        assert!(lru.over_budget());
+        lru.touch("a");
+        assert_eq!(lru.oldest_except("x"), Some("b".to_string()));
+        assert_eq!(lru.oldest_except("b"), Some("c".to_string()));
+        lru.remove("b");
+        assert!(!lru.over_budget());
+        lru.rename("c", "cc");
+        assert_eq!(lru.oldest_except("a"), Some("cc".to_string()));
+        lru.record("d", 1);
+        assert!(!lru.over_budget());
+        lru.record("e", 1);
+        assert!(lru.over_budget());
+    }
+}
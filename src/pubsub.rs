@@ -0,0 +1,65 @@
+use std::sync::mpsc::{self, Receiver};
+
+use crate::error::OrPoisoned;
+use crate::{DcsError, DCS};
+
+impl DCS {
+    /// Subscribes to `channel`. Every message published to it afterwards is
+    /// delivered to the returned receiver, until the receiver is dropped.
+    pub fn subscribe(&self, channel: &str) -> Result<Receiver<String>, DcsError> {
+        let (sender, receiver) = mpsc::channel();
+        let mut channels = self.channels.write().or_poisoned("channels")?;
+        channels
+            .entry(channel.to_string())
+            .or_default()
+            .push(sender);
+        Ok(receiver)
+    }
+
+    /// Sends `message` to every subscriber of `channel` and returns how many got
+    /// it. Subscribers whose receiver was dropped are removed.
+    pub fn publish(&self, channel: &str, message: String) -> Result<usize, DcsError> {
+        let mut channels = self.channels.write().or_poisoned("channels")?;
+        let Some(senders) = channels.get_mut(channel) else {
+            return Ok(0);
+        };
+        senders.retain(|sender| sender.send(message.clone()).is_ok());
+        let delivered = senders.len();
+        if delivered == 0 {
+            channels.remove(channel);
+        }
+        Ok(delivered)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::TryRecvError;
+
+    use crate::DCS;
+
+    #[test]
+    fn test_publish_to_subscribers() {
+        let dcs = DCS::new();
+        let first = dcs.subscribe("news").unwrap();
+        let second = dcs.subscribe("news").unwrap();
+        let other = dcs.subscribe("sports").unwrap();
+        assert_eq!(dcs.publish("news", "hello".to_string()).unwrap(), 2);
+        assert_eq!(first.recv().unwrap(), "hello");
+        assert_eq!(second.recv().unwrap(), "hello");
+        assert_eq!(other.try_recv(), Err(TryRecvError::Empty));
+        assert_eq!(dcs.publish("weather", "rain".to_string()).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_dropped_subscribers_are_pruned() {
+        let dcs = DCS::new();
+        let first = dcs.subscribe("news").unwrap();
+        let second = dcs.subscribe("news").unwrap();
+        drop(first);
+        assert_eq!(dcs.publish("news", "a".to_string()).unwrap(), 1);
+        drop(second);
+        assert_eq!(dcs.publish("news", "b".to_string()).unwrap(), 0);
+        assert!(dcs.channels.read().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,256 @@
+//! The Redis serialization protocol (RESP2), as spoken by Redis clients.
+
+use std::error::Error;
+use std::fmt;
+
+/// Largest bulk string accepted in a request, matching Redis' default.
+const MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+/// Largest number of arguments accepted in a request.
+const MAX_ARGS: usize = 1024 * 1024;
+/// Most characters accepted in a `*` or `$` length, enough for any `i64`.
+const MAX_LENGTH_DIGITS: usize = 20;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RespError {
+    /// The bytes are not a valid request. The connection can't be resynchronized
+    /// and should be closed.
+    Protocol(&'static str),
+}
+
+impl fmt::Display for RespError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RespError::Protocol(reason) => write!(f, "protocol error: {}", reason),
+        }
+    }
+}
+
+impl Error for RespError {}
+
+/// The arguments of a request, and how many bytes of the buffer it took up.
+pub type Command = (Vec<Vec<u8>>, usize);
+
+/// A reply to a client.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Vec<u8>),
+    /// The null bulk string, `$-1`.
+    Null,
+    Array(Vec<Reply>),
+    /// The null array, `*-1`.
+    NullArray,
+}
+
+/// Decodes the request at the start of `buf`, either an array of bulk strings or
+/// an inline command of space-separated words. Returns the arguments and how many
+/// bytes they took up, or `None` if more bytes are needed.
+pub fn parse_command(buf: &[u8]) -> Result<Option<Command>, RespError> {
+    match buf.first() {
+        None => Ok(None),
+        Some(b'*') => parse_array(buf),
+        Some(_) => parse_inline(buf),
+    }
+}
+
+fn parse_array(buf: &[u8]) -> Result<Option<Command>, RespError> {
+    let mut pos = 0;
+    let Some(argc) = parse_length(buf, &mut pos, b'*')? else {
+        return Ok(None);
+    };
+    // A null array carries no command.
+    let Some(argc) = argc.filter(|argc| *argc <= MAX_ARGS) else {
+        return match argc {
+            None => Ok(Some((Vec::new(), pos))),
+            Some(_) => Err(RespError::Protocol("too many arguments")),
+        };
+    };
+    let mut args = Vec::with_capacity(argc.min(64));
+    for _ in 0..argc {
+        if pos >= buf.len() {
+            return Ok(None);
+        }
+        let Some(len) = parse_length(buf, &mut pos, b'$')? else {
+            return Ok(None);
+        };
+        let len = len.ok_or(RespError::Protocol("null bulk string in request"))?;
+        if len > MAX_BULK_LEN {
+            return Err(RespError::Protocol("bulk string too long"));
+        }
+        let Some(arg) = buf.get(pos..pos + len) else {
+            return Ok(None);
+        };
+        match buf.get(pos + len..pos + len + 2) {
+            Some(b"\r\n") => {}
+            Some(_) => return Err(RespError::Protocol("bulk string not terminated by CRLF")),
+            None => return Ok(None),
+        }
+        args.push(arg.to_vec());
+        pos += len + 2;
+    }
+    Ok(Some((args, pos)))
+}
+
+/// Parses a `<prefix><length>\r\n` header at `pos`, advancing past it. A negative
+/// length, used for nulls, is returned as `Some(None)`.
+fn parse_length(
+    buf: &[u8],
+    pos: &mut usize,
+    prefix: u8,
+) -> Result<Option<Option<usize>>, RespError> {
+    let rest = &buf[*pos..];
+    if rest.first() != Some(&prefix) {
+        return Err(RespError::Protocol(if prefix == b'*' {
+            "expected '*'"
+        } else {
+            "expected '$'"
+        }));
+    }
+    let Some(end) = rest.windows(2).position(|window| window == b"\r\n") else {
+        // The prefix, the digits and a `\r` waiting for its `\n`.
+        if rest.len() > MAX_LENGTH_DIGITS + 2 {
+            return Err(RespError::Protocol("invalid length"));
+        }
+        return Ok(None);
+    };
+    let length = std::str::from_utf8(&rest[1..end])
+        .ok()
+        .and_then(|digits| digits.parse::<i64>().ok())
+        .ok_or(RespError::Protocol("invalid length"))?;
+    *pos += end + 2;
+    Ok(Some(usize::try_from(length).ok()))
+}
+
+fn parse_inline(buf: &[u8]) -> Result<Option<Command>, RespError> {
+    let Some(end) = buf.iter().position(|byte| *byte == b'\n') else {
+        if buf.len() > MAX_BULK_LEN {
+            return Err(RespError::Protocol("inline command too long"));
+        }
+        return Ok(None);
+    };
+    let line = buf[..end].strip_suffix(b"\r").unwrap_or(&buf[..end]);
+    let args: Vec<Vec<u8>> = line
+        .split(|byte| *byte == b' ' || *byte == b'\t')
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_vec())
+        .collect();
+    Ok(Some((args, end + 1)))
+}
+
+/// Serializes `reply` in RESP2.
+pub fn encode_reply(reply: &Reply) -> Vec<u8> {
+    let mut out = Vec::new();
+    encode_into(reply, &mut out);
+    out
+}
+
+fn encode_into(reply: &Reply, out: &mut Vec<u8>) {
+    match reply {
+        Reply::Simple(text) => push_line(out, b'+', &strip_newlines(text)),
+        Reply::Error(text) => push_line(out, b'-', &strip_newlines(text)),
+        Reply::Integer(value) => push_line(out, b':', &value.to_string()),
+        Reply::Bulk(bytes) => {
+            push_line(out, b'$', &bytes.len().to_string());
+            out.extend_from_slice(bytes);
+            out.extend_from_slice(b"\r\n");
+        }
+        Reply::Null => out.extend_from_slice(b"$-1\r\n"),
+        Reply::Array(items) => {
+            push_line(out, b'*', &items.len().to_string());
+            for item in items {
+                encode_into(item, out);
+            }
+        }
+        Reply::NullArray => out.extend_from_slice(b"*-1\r\n"),
+    }
+}
+
+fn push_line(out: &mut Vec<u8>, prefix: u8, line: &str) {
+    out.push(prefix);
+    out.extend_from_slice(line.as_bytes());
+    out.extend_from_slice(b"\r\n");
+}
+
+/// Simple strings and errors can't contain line breaks, they would end the reply.
+fn strip_newlines(text: &str) -> String {
+    text.replace(['\r', '\n'], " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode_reply, parse_command, Reply, RespError};
+
+    #[test]
+    fn test_parse_set_command() {
+        let buf = b"*3\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$3\r\nbar\r\n";
+        let (args, len) = parse_command(buf).unwrap().unwrap();
+        assert_eq!(
+            args,
+            vec![b"SET".to_vec(), b"foo".to_vec(), b"bar".to_vec()]
+        );
+        assert_eq!(len, buf.len());
+    }
+
+    #[test]
+    fn test_parse_incomplete_and_pipelined() {
+        let buf = b"*3\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$3\r\nbar\r\n*1\r\n$4\r\nPING\r\n";
+        for end in 0..31 {
+            assert_eq!(parse_command(&buf[..end]).unwrap(), None);
+        }
+        let (_, len) = parse_command(buf).unwrap().unwrap();
+        let (args, _) = parse_command(&buf[len..]).unwrap().unwrap();
+        assert_eq!(args, vec![b"PING".to_vec()]);
+    }
+
+    #[test]
+    fn test_parse_inline_command() {
+        let (args, len) = parse_command(b"SET  foo bar\r\nGET").unwrap().unwrap();
+        assert_eq!(
+            args,
+            vec![b"SET".to_vec(), b"foo".to_vec(), b"bar".to_vec()]
+        );
+        assert_eq!(len, 14);
+        assert_eq!(parse_command(b"PING").unwrap(), None);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            parse_command(b"*1\r\n:3\r\n"),
+            Err(RespError::Protocol("expected '$'"))
+        );
+        assert!(parse_command(b"*1\r\n$-1\r\n").is_err());
+        assert!(parse_command(b"*1\r\n$3\r\nfooXY").is_err());
+        assert!(parse_command(b"*x\r\n").is_err());
+        assert_eq!(parse_command(b"*12345678901234567890"), Ok(None));
+        assert_eq!(
+            parse_command(b"*123456789012345678901234"),
+            Err(RespError::Protocol("invalid length"))
+        );
+        assert_eq!(
+            parse_command(&[b"*1\r\n$".as_slice(), &[b'9'; 64]].concat()),
+            Err(RespError::Protocol("invalid length"))
+        );
+    }
+
+    #[test]
+    fn test_encode_replies() {
+        assert_eq!(encode_reply(&Reply::Null), b"$-1\r\n");
+        assert_eq!(encode_reply(&Reply::NullArray), b"*-1\r\n");
+        assert_eq!(encode_reply(&Reply::Simple("OK".to_string())), b"+OK\r\n");
+        assert_eq!(
+            encode_reply(&Reply::Error("ERR bad\r\nthing".to_string())),
+            b"-ERR bad  thing\r\n"
+        );
+        assert_eq!(
+            encode_reply(&Reply::Array(vec![
+                Reply::Integer(-7),
+                Reply::Bulk(b"bar".to_vec()),
+                Reply::Null,
+            ])),
+            b"*3\r\n:-7\r\n$3\r\nbar\r\n$-1\r\n"
+        );
+    }
+}
@@ -0,0 +1,86 @@
+/// Small xorshift64* generator used for random sampling. It is not suitable for
+/// anything security related, but it is fast and reproducible from a seed.
+pub(crate) struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        // xorshift gets stuck on an all-zero state.
+        Rng { state: seed.max(1) }
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Returns a number in `0..n`. `n` must not be zero.
+    pub(crate) fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    /// Moves `count` randomly chosen elements to the front of `items` and returns them.
+    pub(crate) fn sample<'a, T>(&mut self, items: &'a mut [T], count: usize) -> &'a [T] {
+        let count = count.min(items.len());
+        for i in 0..count {
+            let j = i + self.below(items.len() - i);
+            items.swap(i, j);
+        }
+        &items[..count]
+    }
+
+    /// Returns `count` randomly chosen elements of `items`, distinct and at most
+    /// `items.len()` of them if `count` is positive, or `-count` of them drawn
+    /// with replacement if it is negative.
+    pub(crate) fn pick<T: Clone>(&mut self, items: &mut [T], count: i64) -> Vec<T> {
+        if count >= 0 {
+            let count = usize::try_from(count).unwrap_or(usize::MAX);
+            return self.sample(items, count).to_vec();
+        }
+        if items.is_empty() {
+            return Vec::new();
+        }
+        (0..count.unsigned_abs())
+            .map(|_| items[self.below(items.len())].clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Rng;
+
+    #[test]
+    fn test_same_seed_same_sequence() {
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(42);
+        for _ in 0..10 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+    }
+
+    #[test]
+    fn test_sample_distinct() {
+        let mut rng = Rng::new(7);
+        let mut items: Vec<u32> = (0..10).collect();
+        let mut sample = rng.sample(&mut items, 4).to_vec();
+        sample.sort();
+        sample.dedup();
+        assert_eq!(sample.len(), 4);
+        assert_eq!(rng.sample(&mut items, 20).len(), 10);
+    }
+
+    #[test]
+    fn test_pick_with_replacement() {
+        let mut rng = Rng::new(7);
+        let mut items = vec!['a', 'b'];
+        let picked = rng.pick(&mut items, -5);
+        assert_eq!(picked.len(), 5);
+        assert!(picked.iter().all(|item| items.contains(item)));
+        assert_eq!(rng.pick(&mut items, 5).len(), 2);
+        assert!(rng.pick::<char>(&mut [], -3).is_empty());
+    }
+}
@@ -0,0 +1,81 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+/// Picks the shard of each key, for the stores of a `DCS` built with
+/// [`DcsBuilder::router`](crate::DcsBuilder::router).
+///
+/// Keys routed to the same shard contend for the same lock, but commands over
+/// several keys, like `mget` or `rename`, then only lock that one shard.
+pub trait ShardRouter: Send + Sync {
+    /// Returns the shard of `key`, in `0..shard_count`. Larger values wrap
+    /// around. Must always return the same shard for the same key.
+    fn shard(&self, key: &str, shard_count: usize) -> usize;
+}
+
+/// Routes keys by a hash of the whole key, spreading them evenly.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HashRouter;
+
+impl ShardRouter for HashRouter {
+    fn shard(&self, key: &str, shard_count: usize) -> usize {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        (hasher.finish() % shard_count as u64) as usize
+    }
+}
+
+/// Routes keys by a hash of their hash tag, like Redis Cluster: the part
+/// between the first `{` and the next `}`, if it isn't empty, so that
+/// `{user:1}.name` and `{user:1}.email` share a shard. Keys without a tag are
+/// hashed whole.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HashTagRouter;
+
+impl ShardRouter for HashTagRouter {
+    fn shard(&self, key: &str, shard_count: usize) -> usize {
+        HashRouter.shard(hash_tag(key).unwrap_or(key), shard_count)
+    }
+}
+
+fn hash_tag(key: &str) -> Option<&str> {
+    let start = key.find('{')? + 1;
+    let len = key[start..].find('}')?;
+    (len > 0).then(|| &key[start..start + len])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hash_tag, HashTagRouter, ShardRouter};
+    use crate::DCS;
+
+    #[test]
+    fn test_hash_tag_colocates_keys() {
+        assert_eq!(hash_tag("{user:1}.name"), Some("user:1"));
+        assert_eq!(hash_tag("a{b}{c}"), Some("b"));
+        assert_eq!(hash_tag("{}.name"), None);
+        assert_eq!(hash_tag("plain"), None);
+        let router = HashTagRouter;
+        assert_eq!(
+            router.shard("{user:1}.name", 64),
+            router.shard("{user:1}.email", 64)
+        );
+        assert_eq!(router.shard("plain", 64), router.shard("plain", 64));
+
+        let dcs = DCS::builder()
+            .shards(64)
+            .router(HashTagRouter)
+            .build()
+            .unwrap();
+        dcs.set("{user:1}.name".to_string(), "ann".to_string())
+            .unwrap();
+        dcs.set("{user:1}.email".to_string(), "ann@example.com".to_string())
+            .unwrap();
+        assert!(dcs
+            .rename("{user:1}.email", "{user:1}.mail".to_string())
+            .unwrap());
+        assert_eq!(
+            dcs.mget(&["{user:1}.name".to_string(), "{user:1}.mail".to_string()])
+                .unwrap(),
+            vec![Some("ann".to_string()), Some("ann@example.com".to_string())]
+        );
+    }
+}
@@ -0,0 +1,366 @@
+//! A TCP server speaking RESP2, so Redis clients can talk to a `DCS`.
+
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::resp::{encode_reply, parse_command, Reply};
+use crate::{DcsError, KeyType, DCS};
+
+/// Listens on `addr` and serves Redis clients until accepting fails. Each
+/// connection gets its own thread.
+pub fn serve(dcs: Arc<DCS>, addr: &str) -> Result<(), DcsError> {
+    serve_listener(dcs, TcpListener::bind(addr)?)
+}
+
+impl DCS {
+    /// Listens on `addr` and serves Redis clients from this store, like
+    /// [`serve`].
+    pub fn serve(self: Arc<Self>, addr: SocketAddr) -> Result<(), DcsError> {
+        serve_listener(self, TcpListener::bind(addr)?)
+    }
+}
+
+/// Like `serve`, for a listener that is already bound.
+pub fn serve_listener(dcs: Arc<DCS>, listener: TcpListener) -> Result<(), DcsError> {
+    loop {
+        let (stream, _) = listener.accept()?;
+        let dcs = Arc::clone(&dcs);
+        thread::spawn(move || {
+            // The client going away is the usual way for this to end.
+            let _ = handle_connection(&dcs, stream);
+        });
+    }
+}
+
+fn handle_connection(dcs: &DCS, mut stream: TcpStream) -> io::Result<()> {
+    let mut buf = Vec::new();
+    let mut chunk = [0; 4096];
+    loop {
+        let read = stream.read(&mut chunk)?;
+        if read == 0 {
+            return Ok(());
+        }
+        buf.extend_from_slice(&chunk[..read]);
+        // Answer every complete command received so far in one write, which keeps
+        // pipelined requests cheap.
+        let mut out = Vec::new();
+        let mut consumed = 0;
+        loop {
+            match parse_command(&buf[consumed..]) {
+                Ok(Some((args, len))) => {
+                    consumed += len;
+                    if !args.is_empty() {
+                        out.extend(encode_reply(&execute(dcs, args)));
+                    }
+                }
+                Ok(None) => break,
+                Err(err) => {
+                    out.extend(encode_reply(&Reply::Error(format!("ERR {}", err))));
+                    return stream.write_all(&out);
+                }
+            }
+        }
+        buf.drain(..consumed);
+        stream.write_all(&out)?;
+    }
+}
+
+enum CommandError {
+    Dcs(DcsError),
+    /// Unknown command, or a known one with the wrong number of arguments.
+    Unknown(String),
+    NotAnInteger,
+    NotAFloat,
+    Syntax,
+    NoSuchKey,
+}
+
+impl From<DcsError> for CommandError {
+    fn from(err: DcsError) -> Self {
+        CommandError::Dcs(err)
+    }
+}
+
+impl CommandError {
+    fn into_reply(self) -> Reply {
+        let message = match self {
+            CommandError::Dcs(DcsError::WrongType { .. }) => {
+                "WRONGTYPE Operation against a key holding the wrong kind of value".to_string()
+            }
+            CommandError::Dcs(DcsError::NotAnInteger { .. }) | CommandError::NotAnInteger => {
+                "ERR value is not an integer or out of range".to_string()
+            }
+            CommandError::Dcs(err) => format!("ERR {}", err),
+            CommandError::Unknown(name) => format!(
+                "ERR unknown command or wrong number of arguments for '{}'",
+                name
+            ),
+            CommandError::NotAFloat => "ERR value is not a valid float".to_string(),
+            CommandError::Syntax => "ERR syntax error".to_string(),
+            CommandError::NoSuchKey => "ERR no such key".to_string(),
+        };
+        Reply::Error(message)
+    }
+}
+
+/// Runs one command and returns its reply. Errors become error replies, so the
+/// connection stays usable.
+fn execute(dcs: &DCS, args: Vec<Vec<u8>>) -> Reply {
+    let Ok(args) = args
+        .into_iter()
+        .map(String::from_utf8)
+        .collect::<Result<Vec<String>, _>>()
+    else {
+        return Reply::Error("ERR arguments must be valid UTF-8".to_string());
+    };
+    let Some((name, args)) = args.split_first() else {
+        return Reply::Error("ERR empty command".to_string());
+    };
+    match dispatch(dcs, &name.to_ascii_uppercase(), args) {
+        Ok(reply) => reply,
+        Err(err) => err.into_reply(),
+    }
+}
+
+fn dispatch(dcs: &DCS, name: &str, args: &[String]) -> Result<Reply, CommandError> {
+    let reply = match (name, args) {
+        ("PING", []) => Reply::Simple("PONG".to_string()),
+        ("PING" | "ECHO", [message]) => bulk(message.clone()),
+
+        ("GET", [key]) => bulk_or_null(dcs.get(key)?),
+        ("SET", [key, value]) => {
+            dcs.set(key.clone(), value.clone())?;
+            ok()
+        }
+        ("SETNX", [key, value]) => int(dcs.set_nx(key.clone(), value.clone())?),
+        ("GETSET", [key, value]) => bulk_or_null(dcs.get_set(key.clone(), value.clone())?),
+        ("APPEND", [key, value]) => int(dcs.append(key.clone(), value.clone())?),
+        ("INCR", [key]) => Reply::Integer(dcs.incr(key.clone())?),
+        ("DECR", [key]) => Reply::Integer(dcs.decr(key.clone())?),
+        ("INCRBY", [key, delta]) => Reply::Integer(dcs.incr_by(key.clone(), parse_int(delta)?)?),
+        ("DECRBY", [key, delta]) => Reply::Integer(dcs.decr_by(key.clone(), parse_int(delta)?)?),
+        ("MSET", pairs) if !pairs.is_empty() && pairs.len().is_multiple_of(2) => {
+            dcs.mset(to_pairs(pairs))?;
+            ok()
+        }
+        ("MGET", keys) if !keys.is_empty() => {
+            Reply::Array(dcs.mget(keys)?.into_iter().map(bulk_or_null).collect())
+        }
+
+        ("DEL", keys) if !keys.is_empty() => int(count(keys, |key| dcs.del(key))?),
+        ("EXISTS", keys) if !keys.is_empty() => int(count(keys, |key| dcs.exists(key))?),
+        ("TYPE", [key]) => {
+            let name = match dcs.type_of(key)?.first() {
+                Some(KeyType::String) => "string",
+                Some(KeyType::List) => "list",
+                Some(KeyType::Hash) => "hash",
+                Some(KeyType::Set) => "set",
+                Some(KeyType::ZSet) => "zset",
+                None => "none",
+            };
+            Reply::Simple(name.to_string())
+        }
+        ("EXPIRE", [key, seconds]) => {
+            int(dcs.expire(key, Duration::from_secs(parse_unsigned(seconds)?))?)
+        }
+        ("PEXPIRE", [key, millis]) => {
+            int(dcs.expire(key, Duration::from_millis(parse_unsigned(millis)?))?)
+        }
+        ("TTL", [key]) => ttl_reply(dcs, key, |ttl| ttl.as_secs_f64().ceil() as i64)?,
+        ("PTTL", [key]) => ttl_reply(dcs, key, |ttl| ttl.as_millis() as i64)?,
+        ("PERSIST", [key]) => int(dcs.persist(key)?),
+        ("KEYS", [pattern]) => strings(dcs.keys(pattern)?),
+        ("DBSIZE", []) => int(dcs.db_size()?),
+        ("FLUSHALL", []) => {
+            dcs.flush_all()?;
+            ok()
+        }
+        ("RENAME", [old_key, new_key]) => {
+            if !dcs.rename(old_key, new_key.clone())? {
+                return Err(CommandError::NoSuchKey);
+            }
+            ok()
+        }
+
+        ("LPUSH", [key, values @ ..]) if !values.is_empty() => {
+            int(dcs.list_push_front_multi(key.clone(), values.to_vec())?)
+        }
+        ("RPUSH", [key, values @ ..]) if !values.is_empty() => {
+            int(dcs.list_push_multi(key.clone(), values.to_vec())?)
+        }
+        ("LPOP", [key]) => bulk_or_null(dcs.list_pop_front(key)?),
+        ("RPOP", [key]) => bulk_or_null(dcs.list_pop(key)?),
+        ("LLEN", [key]) => int(dcs.list_len(key)?),
+        ("LRANGE", [key, start, stop]) => {
+            strings(dcs.list_range(key, parse_int(start)?, parse_int(stop)?)?)
+        }
+        ("LINDEX", [key, index]) => bulk_or_null(dcs.list_index(key, parse_int(index)?)?),
+        ("LSET", [key, index, value]) => {
+            dcs.list_set(key, parse_int(index)?, value.clone())?;
+            ok()
+        }
+        ("LTRIM", [key, start, stop]) => {
+            dcs.list_trim(key, parse_int(start)?, parse_int(stop)?)?;
+            ok()
+        }
+        ("LREM", [key, count, value]) => int(dcs.list_remove(key, parse_int(count)?, value)?),
+
+        ("HSET", [key, pairs @ ..]) if !pairs.is_empty() && pairs.len().is_multiple_of(2) => {
+            int(dcs.hash_set_multi(key.clone(), to_pairs(pairs))?)
+        }
+        ("HSETNX", [key, field, value]) => {
+            int(dcs.hash_set_nx(key.clone(), field.clone(), value.clone())?)
+        }
+        ("HGET", [key, field]) => bulk_or_null(dcs.hash_get(key, field)?),
+        ("HDEL", [key, fields @ ..]) if !fields.is_empty() => {
+            int(dcs.hash_del_multi(key.clone(), fields.to_vec())?)
+        }
+        ("HGETALL", [key]) => Reply::Array(
+            dcs.hash_get_all(key)?
+                .into_iter()
+                .flat_map(|(field, value)| [bulk(field), bulk(value)])
+                .collect(),
+        ),
+        ("HKEYS", [key]) => strings(dcs.hash_keys(key)?),
+        ("HVALS", [key]) => strings(dcs.hash_vals(key)?),
+        ("HLEN", [key]) => int(dcs.hash_len(key)?),
+        ("HEXISTS", [key, field]) => int(dcs.hash_exists(key, field)?),
+        ("HINCRBY", [key, field, delta]) => {
+            Reply::Integer(dcs.hash_incr_by(key.clone(), field.clone(), parse_int(delta)?)?)
+        }
+        ("HMGET", [key, fields @ ..]) if !fields.is_empty() => Reply::Array(
+            dcs.hash_mget(key, fields)?
+                .into_iter()
+                .map(bulk_or_null)
+                .collect(),
+        ),
+
+        ("SADD", [key, members @ ..]) if !members.is_empty() => {
+            int(dcs.set_add_multi(key.clone(), members.to_vec())?)
+        }
+        ("SREM", [key, members @ ..]) if !members.is_empty() => {
+            int(dcs.set_remove_multi(key.clone(), members.to_vec())?)
+        }
+        ("SISMEMBER", [key, member]) => int(dcs.set_is_member(key, member)?),
+        ("SCARD", [key]) => int(dcs.set_card(key)?),
+        ("SMEMBERS", [key]) => strings(dcs.set_members(key)?),
+        ("SINTER", keys) if !keys.is_empty() => strings(dcs.set_intersect(keys)?),
+        ("SUNION", keys) if !keys.is_empty() => strings(dcs.set_union(keys)?),
+        ("SDIFF", keys) if !keys.is_empty() => strings(dcs.set_diff(keys)?),
+
+        ("ZADD", [key, pairs @ ..]) if !pairs.is_empty() && pairs.len().is_multiple_of(2) => {
+            let pairs = pairs
+                .chunks(2)
+                .map(|pair| Ok((parse_float(&pair[0])?, pair[1].clone())))
+                .collect::<Result<Vec<_>, CommandError>>()?;
+            int(dcs.zset_add_multi(key.clone(), pairs)?)
+        }
+        ("ZSCORE", [key, member]) => match dcs.zset_score(key, member)? {
+            Some(score) => bulk(score.to_string()),
+            None => Reply::Null,
+        },
+        ("ZREM", [key, members @ ..]) if !members.is_empty() => {
+            int(dcs.zset_remove_multi(key.clone(), members.to_vec())?)
+        }
+        ("ZCARD", [key]) => int(dcs.zset_card(key)?),
+        ("ZCOUNT", [key, min, max]) => {
+            int(dcs.zset_count(key, parse_float(min)?, parse_float(max)?)?)
+        }
+        ("ZINCRBY", [key, delta, member]) => {
+            let score = dcs.zset_incr_by(key.clone(), parse_float(delta)?, member.clone())?;
+            bulk(score.to_string())
+        }
+        ("ZRANK", [key, member]) => rank_reply(dcs.zset_rank(key, member)?),
+        ("ZREVRANK", [key, member]) => rank_reply(dcs.zset_rev_rank(key, member)?),
+        ("ZRANGE", [key, start, stop, options @ ..]) if options.len() <= 1 => {
+            let with_scores = match options {
+                [] => false,
+                [option] if option.eq_ignore_ascii_case("WITHSCORES") => true,
+                _ => return Err(CommandError::Syntax),
+            };
+            let range = dcs.zset_range(key, parse_int(start)?, parse_int(stop)?, with_scores)?;
+            Reply::Array(
+                range
+                    .into_iter()
+                    .flat_map(|(member, score)| {
+                        std::iter::once(bulk(member)).chain(score.map(|s| bulk(s.to_string())))
+                    })
+                    .collect(),
+            )
+        }
+
+        (name, _) => return Err(CommandError::Unknown(name.to_lowercase())),
+    };
+    Ok(reply)
+}
+
+fn ok() -> Reply {
+    Reply::Simple("OK".to_string())
+}
+
+fn bulk(value: String) -> Reply {
+    Reply::Bulk(value.into_bytes())
+}
+
+fn bulk_or_null(value: Option<String>) -> Reply {
+    value.map_or(Reply::Null, bulk)
+}
+
+fn strings(values: impl IntoIterator<Item = String>) -> Reply {
+    Reply::Array(values.into_iter().map(bulk).collect())
+}
+
+fn int(value: impl TryInto<i64>) -> Reply {
+    Reply::Integer(value.try_into().unwrap_or(i64::MAX))
+}
+
+fn rank_reply(rank: Option<usize>) -> Reply {
+    rank.map_or(Reply::Null, int)
+}
+
+/// Replies -2 if `key` does not exist, -1 if it has no TTL, and otherwise the TTL
+/// converted by `unit`.
+fn ttl_reply(dcs: &DCS, key: &str, unit: impl Fn(Duration) -> i64) -> Result<Reply, CommandError> {
+    let ttl = dcs.ttl(key)?;
+    if !dcs.exists(key)? {
+        return Ok(Reply::Integer(-2));
+    }
+    Ok(Reply::Integer(ttl.map_or(-1, unit)))
+}
+
+fn count(
+    items: &[String],
+    mut f: impl FnMut(&String) -> Result<bool, DcsError>,
+) -> Result<usize, CommandError> {
+    let mut count = 0;
+    for item in items {
+        if f(item)? {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+fn to_pairs(args: &[String]) -> Vec<(String, String)> {
+    args.chunks(2)
+        .map(|pair| (pair[0].clone(), pair[1].clone()))
+        .collect()
+}
+
+fn parse_int(arg: &str) -> Result<i64, CommandError> {
+    arg.parse().map_err(|_| CommandError::NotAnInteger)
+}
+
+fn parse_unsigned(arg: &str) -> Result<u64, CommandError> {
+    arg.parse().map_err(|_| CommandError::NotAnInteger)
+}
+
+fn parse_float(arg: &str) -> Result<f64, CommandError> {
+    match arg.parse::<f64>() {
+        Ok(value) if !value.is_nan() => Ok(value),
+        _ => Err(CommandError::NotAFloat),
+    }
+}
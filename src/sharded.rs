@@ -0,0 +1,461 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::{DcsError, ShardRouter};
+
+pub(crate) use backend::Map;
+use backend::{Locks, ReadGuard, WriteGuard};
+
+/// A map from keys to `V` split into shards with a lock each, picked by a hash
+/// of the key or by a `ShardRouter`, so writes to different keys don't contend unless their shards
+/// collide.
+///
+/// Operations on several keys lock the shards they need in index order, through
+/// `read_keys`/`write_keys`, or all of them with `read_all`/`write_all`.
+///
+/// With the `dashmap` feature the shards are those of a `DashMap`, whose locks
+/// are cheaper and can't be poisoned.
+pub(crate) struct Sharded<V> {
+    locks: Locks<V>,
+    /// What the map holds, for naming it in `DcsError::Poisoned`.
+    name: &'static str,
+    /// Picks the shard of each key instead of the backend's own hash, if set.
+    router: Option<Arc<dyn ShardRouter>>,
+    /// Number of keys across all shards, kept up to date by `Shards` so it can
+    /// be read without locking anything.
+    len: AtomicUsize,
+}
+
+pub(crate) type ReadShards<'a, V> = Shards<'a, V, ReadGuard<'a, V>>;
+pub(crate) type WriteShards<'a, V> = Shards<'a, V, WriteGuard<'a, V>>;
+
+impl<V> Sharded<V> {
+    /// Creates a map with `count` shards, at least one. `DashMap` rounds the
+    /// count up to a power of two, at least two.
+    pub(crate) fn new(
+        name: &'static str,
+        count: usize,
+        router: Option<Arc<dyn ShardRouter>>,
+    ) -> Self {
+        Sharded {
+            locks: Locks::new(count),
+            name,
+            router,
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    pub(crate) fn router(&self) -> Option<Arc<dyn ShardRouter>> {
+        self.router.clone()
+    }
+
+    pub(crate) fn shard_count(&self) -> usize {
+        self.locks.shards().len()
+    }
+
+    /// Returns the number of keys, without taking any lock. Concurrent writes
+    /// may or may not be counted yet.
+    pub(crate) fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    fn index(&self, key: &str) -> usize {
+        match &self.router {
+            Some(router) => {
+                let count = self.locks.shards().len();
+                router.shard(key, count) % count
+            }
+            None => self.locks.index(key),
+        }
+    }
+
+    /// Locks the shard holding `key` for reading.
+    pub(crate) fn read(&self, key: &str) -> Result<ReadShards<'_, V>, DcsError> {
+        let index = self.index(key);
+        let guard = backend::read(&self.locks.shards()[index], self.name)?;
+        Ok(self.one(index, guard))
+    }
+
+    /// Locks the shard holding `key` for writing.
+    pub(crate) fn write(&self, key: &str) -> Result<WriteShards<'_, V>, DcsError> {
+        let index = self.index(key);
+        let guard = backend::write(&self.locks.shards()[index], self.name)?;
+        Ok(self.one(index, guard))
+    }
+
+    /// Locks the shards holding any of `keys` for reading.
+    pub(crate) fn read_keys<K: AsRef<str>>(
+        &self,
+        keys: impl IntoIterator<Item = K>,
+    ) -> Result<ReadShards<'_, V>, DcsError> {
+        self.lock(self.involved(keys), backend::read)
+    }
+
+    /// Locks the shards holding any of `keys` for writing.
+    pub(crate) fn write_keys<K: AsRef<str>>(
+        &self,
+        keys: impl IntoIterator<Item = K>,
+    ) -> Result<WriteShards<'_, V>, DcsError> {
+        self.lock(self.involved(keys), backend::write)
+    }
+
+    pub(crate) fn read_all(&self) -> Result<ReadShards<'_, V>, DcsError> {
+        self.lock(vec![true; self.locks.shards().len()], backend::read)
+    }
+
+    pub(crate) fn write_all(&self) -> Result<WriteShards<'_, V>, DcsError> {
+        self.lock(vec![true; self.locks.shards().len()], backend::write)
+    }
+
+    fn involved<K: AsRef<str>>(&self, keys: impl IntoIterator<Item = K>) -> Vec<bool> {
+        let mut involved = vec![false; self.locks.shards().len()];
+        for key in keys {
+            involved[self.index(key.as_ref())] = true;
+        }
+        involved
+    }
+
+    fn one<G>(&self, index: usize, guard: G) -> Shards<'_, V, G> {
+        Shards {
+            sharded: self,
+            guards: Guards::One(index, guard),
+        }
+    }
+
+    /// Locks the shards flagged in `involved`, in index order.
+    fn lock<'a, G>(
+        &'a self,
+        involved: Vec<bool>,
+        lock: impl Fn(&'a backend::Lock<V>, &str) -> Result<G, DcsError>,
+    ) -> Result<Shards<'a, V, G>, DcsError> {
+        let guards = self
+            .locks
+            .shards()
+            .iter()
+            .zip(involved)
+            .map(|(shard, involved)| involved.then(|| lock(shard, self.name)).transpose())
+            .collect::<Result<_, _>>()?;
+        Ok(Shards {
+            sharded: self,
+            guards: Guards::Many(guards),
+        })
+    }
+}
+
+/// Locks held on some shards of a `Sharded` map, which can be used like the map
+/// itself for the keys they cover. Touching a key whose shard isn't locked is a
+/// bug and panics.
+pub(crate) struct Shards<'a, V, G> {
+    sharded: &'a Sharded<V>,
+    guards: Guards<G>,
+}
+
+enum Guards<G> {
+    /// The lock on a single shard, taken for one key.
+    One(usize, G),
+    /// Locks on the shards whose slot is filled.
+    Many(Vec<Option<G>>),
+}
+
+impl<G> Guards<G> {
+    fn get(&self, index: usize) -> Option<&G> {
+        match self {
+            Guards::One(one, guard) => (*one == index).then_some(guard),
+            Guards::Many(guards) => guards[index].as_ref(),
+        }
+    }
+
+    fn get_mut(&mut self, index: usize) -> Option<&mut G> {
+        match self {
+            Guards::One(one, guard) => (*one == index).then_some(guard),
+            Guards::Many(guards) => guards[index].as_mut(),
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &G> {
+        let (one, many) = match self {
+            Guards::One(_, guard) => (Some(guard), &[][..]),
+            Guards::Many(guards) => (None, &guards[..]),
+        };
+        one.into_iter().chain(many.iter().flatten())
+    }
+
+    fn iter_mut(&mut self) -> impl Iterator<Item = &mut G> {
+        let (one, many) = match self {
+            Guards::One(_, guard) => (Some(guard), &mut [][..]),
+            Guards::Many(guards) => (None, &mut guards[..]),
+        };
+        one.into_iter().chain(many.iter_mut().flatten())
+    }
+}
+
+impl<V, G: Deref<Target = Map<V>>> Shards<'_, V, G> {
+    fn shard(&self, key: &str) -> &Map<V> {
+        self.guards
+            .get(self.sharded.index(key))
+            .expect("shard of key is not locked")
+    }
+
+    pub(crate) fn get(&self, key: &str) -> Option<&V> {
+        self.shard(key).get(key).map(backend::value)
+    }
+
+    pub(crate) fn contains_key(&self, key: &str) -> bool {
+        self.shard(key).contains_key(key)
+    }
+
+    /// Iterates over the entries of the locked shards.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&String, &V)> {
+        self.guards
+            .iter()
+            .flat_map(|shard| shard.iter())
+            .map(|(key, value)| (key, backend::value(value)))
+    }
+
+    pub(crate) fn keys(&self) -> impl Iterator<Item = &String> {
+        self.iter().map(|(key, _)| key)
+    }
+
+    /// Copies the entries of the locked shards into one map.
+    #[cfg(feature = "serde")]
+    pub(crate) fn to_map(&self) -> std::collections::HashMap<String, V>
+    where
+        V: Clone,
+    {
+        self.iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+}
+
+impl<V, G: DerefMut<Target = Map<V>>> Shards<'_, V, G> {
+    fn shard_mut(&mut self, key: &str) -> &mut Map<V> {
+        self.guards
+            .get_mut(self.sharded.index(key))
+            .expect("shard of key is not locked")
+    }
+
+    pub(crate) fn get_mut(&mut self, key: &str) -> Option<&mut V> {
+        self.shard_mut(key).get_mut(key).map(backend::value_mut)
+    }
+
+    pub(crate) fn insert(&mut self, key: String, value: V) -> Option<V> {
+        let previous = self
+            .shard_mut(&key)
+            .insert(key, backend::slot(value))
+            .map(backend::into_value);
+        if previous.is_none() {
+            self.sharded.len.fetch_add(1, Ordering::Relaxed);
+        }
+        previous
+    }
+
+    pub(crate) fn remove(&mut self, key: &str) -> Option<V> {
+        let removed = self.shard_mut(key).remove(key).map(backend::into_value);
+        if removed.is_some() {
+            self.sharded.len.fetch_sub(1, Ordering::Relaxed);
+        }
+        removed
+    }
+
+    /// Returns the value of `key`, inserting the default value if it's missing.
+    pub(crate) fn get_or_default(&mut self, key: String) -> &mut V
+    where
+        V: Default,
+    {
+        let len = &self.sharded.len;
+        let slot = self.shard_mut(&key).entry(key).or_insert_with(|| {
+            len.fetch_add(1, Ordering::Relaxed);
+            backend::slot(V::default())
+        });
+        backend::value_mut(slot)
+    }
+
+    /// Empties the locked shards.
+    pub(crate) fn clear(&mut self) {
+        for shard in self.guards.iter_mut() {
+            self.sharded.len.fetch_sub(shard.len(), Ordering::Relaxed);
+            shard.clear();
+        }
+    }
+
+    /// Empties the locked shards, returning their keys.
+    pub(crate) fn drain_keys(&mut self) -> Vec<String> {
+        let keys: Vec<String> = self
+            .guards
+            .iter_mut()
+            .flat_map(|shard| shard.drain().map(|(key, _)| key))
+            .collect();
+        self.sharded.len.fetch_sub(keys.len(), Ordering::Relaxed);
+        keys
+    }
+}
+
+impl<V, G: DerefMut<Target = Map<V>>> Extend<(String, V)> for Shards<'_, V, G> {
+    fn extend<I: IntoIterator<Item = (String, V)>>(&mut self, entries: I) {
+        for (key, value) in entries {
+            self.insert(key, value);
+        }
+    }
+}
+
+#[cfg(not(feature = "dashmap"))]
+mod backend {
+    use std::collections::HashMap;
+    use std::hash::{DefaultHasher, Hash, Hasher};
+    use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+    use crate::error::OrPoisoned;
+    use crate::DcsError;
+
+    pub(crate) type Map<V> = HashMap<String, V>;
+    pub(crate) type Lock<V> = RwLock<Map<V>>;
+    pub(crate) type ReadGuard<'a, V> = RwLockReadGuard<'a, Map<V>>;
+    pub(crate) type WriteGuard<'a, V> = RwLockWriteGuard<'a, Map<V>>;
+
+    pub(crate) struct Locks<V>(Vec<Lock<V>>);
+
+    impl<V> Locks<V> {
+        pub(crate) fn new(count: usize) -> Self {
+            Locks(
+                (0..count.max(1))
+                    .map(|_| RwLock::new(HashMap::new()))
+                    .collect(),
+            )
+        }
+
+        pub(crate) fn shards(&self) -> &[Lock<V>] {
+            &self.0
+        }
+
+        pub(crate) fn index(&self, key: &str) -> usize {
+            if self.0.len() == 1 {
+                return 0;
+            }
+            let mut hasher = DefaultHasher::new();
+            key.hash(&mut hasher);
+            (hasher.finish() % self.0.len() as u64) as usize
+        }
+    }
+
+    pub(crate) fn read<'a, V>(lock: &'a Lock<V>, name: &str) -> Result<ReadGuard<'a, V>, DcsError> {
+        lock.read().or_poisoned(name)
+    }
+
+    pub(crate) fn write<'a, V>(
+        lock: &'a Lock<V>,
+        name: &str,
+    ) -> Result<WriteGuard<'a, V>, DcsError> {
+        lock.write().or_poisoned(name)
+    }
+
+    pub(crate) fn slot<V>(value: V) -> V {
+        value
+    }
+
+    pub(crate) fn value<V>(slot: &V) -> &V {
+        slot
+    }
+
+    pub(crate) fn value_mut<V>(slot: &mut V) -> &mut V {
+        slot
+    }
+
+    pub(crate) fn into_value<V>(slot: V) -> V {
+        slot
+    }
+}
+
+#[cfg(feature = "dashmap")]
+mod backend {
+    use std::collections::hash_map::RandomState;
+
+    use dashmap::{DashMap, RwLock, RwLockReadGuard, RwLockWriteGuard, SharedValue};
+
+    use crate::DcsError;
+
+    pub(crate) type Map<V> = hashbrown::HashMap<String, SharedValue<V>, RandomState>;
+    pub(crate) type Lock<V> = RwLock<Map<V>>;
+    pub(crate) type ReadGuard<'a, V> = RwLockReadGuard<'a, Map<V>>;
+    pub(crate) type WriteGuard<'a, V> = RwLockWriteGuard<'a, Map<V>>;
+
+    pub(crate) struct Locks<V>(DashMap<String, V>);
+
+    impl<V> Locks<V> {
+        pub(crate) fn new(count: usize) -> Self {
+            Locks(DashMap::with_shard_amount(count.max(2).next_power_of_two()))
+        }
+
+        pub(crate) fn shards(&self) -> &[Lock<V>] {
+            self.0.shards()
+        }
+
+        pub(crate) fn index(&self, key: &str) -> usize {
+            self.0.determine_map(key)
+        }
+    }
+
+    /// Takes `name` like the std backend, but `DashMap` locks can't be poisoned.
+    pub(crate) fn read<'a, V>(
+        lock: &'a Lock<V>,
+        _name: &str,
+    ) -> Result<ReadGuard<'a, V>, DcsError> {
+        Ok(lock.read())
+    }
+
+    pub(crate) fn write<'a, V>(
+        lock: &'a Lock<V>,
+        _name: &str,
+    ) -> Result<WriteGuard<'a, V>, DcsError> {
+        Ok(lock.write())
+    }
+
+    pub(crate) fn slot<V>(value: V) -> SharedValue<V> {
+        SharedValue::new(value)
+    }
+
+    pub(crate) fn value<V>(slot: &SharedValue<V>) -> &V {
+        slot.get()
+    }
+
+    pub(crate) fn value_mut<V>(slot: &mut SharedValue<V>) -> &mut V {
+        slot.get_mut()
+    }
+
+    pub(crate) fn into_value<V>(slot: SharedValue<V>) -> V {
+        slot.into_inner()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::Sharded;
+
+    #[test]
+    fn test_disjoint_keys_do_not_block() {
+        let sharded: Sharded<String> = Sharded::new("strings", 16, None);
+        let first = "key0".to_string();
+        let other = (1..)
+            .map(|i| format!("key{}", i))
+            .find(|key| sharded.index(key) != sharded.index(&first))
+            .unwrap();
+        let held = sharded.write(&first).unwrap();
+        let (done, wait) = mpsc::channel();
+        let unblocked = thread::scope(|scope| {
+            scope.spawn(|| {
+                drop(sharded.write(&other).unwrap());
+                done.send(()).unwrap();
+            });
+            let unblocked = wait.recv_timeout(Duration::from_secs(5)).is_ok();
+            // Let a blocked writer through before the scope joins it.
+            drop(held);
+            unblocked
+        });
+        assert!(unblocked);
+    }
+}
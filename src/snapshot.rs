@@ -0,0 +1,596 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::OrPoisoned;
+use crate::expiry::{from_unix_millis, to_unix_millis};
+use crate::value::StringValue;
+use crate::zset::ZSet;
+use crate::{DcsError, DCS};
+
+/// Bumped whenever the layout of `DcsSnapshot` changes. Older versions are still
+/// read, and migrated on load.
+const VERSION: u32 = 2;
+
+/// Every snapshot file starts with one of these, naming the encoding of the rest.
+const JSON_MAGIC: &[u8; 4] = b"DCSJ";
+const BINCODE_MAGIC: &[u8; 4] = b"DCSB";
+
+/// Encoding used for a snapshot file. `load_snapshot` reads any of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotFormat {
+    Json,
+    #[cfg(feature = "bincode")]
+    Bincode,
+}
+
+impl Default for SnapshotFormat {
+    /// The most compact format compiled in.
+    fn default() -> Self {
+        #[cfg(feature = "bincode")]
+        return SnapshotFormat::Bincode;
+        #[cfg(not(feature = "bincode"))]
+        return SnapshotFormat::Json;
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Envelope<T = DcsSnapshot> {
+    version: u32,
+    data: T,
+}
+
+/// The leading field of every `Envelope`, read first so a snapshot from an
+/// unknown version is reported as such rather than as a decoding failure.
+#[derive(Deserialize)]
+struct EnvelopeHeader {
+    version: u32,
+}
+
+/// `DcsSnapshot` as written at version 1, before binary strings.
+#[derive(Deserialize)]
+struct SnapshotV1 {
+    strings: HashMap<String, String>,
+    lists: HashMap<String, Vec<String>>,
+    hashes: HashMap<String, HashMap<String, String>>,
+    sets: HashMap<String, HashSet<String>>,
+    #[serde(with = "scores")]
+    zsets: HashMap<String, HashMap<String, f64>>,
+    expirations: HashMap<String, u64>,
+}
+
+impl From<SnapshotV1> for DcsSnapshot {
+    fn from(v1: SnapshotV1) -> Self {
+        DcsSnapshot {
+            strings: v1.strings,
+            bytes: HashMap::new(),
+            lists: v1.lists,
+            hashes: v1.hashes,
+            sets: v1.sets,
+            zsets: v1.zsets,
+            expirations: v1.expirations,
+        }
+    }
+}
+
+/// An owned copy of every store, as produced by [`DCS::to_snapshot`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DcsSnapshot {
+    pub strings: HashMap<String, String>,
+    /// Strings set with `set_bytes` that aren't valid UTF-8.
+    pub bytes: HashMap<String, Vec<u8>>,
+    pub lists: HashMap<String, Vec<String>>,
+    pub hashes: HashMap<String, HashMap<String, String>>,
+    pub sets: HashMap<String, HashSet<String>>,
+    /// Member to score maps. NaN scores are rejected by [`DCS::from_snapshot`].
+    #[serde(with = "scores")]
+    pub zsets: HashMap<String, HashMap<String, f64>>,
+    /// TTL deadlines as milliseconds since the Unix epoch, so they keep counting
+    /// down while a snapshot is stored elsewhere.
+    pub expirations: HashMap<String, u64>,
+}
+
+/// Encodes zset scores as numbers, except those JSON has no number for, which
+/// become the strings `"inf"`, `"-inf"` and `"nan"`. Formats that aren't human
+/// readable, like bincode, keep plain `f64`s.
+mod scores {
+    use std::collections::HashMap;
+
+    use serde::de::Error as _;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    type Zsets = HashMap<String, HashMap<String, f64>>;
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(untagged)]
+    enum Score<S> {
+        Number(f64),
+        Named(S),
+    }
+
+    pub(super) fn serialize<Ser: Serializer>(
+        zsets: &Zsets,
+        serializer: Ser,
+    ) -> Result<Ser::Ok, Ser::Error> {
+        if !serializer.is_human_readable() {
+            return zsets.serialize(serializer);
+        }
+        let encoded: HashMap<&String, HashMap<&String, Score<&str>>> = zsets
+            .iter()
+            .map(|(key, members)| {
+                let members = members
+                    .iter()
+                    .map(|(member, score)| (member, encode(*score)))
+                    .collect();
+                (key, members)
+            })
+            .collect();
+        encoded.serialize(serializer)
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Zsets, D::Error> {
+        if !deserializer.is_human_readable() {
+            return Zsets::deserialize(deserializer);
+        }
+        let encoded = HashMap::<String, HashMap<String, Score<String>>>::deserialize(deserializer)?;
+        encoded
+            .into_iter()
+            .map(|(key, members)| {
+                let members = members
+                    .into_iter()
+                    .map(|(member, score)| Ok((member, decode(score).map_err(D::Error::custom)?)))
+                    .collect::<Result<_, D::Error>>()?;
+                Ok((key, members))
+            })
+            .collect()
+    }
+
+    fn encode(score: f64) -> Score<&'static str> {
+        if score.is_nan() {
+            Score::Named("nan")
+        } else if score == f64::INFINITY {
+            Score::Named("inf")
+        } else if score == f64::NEG_INFINITY {
+            Score::Named("-inf")
+        } else {
+            Score::Number(score)
+        }
+    }
+
+    fn decode(score: Score<String>) -> Result<f64, String> {
+        match score {
+            Score::Number(score) => Ok(score),
+            Score::Named(name) => match name.as_str() {
+                "nan" => Ok(f64::NAN),
+                "inf" => Ok(f64::INFINITY),
+                "-inf" => Ok(f64::NEG_INFINITY),
+                _ => Err(format!("invalid score {:?}", name)),
+            },
+        }
+    }
+}
+
+impl DCS {
+    /// Writes every store and TTL to `path` in the default `SnapshotFormat`.
+    pub fn save_snapshot<P: AsRef<Path>>(&self, path: P) -> Result<(), DcsError> {
+        self.save_snapshot_as(path, SnapshotFormat::default())
+    }
+
+    /// Writes every store and TTL to `path`. The snapshot goes to a temporary file
+    /// first and is then renamed over `path`, so a crash mid-write never leaves a
+    /// truncated snapshot behind.
+    pub fn save_snapshot_as<P: AsRef<Path>>(
+        &self,
+        path: P,
+        format: SnapshotFormat,
+    ) -> Result<(), DcsError> {
+        let path = path.as_ref();
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        self.save_as(&mut writer, format)?;
+        let file = writer.into_inner().map_err(|err| err.into_error())?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Writes every store and TTL to `writer` in the default `SnapshotFormat`,
+    /// in the same layout as a snapshot file.
+    pub fn save<W: Write>(&self, writer: &mut W) -> Result<(), DcsError> {
+        self.save_as(writer, SnapshotFormat::default())
+    }
+
+    /// Writes every store and TTL to `writer`. Nothing is flushed, so a buffered
+    /// `writer` has to be flushed by the caller.
+    pub fn save_as<W: Write>(
+        &self,
+        writer: &mut W,
+        format: SnapshotFormat,
+    ) -> Result<(), DcsError> {
+        let envelope = Envelope {
+            version: VERSION,
+            data: self.to_snapshot()?,
+        };
+        match format {
+            SnapshotFormat::Json => {
+                writer.write_all(JSON_MAGIC)?;
+                serde_json::to_writer(writer, &envelope)
+                    .map_err(|err| DcsError::Serialization(err.into()))?;
+            }
+            #[cfg(feature = "bincode")]
+            SnapshotFormat::Bincode => {
+                writer.write_all(BINCODE_MAGIC)?;
+                bincode::serialize_into(writer, &envelope)
+                    .map_err(|err| DcsError::Serialization(err))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads a snapshot written by `save_snapshot`. Keys whose TTL ran out in the
+    /// meantime are left out.
+    pub fn load_snapshot<P: AsRef<Path>>(path: P) -> Result<DCS, DcsError> {
+        DCS::load(&mut File::open(path)?)
+    }
+
+    /// Reads a snapshot written by `save` or `save_snapshot` from `reader`, to
+    /// its end. Keys whose TTL ran out in the meantime are left out.
+    pub fn load<R: Read>(reader: &mut R) -> Result<DCS, DcsError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        let (magic, body) = bytes.split_at_checked(4).ok_or_else(unknown_format)?;
+        let snapshot = if magic == JSON_MAGIC {
+            decode_json(body)?
+        } else if magic == BINCODE_MAGIC {
+            decode_bincode(body)?
+        } else {
+            return Err(unknown_format());
+        };
+        DCS::from_snapshot(snapshot)
+    }
+
+    /// Copies every store and TTL out under read locks.
+    pub fn to_snapshot(&self) -> Result<DcsSnapshot, DcsError> {
+        // Hold every read lock at once so the snapshot is a single point in time.
+        let expirations = self.expirations.read().or_poisoned("expirations")?;
+        let store = self.store.read_all()?;
+        let list_store = self.list_store.read_all()?;
+        let hash_store = self.hash_store.read_all()?;
+        let set_store = self.set_store.read_all()?;
+        let zset_store = self.zset_store.read_all()?;
+        Ok(DcsSnapshot {
+            strings: store
+                .iter()
+                .filter_map(|(key, value)| match value {
+                    StringValue::Text(text) => Some((key.clone(), text.to_string())),
+                    StringValue::Bytes(_) => None,
+                })
+                .collect(),
+            bytes: store
+                .iter()
+                .filter_map(|(key, value)| match value {
+                    StringValue::Text(_) => None,
+                    StringValue::Bytes(bytes) => Some((key.clone(), bytes.to_vec())),
+                })
+                .collect(),
+            lists: list_store.to_map(),
+            hashes: hash_store.to_map(),
+            sets: set_store.to_map(),
+            zsets: zset_store
+                .iter()
+                .map(|(key, zset)| {
+                    let members = zset
+                        .iter()
+                        .map(|(member, score)| (member.clone(), score))
+                        .collect();
+                    (key.clone(), members)
+                })
+                .collect(),
+            expirations: expirations
+                .iter()
+                .map(|(key, deadline)| (key.clone(), to_unix_millis(*deadline)))
+                .collect(),
+        })
+    }
+
+    /// Builds a store from `snapshot`. Fails with `InvalidScore` if a zset holds a
+    /// NaN score. Keys whose TTL already ran out are left out.
+    pub fn from_snapshot(snapshot: DcsSnapshot) -> Result<DCS, DcsError> {
+        let has_nan = snapshot
+            .zsets
+            .values()
+            .flat_map(|members| members.values())
+            .any(|score| score.is_nan());
+        if has_nan {
+            return Err(DcsError::InvalidScore);
+        }
+        let mut dcs = DCS::new();
+        let mut expired = Vec::new();
+        let expirations = dcs.expirations.get_mut().or_poisoned("expirations")?;
+        for (key, millis) in snapshot.expirations {
+            match from_unix_millis(millis) {
+                Some(deadline) => {
+                    expirations.insert(key, deadline);
+                }
+                None => expired.push(key),
+            }
+        }
+        dcs.store.write_all()?.extend(
+            snapshot
+                .strings
+                .into_iter()
+                .map(|(key, value)| (key, value.into()))
+                .chain(
+                    snapshot
+                        .bytes
+                        .into_iter()
+                        .map(|(key, bytes)| (key, StringValue::from_bytes(bytes))),
+                ),
+        );
+        dcs.list_store.write_all()?.extend(snapshot.lists);
+        dcs.hash_store.write_all()?.extend(snapshot.hashes);
+        dcs.set_store.write_all()?.extend(snapshot.sets);
+        dcs.zset_store
+            .write_all()?
+            .extend(snapshot.zsets.into_iter().map(|(key, members)| {
+                let mut zset = ZSet::new();
+                for (member, score) in members {
+                    zset.insert(member, score);
+                }
+                (key, zset)
+            }));
+        dcs.remove_from_stores(&expired)?;
+        Ok(dcs)
+    }
+}
+
+fn decode_json(body: &[u8]) -> Result<DcsSnapshot, DcsError> {
+    match from_json::<EnvelopeHeader>(body)?.version {
+        1 => Ok(from_json::<Envelope<SnapshotV1>>(body)?.data.into()),
+        VERSION => Ok(from_json::<Envelope>(body)?.data),
+        version => Err(unsupported_version(version)),
+    }
+}
+
+fn from_json<'a, T: Deserialize<'a>>(body: &'a [u8]) -> Result<T, DcsError> {
+    serde_json::from_slice(body).map_err(|err| DcsError::Serialization(err.into()))
+}
+
+#[cfg(feature = "bincode")]
+fn decode_bincode(body: &[u8]) -> Result<DcsSnapshot, DcsError> {
+    match from_bincode::<EnvelopeHeader>(body)?.version {
+        1 => Ok(from_bincode::<Envelope<SnapshotV1>>(body)?.data.into()),
+        VERSION => Ok(from_bincode::<Envelope>(body)?.data),
+        version => Err(unsupported_version(version)),
+    }
+}
+
+#[cfg(feature = "bincode")]
+fn from_bincode<'a, T: Deserialize<'a>>(body: &'a [u8]) -> Result<T, DcsError> {
+    bincode::deserialize(body).map_err(|err| DcsError::Serialization(err))
+}
+
+#[cfg(not(feature = "bincode"))]
+fn decode_bincode(_: &[u8]) -> Result<DcsSnapshot, DcsError> {
+    Err(DcsError::Serialization(
+        "snapshot is bincode-encoded, but the `bincode` feature is disabled".into(),
+    ))
+}
+
+fn unsupported_version(version: u32) -> DcsError {
+    DcsError::Serialization(format!("unsupported snapshot version {}", version).into())
+}
+
+fn unknown_format() -> DcsError {
+    DcsError::Serialization("not a snapshot file".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DcsSnapshot, SnapshotFormat};
+    use crate::{DcsError, DCS};
+    use std::path::PathBuf;
+    use std::time::Duration;
+    use std::{env, fs, process};
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("dcs-{}-{}.snapshot", name, process::id()))
+    }
+
+    fn populated() -> DCS {
+        let dcs = DCS::new();
+        dcs.set("name".to_string(), "dcs".to_string()).unwrap();
+        dcs.list_push_multi(
+            "queue".to_string(),
+            vec!["a".to_string(), "b".to_string(), "c".to_string()],
+        )
+        .unwrap();
+        dcs.hash_set("user".to_string(), "id".to_string(), "7".to_string())
+            .unwrap();
+        dcs.set_add("tags".to_string(), "rust".to_string()).unwrap();
+        dcs.zset_add("board".to_string(), 1.5, "alice".to_string())
+            .unwrap();
+        dcs.zset_add("board".to_string(), -2.0, "bob".to_string())
+            .unwrap();
+        dcs.zset_incr_by("board".to_string(), f64::INFINITY, "carol".to_string())
+            .unwrap();
+        dcs.zset_add("board".to_string(), f64::NEG_INFINITY, "dave".to_string())
+            .unwrap();
+        dcs.set_bytes("blob".to_string(), vec![0xff, 0x00, 0xfe])
+            .unwrap();
+        dcs.set("session".to_string(), "abc".to_string()).unwrap();
+        dcs.expire("session", Duration::from_secs(60)).unwrap();
+        dcs
+    }
+
+    fn round_trip(format: SnapshotFormat) {
+        let dcs = populated();
+        let path = temp_path(&format!("{:?}", format));
+        dcs.save_snapshot_as(&path, format).unwrap();
+        let loaded = DCS::load_snapshot(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let (mut expected, mut actual) =
+            (dcs.to_snapshot().unwrap(), loaded.to_snapshot().unwrap());
+        // Deadlines are rebased on load and may shift by a millisecond.
+        let drift = expected.expirations["session"].abs_diff(actual.expirations["session"]);
+        assert!(drift <= 1);
+        expected.expirations.clear();
+        actual.expirations.clear();
+        assert_eq!(expected, actual);
+        assert!(loaded.ttl("session").unwrap().is_some());
+        assert_eq!(
+            loaded.zset_score("board", "carol").unwrap(),
+            Some(f64::INFINITY)
+        );
+        assert_eq!(
+            loaded.list_range("queue", 0, -1).unwrap(),
+            vec!["a", "b", "c"]
+        );
+    }
+
+    #[test]
+    fn test_save_load_round_trip() {
+        let dcs = populated();
+        let mut bytes = Vec::new();
+        dcs.save(&mut bytes).unwrap();
+        let loaded = DCS::load(&mut bytes.as_slice()).unwrap();
+        let (mut expected, mut actual) =
+            (dcs.to_snapshot().unwrap(), loaded.to_snapshot().unwrap());
+        let drift = expected.expirations["session"].abs_diff(actual.expirations["session"]);
+        assert!(drift <= 1);
+        expected.expirations.clear();
+        actual.expirations.clear();
+        assert_eq!(expected, actual);
+        assert_eq!(loaded.zset_score("board", "bob").unwrap(), Some(-2.0));
+        assert!(matches!(
+            DCS::load(&mut &b"DCS"[..]),
+            Err(DcsError::Serialization(_))
+        ));
+    }
+
+    #[test]
+    fn test_load_version_1() {
+        let fixture = br#"DCSJ{"version":1,"data":{
+            "strings":{"name":"dcs"},
+            "lists":{"queue":["a","b"]},
+            "hashes":{"user":{"id":"7"}},
+            "sets":{"tags":["rust"]},
+            "zsets":{"board":{"alice":1.5}},
+            "expirations":{}
+        }}"#;
+        let loaded = DCS::load(&mut &fixture[..]).unwrap();
+        assert_eq!(loaded.get("name").unwrap(), Some("dcs".to_string()));
+        assert_eq!(loaded.list_range("queue", 0, -1).unwrap(), vec!["a", "b"]);
+        assert_eq!(
+            loaded.hash_get("user", "id").unwrap(),
+            Some("7".to_string())
+        );
+        assert!(loaded.set_is_member("tags", "rust").unwrap());
+        assert_eq!(loaded.zset_score("board", "alice").unwrap(), Some(1.5));
+        assert!(matches!(
+            DCS::load(&mut &br#"DCSJ{"version":9,"data":{}}"#[..]),
+            Err(DcsError::Serialization(_))
+        ));
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn test_load_version_1_bincode() {
+        use std::collections::{HashMap, HashSet};
+
+        // bincode writes structs as their fields in order, so a tuple of the
+        // version 1 fields encodes the same bytes.
+        let data = (
+            HashMap::from([("name".to_string(), "dcs".to_string())]),
+            HashMap::<String, Vec<String>>::new(),
+            HashMap::<String, HashMap<String, String>>::new(),
+            HashMap::<String, HashSet<String>>::new(),
+            HashMap::from([(
+                "board".to_string(),
+                HashMap::from([("alice".to_string(), f64::INFINITY)]),
+            )]),
+            HashMap::<String, u64>::new(),
+        );
+        let mut fixture = b"DCSB".to_vec();
+        bincode::serialize_into(&mut fixture, &(1u32, data)).unwrap();
+        let loaded = DCS::load(&mut fixture.as_slice()).unwrap();
+        assert_eq!(loaded.get("name").unwrap(), Some("dcs".to_string()));
+        assert_eq!(
+            loaded.zset_score("board", "alice").unwrap(),
+            Some(f64::INFINITY)
+        );
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        round_trip(SnapshotFormat::Json);
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn test_bincode_round_trip() {
+        round_trip(SnapshotFormat::Bincode);
+    }
+
+    #[test]
+    fn test_load_drops_expired_keys() {
+        let dcs = populated();
+        dcs.expire("name", Duration::from_millis(20)).unwrap();
+        let path = temp_path("expired");
+        dcs.save_snapshot(&path).unwrap();
+        std::thread::sleep(Duration::from_millis(40));
+        let loaded = DCS::load_snapshot(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.get("name").unwrap(), None);
+        assert_eq!(loaded.get("session").unwrap(), Some("abc".to_string()));
+    }
+
+    #[test]
+    fn test_load_rejects_garbage() {
+        let path = temp_path("garbage");
+        fs::write(&path, b"not a snapshot").unwrap();
+        let result = DCS::load_snapshot(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(DcsError::Serialization(_))));
+        assert!(matches!(
+            DCS::load_snapshot(temp_path("missing")),
+            Err(DcsError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test_snapshot_json_round_trip() {
+        let dcs = populated();
+        let snapshot = dcs.to_snapshot().unwrap();
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let decoded: DcsSnapshot = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, snapshot);
+        let restored = DCS::from_snapshot(decoded).unwrap();
+        assert_eq!(restored.zset_score("board", "bob").unwrap(), Some(-2.0));
+        assert_eq!(
+            restored.hash_get("user", "id").unwrap(),
+            Some("7".to_string())
+        );
+        assert!(restored.set_is_member("tags", "rust").unwrap());
+    }
+
+    #[test]
+    fn test_from_snapshot_rejects_nan() {
+        let mut snapshot = DcsSnapshot::default();
+        snapshot
+            .zsets
+            .entry("board".to_string())
+            .or_default()
+            .insert("alice".to_string(), f64::NAN);
+        assert!(matches!(
+            DCS::from_snapshot(snapshot),
+            Err(DcsError::InvalidScore)
+        ));
+    }
+}
@@ -0,0 +1,173 @@
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{DcsError, DCS};
+
+/// Counters of what a `DCS` has done since it was created, as returned by
+/// [`DCS::stats`]. There is no byte size here since adding it up needs the store
+/// locks; see [`DCS::memory_usage`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DcsStats {
+    /// Commands that read without writing.
+    pub reads: u64,
+    /// Writes applied, counting each write of a transaction and each eviction of
+    /// an expired key.
+    pub writes: u64,
+    /// Lookups through `get`, `get_shared`, `get_bytes`, `mget`, `watch_get` and
+    /// `get_or_set_with` that found a string, counting each key of an `mget`.
+    pub get_hits: u64,
+    /// Lookups through the same methods that found nothing.
+    pub get_misses: u64,
+    /// Keys in each store, including expired keys that were not evicted yet.
+    pub strings: usize,
+    pub lists: usize,
+    pub hashes: usize,
+    pub sets: usize,
+    pub zsets: usize,
+}
+
+/// The counters behind `DcsStats`, updated without taking any lock.
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    reads: AtomicU64,
+    writes: AtomicU64,
+    get_hits: AtomicU64,
+    get_misses: AtomicU64,
+}
+
+impl Counters {
+    pub(crate) fn read(&self) {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn write(&self) {
+        self.writes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn get_one(&self, hit: bool) {
+        self.get(u64::from(hit), u64::from(!hit));
+    }
+
+    /// Counts a read by the `get` family, which looked up `hits + misses` keys.
+    pub(crate) fn get(&self, hits: u64, misses: u64) {
+        self.read();
+        self.get_hits.fetch_add(hits, Ordering::Relaxed);
+        self.get_misses.fetch_add(misses, Ordering::Relaxed);
+    }
+}
+
+impl DCS {
+    /// Returns the counters of reads, writes and `get` hits and misses, and the
+    /// number of keys in each store. Never waits for a lock, so the numbers may
+    /// be off by writes running at the same time. For the same reason the size in
+    /// bytes is left to `memory_usage`, which read-locks every store.
+    pub fn stats(&self) -> DcsStats {
+        let counters = &self.counters;
+        DcsStats {
+            reads: counters.reads.load(Ordering::Relaxed),
+            writes: counters.writes.load(Ordering::Relaxed),
+            get_hits: counters.get_hits.load(Ordering::Relaxed),
+            get_misses: counters.get_misses.load(Ordering::Relaxed),
+            strings: self.store.len(),
+            lists: self.list_store.len(),
+            hashes: self.hash_store.len(),
+            sets: self.set_store.len(),
+            zsets: self.zset_store.len(),
+        }
+    }
+
+    /// Returns the number of keys summed over all stores, without taking any
+    /// lock. Unlike `db_size`, a key held by two stores counts twice and expired
+    /// keys that were not evicted yet are counted.
+    pub fn len(&self) -> usize {
+        self.store.len()
+            + self.list_store.len()
+            + self.hash_store.len()
+            + self.set_store.len()
+            + self.zset_store.len()
+    }
+
+    /// Returns whether no store holds any key, without taking any lock.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the approximate number of bytes held: the length of every key and
+    /// string value, and of every element of a collection, where a hash field
+    /// counts with its value and a zset member with its 8-byte score. Stores are
+    /// read-locked one at a time and nothing is copied.
+    pub fn memory_usage(&self) -> Result<usize, DcsError> {
+        let mut bytes = 0;
+        for (key, value) in self.store.read_all()?.iter() {
+            bytes += key.len() + value.as_bytes().len();
+        }
+        for (key, list) in self.list_store.read_all()?.iter() {
+            bytes += key.len() + list.iter().map(String::len).sum::<usize>();
+        }
+        for (key, hash) in self.hash_store.read_all()?.iter() {
+            bytes += key.len()
+                + hash
+                    .iter()
+                    .map(|(field, value)| field.len() + value.len())
+                    .sum::<usize>();
+        }
+        for (key, set) in self.set_store.read_all()?.iter() {
+            bytes += key.len() + set.iter().map(String::len).sum::<usize>();
+        }
+        for (key, zset) in self.zset_store.read_all()?.iter() {
+            bytes += key.len()
+                + zset
+                    .iter()
+                    .map(|(member, _)| member.len() + mem::size_of::<f64>())
+                    .sum::<usize>();
+        }
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DcsStats;
+    use crate::DCS;
+
+    #[test]
+    fn test_stats_counts_operations() {
+        let dcs = DCS::new();
+        dcs.set("a".to_string(), "1".to_string()).unwrap();
+        dcs.set("b".to_string(), "2".to_string()).unwrap();
+        dcs.list_push("queue".to_string(), "x".to_string()).unwrap();
+        dcs.set_add("tags".to_string(), "rust".to_string()).unwrap();
+        dcs.get("a").unwrap();
+        dcs.get("missing").unwrap();
+        dcs.mget(&["a".to_string(), "b".to_string(), "c".to_string()])
+            .unwrap();
+        dcs.list_len("queue").unwrap();
+        dcs.del("b").unwrap();
+        assert_eq!(
+            dcs.stats(),
+            DcsStats {
+                reads: 4,
+                writes: 5,
+                get_hits: 3,
+                get_misses: 2,
+                strings: 1,
+                lists: 1,
+                hashes: 0,
+                sets: 1,
+                zsets: 0,
+            }
+        );
+        assert_eq!(dcs.len(), 3);
+        // "a" + "1", "queue" + "x", "tags" + "rust".
+        assert_eq!(dcs.memory_usage().unwrap(), 2 + 6 + 8);
+        dcs.hash_set("h".to_string(), "f".to_string(), "vv".to_string())
+            .unwrap();
+        dcs.zset_add("z".to_string(), 1.0, "m".to_string()).unwrap();
+        assert_eq!(dcs.memory_usage().unwrap(), 16 + 4 + 10);
+        dcs.flush_all().unwrap();
+        assert!(dcs.is_empty());
+        let stats = dcs.stats();
+        assert_eq!(stats.writes, 8);
+        assert_eq!((stats.strings, stats.lists, stats.sets), (0, 0, 0));
+    }
+}
@@ -0,0 +1,712 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::{Deref, DerefMut};
+use std::sync::RwLockWriteGuard;
+use std::time::{Duration, Instant};
+
+use crate::aof::Record;
+use crate::error::OrPoisoned;
+use crate::expiry::to_unix_millis;
+use crate::sharded::{Map, Shards, WriteShards};
+use crate::value::StringValue;
+use crate::zset::ZSet;
+use crate::{remove_if_empty, wrong_type, DcsError, KeyOp, KeyType, DCS};
+
+/// A batch of writes queued with the builder methods and applied by `DCS::exec`.
+///
+/// Readers see either none of the writes or all of them. If one of them fails,
+/// the ones before it are rolled back and nothing is applied.
+#[derive(Default)]
+#[must_use = "a transaction does nothing until it is passed to `DCS::exec`"]
+pub struct Transaction {
+    ops: Vec<Op>,
+}
+
+/// What one write of a committed `Transaction` returned, like the `DCS` method
+/// of the same name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxnResult {
+    /// From `set`, `hash_set`, `set_add` and `zset_add`.
+    Done,
+    /// The new value, from `incr_by` and `incr`.
+    Integer(i64),
+    /// The length of the list, from `list_push` and `list_push_front`.
+    Len(usize),
+    /// Whether there was something to act on, from `del`, `expire`, `hash_del`,
+    /// `set_remove` and `zset_remove`.
+    Bool(bool),
+}
+
+enum Op {
+    Set(String, String),
+    IncrBy(String, i64),
+    Del(String),
+    Expire(String, Duration),
+    ListPush(String, String),
+    ListPushFront(String, String),
+    HashSet(String, String, String),
+    HashDel(String, String),
+    SetAdd(String, String),
+    SetRemove(String, String),
+    ZSetAdd(String, f64, String),
+    ZSetRemove(String, String),
+}
+
+impl Op {
+    fn key(&self) -> &str {
+        match self {
+            Op::Set(key, _)
+            | Op::IncrBy(key, _)
+            | Op::Del(key)
+            | Op::Expire(key, _)
+            | Op::ListPush(key, _)
+            | Op::ListPushFront(key, _)
+            | Op::HashSet(key, _, _)
+            | Op::HashDel(key, _)
+            | Op::SetAdd(key, _)
+            | Op::SetRemove(key, _)
+            | Op::ZSetAdd(key, _, _)
+            | Op::ZSetRemove(key, _) => key,
+        }
+    }
+
+    /// The AOF record for this write, with TTLs resolved against `now`.
+    fn record<'r>(&self, r: &'r mut Record, now: Instant) -> &'r mut Record {
+        match self {
+            Op::Set(key, value) => r.arg("SET").arg(key).arg(value),
+            Op::IncrBy(key, delta) => r.arg("INCRBY").arg(key).arg(delta),
+            Op::Del(key) => r.arg("DEL").arg(key),
+            Op::Expire(key, ttl) => r.arg("PEXPIREAT").arg(key).arg(to_unix_millis(now + *ttl)),
+            Op::ListPush(key, value) => r.arg("RPUSH").arg(key).arg(value),
+            Op::ListPushFront(key, value) => r.arg("LPUSH").arg(key).arg(value),
+            Op::HashSet(key, field, value) => r.arg("HSET").arg(key).arg(field).arg(value),
+            Op::HashDel(key, field) => r.arg("HDEL").arg(key).arg(field),
+            Op::SetAdd(key, value) => r.arg("SADD").arg(key).arg(value),
+            Op::SetRemove(key, value) => r.arg("SREM").arg(key).arg(value),
+            Op::ZSetAdd(key, score, value) => r.arg("ZADD").arg(key).arg(score).arg(value),
+            Op::ZSetRemove(key, value) => r.arg("ZREM").arg(key).arg(value),
+        }
+    }
+}
+
+impl Transaction {
+    pub fn new() -> Self {
+        Transaction::default()
+    }
+
+    pub fn set(&mut self, key: String, value: String) -> &mut Self {
+        self.push(Op::Set(key, value))
+    }
+
+    pub fn incr_by(&mut self, key: String, delta: i64) -> &mut Self {
+        self.push(Op::IncrBy(key, delta))
+    }
+
+    pub fn incr(&mut self, key: String) -> &mut Self {
+        self.incr_by(key, 1)
+    }
+
+    pub fn del(&mut self, key: String) -> &mut Self {
+        self.push(Op::Del(key))
+    }
+
+    /// Sets a TTL on `key`, counted from the commit. Ignored if the key does not
+    /// exist at that point of the transaction.
+    pub fn expire(&mut self, key: String, ttl: Duration) -> &mut Self {
+        self.push(Op::Expire(key, ttl))
+    }
+
+    pub fn list_push(&mut self, key: String, value: String) -> &mut Self {
+        self.push(Op::ListPush(key, value))
+    }
+
+    pub fn list_push_front(&mut self, key: String, value: String) -> &mut Self {
+        self.push(Op::ListPushFront(key, value))
+    }
+
+    pub fn hash_set(&mut self, key: String, field: String, value: String) -> &mut Self {
+        self.push(Op::HashSet(key, field, value))
+    }
+
+    pub fn hash_del(&mut self, key: String, field: String) -> &mut Self {
+        self.push(Op::HashDel(key, field))
+    }
+
+    pub fn set_add(&mut self, key: String, value: String) -> &mut Self {
+        self.push(Op::SetAdd(key, value))
+    }
+
+    pub fn set_remove(&mut self, key: String, value: String) -> &mut Self {
+        self.push(Op::SetRemove(key, value))
+    }
+
+    pub fn zset_add(&mut self, key: String, score: f64, value: String) -> &mut Self {
+        self.push(Op::ZSetAdd(key, score, value))
+    }
+
+    pub fn zset_remove(&mut self, key: String, value: String) -> &mut Self {
+        self.push(Op::ZSetRemove(key, value))
+    }
+
+    fn push(&mut self, op: Op) -> &mut Self {
+        self.ops.push(op);
+        self
+    }
+}
+
+impl DCS {
+    /// Applies every write queued on `txn` while holding the TTL lock and all
+    /// store write locks, taken in field declaration order like everywhere else.
+    /// Each write goes through the same checks as the method of the same name,
+    /// and `set` gives its key the default TTL.
+    ///
+    /// Returns what each write returned, in order. Fails with the error of the
+    /// first write that fails, such as `NotAnInteger`, `WrongType` or
+    /// `OomRejected`, after undoing the writes before it.
+    pub fn exec(&self, txn: Transaction) -> Result<Vec<TxnResult>, DcsError> {
+        for op in &txn.ops {
+            self.evict_if_expired(op.key())?;
+        }
+        let keys = txn.ops.iter().map(Op::key);
+        let mut locked = Locked {
+            dcs: self,
+            expirations: self.expirations.write().or_poisoned("expirations")?,
+            strings: self.store.write_keys(keys.clone())?,
+            lists: self.list_store.write_keys(keys.clone())?,
+            hashes: self.hash_store.write_keys(keys.clone())?,
+            sets: self.set_store.write_keys(keys.clone())?,
+            zsets: self.zset_store.write_keys(keys)?,
+        };
+        let mut undo = Undo::default();
+        let now = Instant::now();
+        let mut applied = Vec::with_capacity(txn.ops.len());
+        for op in &txn.ops {
+            match locked.apply(op, &mut undo, now) {
+                Ok(done) => applied.push(done),
+                Err(err) => {
+                    undo.restore(&mut locked);
+                    return Err(err);
+                }
+            }
+        }
+        // Replay skips a batch whose `EXEC` never made it to the file.
+        self.log_marker(|r| r.arg("MULTI"))?;
+        let mut results = Vec::with_capacity(applied.len());
+        for (op, done) in txn.ops.iter().zip(applied) {
+            results.push(done.result);
+            self.log(|r| op.record(r, now))?;
+            if let Some(deadline) = done.default_ttl {
+                self.log(|r| {
+                    r.arg("PEXPIREAT")
+                        .arg(op.key())
+                        .arg(to_unix_millis(deadline))
+                })?;
+                self.notify_keyspace(op.key(), KeyType::String, KeyOp::Expire)?;
+            }
+            let event = match op {
+                Op::Del(_) => KeyOp::Del,
+                Op::Expire(..) => KeyOp::Expire,
+                _ => continue,
+            };
+            for ty in done.types {
+                self.notify_keyspace(op.key(), ty, event)?;
+            }
+        }
+        self.log_marker(|r| r.arg("EXEC"))?;
+        let mut versions = self.versions.lock().or_poisoned("versions")?;
+        let mut lengths = Vec::new();
+        for op in &txn.ops {
+            if let Op::Set(key, _) | Op::IncrBy(key, _) | Op::Del(key) = op {
+                let value = locked.strings.get(key);
+                versions.bump(key, value.is_some());
+                lengths.push((key, value.map(|value| value.as_bytes().len())));
+            }
+        }
+        drop(versions);
+        drop(locked);
+        for (key, len) in lengths {
+            match len {
+                Some(len) => self.track_string(key, len)?,
+                None => self.untrack(key)?,
+            }
+        }
+        if txn
+            .ops
+            .iter()
+            .any(|op| matches!(op, Op::ListPush(..) | Op::ListPushFront(..)))
+        {
+            self.notify_list_push()?;
+        }
+        Ok(results)
+    }
+}
+
+/// What applying one write of a transaction did.
+struct Applied {
+    result: TxnResult,
+    /// The stores acted on by `Del` and `Expire`, whose records don't say which,
+    /// so keyspace events can be sent.
+    types: Vec<KeyType>,
+    /// The deadline a `Set` gave its key from `DcsBuilder::default_ttl`.
+    default_ttl: Option<Instant>,
+}
+
+impl Applied {
+    fn result(result: TxnResult) -> Applied {
+        Applied {
+            result,
+            types: Vec::new(),
+            default_ttl: None,
+        }
+    }
+}
+
+/// The TTLs of a `DCS` and the shards of its stores holding the keys of a
+/// transaction, all locked for writing.
+struct Locked<'a> {
+    dcs: &'a DCS,
+    expirations: RwLockWriteGuard<'a, HashMap<String, Instant>>,
+    strings: WriteShards<'a, StringValue>,
+    lists: WriteShards<'a, Vec<String>>,
+    hashes: WriteShards<'a, HashMap<String, String>>,
+    sets: WriteShards<'a, HashSet<String>>,
+    zsets: WriteShards<'a, ZSet>,
+}
+
+impl Locked<'_> {
+    /// Applies `op`.
+    fn apply(&mut self, op: &Op, undo: &mut Undo, now: Instant) -> Result<Applied, DcsError> {
+        let result = match op {
+            Op::Set(key, value) => {
+                self.check_type(key, KeyType::String)?;
+                self.dcs.check_budget(key, value.len())?;
+                save(&mut undo.strings, &self.strings, key);
+                self.strings.insert(key.clone(), value.as_str().into());
+                let default_ttl = match self.dcs.default_ttl {
+                    Some(ttl) if !self.expirations.contains_key(key) => {
+                        undo.save_ttl(&self.expirations, key);
+                        self.expirations.insert(key.clone(), now + ttl);
+                        Some(now + ttl)
+                    }
+                    _ => None,
+                };
+                return Ok(Applied {
+                    default_ttl,
+                    ..Applied::result(TxnResult::Done)
+                });
+            }
+            Op::IncrBy(key, delta) => {
+                self.check_type(key, KeyType::String)?;
+                let current = match self.strings.get(key) {
+                    Some(value) => value.to_integer(key)?,
+                    None => 0,
+                };
+                let new_value = current.checked_add(*delta).ok_or(DcsError::Overflow)?;
+                let new_text = new_value.to_string();
+                self.dcs.check_budget(key, new_text.len())?;
+                save(&mut undo.strings, &self.strings, key);
+                self.strings.insert(key.clone(), new_text.into());
+                TxnResult::Integer(new_value)
+            }
+            Op::Del(key) => {
+                let types = self.types(key);
+                undo.save_ttl(&self.expirations, key);
+                save(&mut undo.strings, &self.strings, key);
+                save(&mut undo.lists, &self.lists, key);
+                save(&mut undo.hashes, &self.hashes, key);
+                save(&mut undo.sets, &self.sets, key);
+                save(&mut undo.zsets, &self.zsets, key);
+                self.expirations.remove(key);
+                self.strings.remove(key);
+                self.lists.remove(key);
+                self.hashes.remove(key);
+                self.sets.remove(key);
+                self.zsets.remove(key);
+                let existed = !types.is_empty();
+                return Ok(Applied {
+                    types,
+                    ..Applied::result(TxnResult::Bool(existed))
+                });
+            }
+            Op::Expire(key, ttl) => {
+                let types = self.types(key);
+                let exists = !types.is_empty();
+                if exists {
+                    undo.save_ttl(&self.expirations, key);
+                    self.expirations.insert(key.clone(), now + *ttl);
+                }
+                return Ok(Applied {
+                    types,
+                    ..Applied::result(TxnResult::Bool(exists))
+                });
+            }
+            Op::ListPush(key, value) | Op::ListPushFront(key, value) => {
+                self.check_type(key, KeyType::List)?;
+                save(&mut undo.lists, &self.lists, key);
+                let list = self.lists.get_or_default(key.clone());
+                match op {
+                    Op::ListPush(..) => list.push(value.clone()),
+                    _ => list.insert(0, value.clone()),
+                }
+                TxnResult::Len(list.len())
+            }
+            Op::HashSet(key, field, value) => {
+                self.check_type(key, KeyType::Hash)?;
+                save(&mut undo.hashes, &self.hashes, key);
+                let hash = self.hashes.get_or_default(key.clone());
+                hash.insert(field.clone(), value.clone());
+                TxnResult::Done
+            }
+            Op::HashDel(key, field) => {
+                self.check_type(key, KeyType::Hash)?;
+                save(&mut undo.hashes, &self.hashes, key);
+                let removed = self
+                    .hashes
+                    .get_mut(key)
+                    .is_some_and(|hash| hash.remove(field).is_some());
+                if remove_if_empty(&mut self.hashes, key, HashMap::is_empty) {
+                    self.drop_ttl_if_gone(key, undo);
+                }
+                TxnResult::Bool(removed)
+            }
+            Op::SetAdd(key, value) => {
+                self.check_type(key, KeyType::Set)?;
+                save(&mut undo.sets, &self.sets, key);
+                let set = self.sets.get_or_default(key.clone());
+                set.insert(value.clone());
+                TxnResult::Done
+            }
+            Op::SetRemove(key, value) => {
+                self.check_type(key, KeyType::Set)?;
+                save(&mut undo.sets, &self.sets, key);
+                let removed = self.sets.get_mut(key).is_some_and(|set| set.remove(value));
+                if remove_if_empty(&mut self.sets, key, HashSet::is_empty) {
+                    self.drop_ttl_if_gone(key, undo);
+                }
+                TxnResult::Bool(removed)
+            }
+            Op::ZSetAdd(key, score, value) => {
+                if score.is_nan() {
+                    return Err(DcsError::InvalidScore);
+                }
+                self.check_type(key, KeyType::ZSet)?;
+                save(&mut undo.zsets, &self.zsets, key);
+                let zset = self.zsets.get_or_default(key.clone());
+                zset.insert(value.clone(), *score);
+                TxnResult::Done
+            }
+            Op::ZSetRemove(key, value) => {
+                self.check_type(key, KeyType::ZSet)?;
+                save(&mut undo.zsets, &self.zsets, key);
+                let removed = self
+                    .zsets
+                    .get_mut(key)
+                    .is_some_and(|zset| zset.remove(value).is_some());
+                if remove_if_empty(&mut self.zsets, key, ZSet::is_empty) {
+                    self.drop_ttl_if_gone(key, undo);
+                }
+                TxnResult::Bool(removed)
+            }
+        };
+        Ok(Applied::result(result))
+    }
+
+    /// Like `DCS::drop_ttl_if_gone`, against the locked stores.
+    fn drop_ttl_if_gone(&mut self, key: &str, undo: &mut Undo) {
+        if self.types(key).is_empty() {
+            undo.save_ttl(&self.expirations, key);
+            self.expirations.remove(key);
+        }
+    }
+
+    /// Like the check of `DCS::write_typed_keys`, but against the locked stores, so it also sees
+    /// earlier writes of the transaction.
+    fn check_type(&self, key: &str, ty: KeyType) -> Result<(), DcsError> {
+        match self.types(key).into_iter().find(|t| *t != ty) {
+            Some(found) => Err(wrong_type(key, ty, found)),
+            None => Ok(()),
+        }
+    }
+
+    fn types(&self, key: &str) -> Vec<KeyType> {
+        let mut types = Vec::new();
+        if self.strings.contains_key(key) {
+            types.push(KeyType::String);
+        }
+        if self.lists.contains_key(key) {
+            types.push(KeyType::List);
+        }
+        if self.hashes.contains_key(key) {
+            types.push(KeyType::Hash);
+        }
+        if self.sets.contains_key(key) {
+            types.push(KeyType::Set);
+        }
+        if self.zsets.contains_key(key) {
+            types.push(KeyType::ZSet);
+        }
+        types
+    }
+}
+
+/// The entries a transaction touched, as they were before its first write to
+/// each. `None` means the key was absent.
+#[derive(Default)]
+struct Undo {
+    expirations: HashMap<String, Option<Instant>>,
+    strings: HashMap<String, Option<StringValue>>,
+    lists: HashMap<String, Option<Vec<String>>>,
+    hashes: HashMap<String, Option<HashMap<String, String>>>,
+    sets: HashMap<String, Option<HashSet<String>>>,
+    zsets: HashMap<String, Option<ZSet>>,
+}
+
+impl Undo {
+    fn save_ttl(&mut self, expirations: &HashMap<String, Instant>, key: &str) {
+        if !self.expirations.contains_key(key) {
+            self.expirations
+                .insert(key.to_string(), expirations.get(key).copied());
+        }
+    }
+
+    fn restore(self, locked: &mut Locked<'_>) {
+        for (key, deadline) in self.expirations {
+            match deadline {
+                Some(deadline) => locked.expirations.insert(key, deadline),
+                None => locked.expirations.remove(&key),
+            };
+        }
+        restore(self.strings, &mut locked.strings);
+        restore(self.lists, &mut locked.lists);
+        restore(self.hashes, &mut locked.hashes);
+        restore(self.sets, &mut locked.sets);
+        restore(self.zsets, &mut locked.zsets);
+    }
+}
+
+fn save<V: Clone, G: Deref<Target = Map<V>>>(
+    saved: &mut HashMap<String, Option<V>>,
+    map: &Shards<'_, V, G>,
+    key: &str,
+) {
+    if !saved.contains_key(key) {
+        saved.insert(key.to_string(), map.get(key).cloned());
+    }
+}
+
+fn restore<V, G: DerefMut<Target = Map<V>>>(
+    saved: HashMap<String, Option<V>>,
+    map: &mut Shards<'_, V, G>,
+) {
+    for (key, value) in saved {
+        match value {
+            Some(value) => map.insert(key, value),
+            None => map.remove(&key),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::{Transaction, TxnResult};
+    use crate::{DcsError, KeyOp, KeyType, DCS};
+
+    #[test]
+    fn test_transaction_results() {
+        let dcs = DCS::new();
+        let mut tx = Transaction::new();
+        tx.incr("counter".to_string())
+            .list_push("log".to_string(), "a".to_string())
+            .set_add("seen".to_string(), "a".to_string());
+        assert_eq!(
+            dcs.exec(tx).unwrap(),
+            vec![TxnResult::Integer(1), TxnResult::Len(1), TxnResult::Done]
+        );
+        assert_eq!(dcs.get("counter").unwrap(), Some("1".to_string()));
+        assert_eq!(dcs.list_range("log", 0, -1).unwrap(), vec!["a".to_string()]);
+        assert!(dcs.set_is_member("seen", "a").unwrap());
+        let mut tx = Transaction::new();
+        tx.set_remove("seen".to_string(), "b".to_string())
+            .del("counter".to_string())
+            .expire("counter".to_string(), Duration::from_secs(10));
+        assert_eq!(
+            dcs.exec(tx).unwrap(),
+            vec![
+                TxnResult::Bool(false),
+                TxnResult::Bool(true),
+                TxnResult::Bool(false)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_exec() {
+        let dcs = DCS::new();
+        dcs.set("a".to_string(), "1".to_string()).unwrap();
+        let mut txn = Transaction::new();
+        txn.del("a".to_string()).incr("b".to_string());
+        assert_eq!(
+            dcs.exec(txn).unwrap(),
+            vec![TxnResult::Bool(true), TxnResult::Integer(1)]
+        );
+        assert!(!dcs.exists("a").unwrap());
+        assert_eq!(dcs.get("b").unwrap(), Some("1".to_string()));
+    }
+
+    #[test]
+    fn test_transaction_rolls_back_on_error() {
+        let dcs = DCS::new();
+        dcs.set("a".to_string(), "1".to_string()).unwrap();
+        dcs.set("b".to_string(), "x".to_string()).unwrap();
+        let mut tx = Transaction::new();
+        tx.set("a".to_string(), "2".to_string())
+            .del("a".to_string())
+            .list_push("log".to_string(), "a".to_string())
+            .incr("b".to_string());
+        assert!(matches!(dcs.exec(tx), Err(DcsError::NotAnInteger { .. })));
+        assert_eq!(dcs.get("a").unwrap(), Some("1".to_string()));
+        assert_eq!(dcs.list_len("log").unwrap(), 0);
+        assert!(!dcs.exists("log").unwrap());
+    }
+
+    #[test]
+    fn test_transaction_rejects_nan_score() {
+        let dcs = DCS::new();
+        let mut tx = Transaction::new();
+        tx.set("a".to_string(), "1".to_string()).zset_add(
+            "board".to_string(),
+            f64::NAN,
+            "alice".to_string(),
+        );
+        assert!(matches!(dcs.exec(tx), Err(DcsError::InvalidScore)));
+        assert!(!dcs.exists("a").unwrap());
+        assert!(!dcs.exists("board").unwrap());
+    }
+
+    #[test]
+    fn test_transaction_checks_memory_budget() {
+        let dcs = DCS::builder().max_memory(10).build().unwrap();
+        let mut tx = Transaction::new();
+        tx.set("a".to_string(), "1".to_string())
+            .set("b".to_string(), "x".repeat(10));
+        assert!(matches!(dcs.exec(tx), Err(DcsError::OomRejected)));
+        assert!(!dcs.exists("a").unwrap());
+        dcs.set("n".to_string(), "1".to_string()).unwrap();
+        let mut tx = Transaction::new();
+        tx.incr_by("n".to_string(), i64::MAX - 1);
+        assert!(matches!(dcs.exec(tx), Err(DcsError::OomRejected)));
+        assert_eq!(dcs.get("n").unwrap(), Some("1".to_string()));
+    }
+
+    #[test]
+    fn test_transaction_applies_default_ttl() {
+        let dcs = DCS::builder()
+            .default_ttl(Duration::from_secs(60))
+            .build()
+            .unwrap();
+        let mut tx = Transaction::new();
+        tx.set("cached".to_string(), "v".to_string())
+            .incr("counter".to_string())
+            .set("short".to_string(), "v".to_string())
+            .expire("short".to_string(), Duration::from_secs(5));
+        dcs.exec(tx).unwrap();
+        assert!(dcs.ttl("cached").unwrap().is_some());
+        assert_eq!(dcs.ttl("counter").unwrap(), None);
+        assert!(dcs.ttl("short").unwrap().unwrap() <= Duration::from_secs(5));
+        let mut tx = Transaction::new();
+        tx.set("short".to_string(), "w".to_string());
+        dcs.exec(tx).unwrap();
+        assert!(dcs.ttl("short").unwrap().unwrap() <= Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_transaction_removes_emptied_collections() {
+        let dcs = DCS::new();
+        dcs.set_add("set".to_string(), "a".to_string()).unwrap();
+        dcs.expire("set", Duration::from_secs(60)).unwrap();
+        let mut tx = Transaction::new();
+        tx.set_remove("set".to_string(), "a".to_string());
+        dcs.exec(tx).unwrap();
+        assert!(!dcs.exists("set").unwrap());
+        dcs.set_add("set".to_string(), "b".to_string()).unwrap();
+        assert_eq!(dcs.ttl("set").unwrap(), None);
+    }
+
+    #[test]
+    fn test_transaction_type_check_sees_own_writes() {
+        let dcs = DCS::new();
+        dcs.set("k".to_string(), "v".to_string()).unwrap();
+        let mut tx = Transaction::new();
+        tx.list_push("k".to_string(), "a".to_string());
+        assert!(matches!(dcs.exec(tx), Err(DcsError::WrongType { .. })));
+        let mut tx = Transaction::new();
+        tx.del("k".to_string())
+            .list_push("k".to_string(), "a".to_string());
+        dcs.exec(tx).unwrap();
+        assert_eq!(dcs.list_len("k").unwrap(), 1);
+    }
+
+    #[test]
+    fn test_transaction_is_atomic_for_readers() {
+        // The key moves between the string and list stores; a reader listing all
+        // keys must always find exactly one of the two.
+        let dcs = Arc::new(DCS::new());
+        dcs.set("a".to_string(), "1".to_string()).unwrap();
+        let writer = {
+            let dcs = Arc::clone(&dcs);
+            thread::spawn(move || {
+                for _ in 0..500 {
+                    let mut tx = Transaction::new();
+                    tx.del("a".to_string())
+                        .list_push("b".to_string(), "1".to_string());
+                    dcs.exec(tx).unwrap();
+                    let mut tx = Transaction::new();
+                    tx.del("b".to_string())
+                        .set("a".to_string(), "1".to_string());
+                    dcs.exec(tx).unwrap();
+                }
+            })
+        };
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let dcs = Arc::clone(&dcs);
+                thread::spawn(move || {
+                    for _ in 0..500 {
+                        assert_eq!(dcs.keys("*").unwrap().len(), 1);
+                    }
+                })
+            })
+            .collect();
+        writer.join().unwrap();
+        for reader in readers {
+            reader.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_transaction_keyspace_events() {
+        let dcs = DCS::new();
+        dcs.set("a".to_string(), "1".to_string()).unwrap();
+        let events = dcs.subscribe_keyspace().unwrap();
+        let mut tx = Transaction::new();
+        tx.del("a".to_string())
+            .list_push("b".to_string(), "1".to_string())
+            .expire("b".to_string(), Duration::from_secs(60));
+        dcs.exec(tx).unwrap();
+        let ops: Vec<_> = events
+            .try_iter()
+            .map(|event| (event.key, event.key_type, event.op))
+            .collect();
+        assert_eq!(
+            ops,
+            vec![
+                ("a".to_string(), KeyType::String, KeyOp::Del),
+                ("b".to_string(), KeyType::List, KeyOp::Push),
+                ("b".to_string(), KeyType::List, KeyOp::Expire),
+            ]
+        );
+    }
+}
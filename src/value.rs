@@ -0,0 +1,79 @@
+use std::sync::Arc;
+
+use crate::DcsError;
+
+/// A value in the string store. Values are held as text when they are valid
+/// UTF-8, so `get_shared` can hand them out without copying, and as bytes
+/// otherwise, which only `get_bytes` can read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum StringValue {
+    Text(Arc<str>),
+    Bytes(Arc<[u8]>),
+}
+
+impl StringValue {
+    pub(crate) fn from_bytes(bytes: Vec<u8>) -> Self {
+        match String::from_utf8(bytes) {
+            Ok(text) => StringValue::Text(text.into()),
+            Err(err) => StringValue::Bytes(err.into_bytes().into()),
+        }
+    }
+
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        match self {
+            StringValue::Text(text) => text.as_bytes(),
+            StringValue::Bytes(bytes) => bytes,
+        }
+    }
+
+    /// Returns the value as text, failing with `InvalidUtf8` if it isn't.
+    pub(crate) fn text(&self) -> Result<&Arc<str>, DcsError> {
+        match self {
+            StringValue::Text(text) => Ok(text),
+            StringValue::Bytes(_) => Err(DcsError::InvalidUtf8),
+        }
+    }
+
+    pub(crate) fn to_text(&self) -> Result<String, DcsError> {
+        self.text().map(|text| text.to_string())
+    }
+
+    /// Parses the value at `key` as an integer, for the `incr` family.
+    pub(crate) fn to_integer(&self, key: &str) -> Result<i64, DcsError> {
+        let not_an_integer = || DcsError::NotAnInteger {
+            key: key.to_string(),
+        };
+        match self {
+            StringValue::Text(text) => text.parse().map_err(|_| not_an_integer()),
+            StringValue::Bytes(_) => Err(not_an_integer()),
+        }
+    }
+}
+
+impl From<String> for StringValue {
+    fn from(text: String) -> Self {
+        StringValue::Text(text.into())
+    }
+}
+
+impl From<&str> for StringValue {
+    fn from(text: &str) -> Self {
+        StringValue::Text(text.into())
+    }
+}
+
+/// Encodes `bytes` as lowercase hex, for logging binary values to the AOF, whose
+/// records hold text.
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub(crate) fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
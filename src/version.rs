@@ -0,0 +1,52 @@
+use std::collections::HashMap;
+
+/// Version stamps of string keys, for `watch_get` and `compare_and_set`.
+///
+/// Every write to a string gives it a stamp no key has had before, so a stamp
+/// only matches again if nothing was written in between. Missing keys are at
+/// version 0 and don't take up an entry. Keys written without going through
+/// `bump`, like those loaded from a snapshot, get their first stamp when read.
+#[derive(Debug, Default)]
+pub(crate) struct Versions {
+    /// The last stamp handed out.
+    last: u64,
+    by_key: HashMap<String, u64>,
+}
+
+impl Versions {
+    pub(crate) fn new() -> Self {
+        Versions::default()
+    }
+
+    /// Returns the version of `key`, which must be passed whether it exists.
+    pub(crate) fn get(&mut self, key: &str, exists: bool) -> u64 {
+        if !exists {
+            return 0;
+        }
+        if let Some(version) = self.by_key.get(key) {
+            return *version;
+        }
+        let version = self.next();
+        self.by_key.insert(key.to_string(), version);
+        version
+    }
+
+    /// Records a write to `key`, which must be passed whether it still exists.
+    pub(crate) fn bump(&mut self, key: &str, exists: bool) {
+        if exists {
+            let version = self.next();
+            self.by_key.insert(key.to_string(), version);
+        } else {
+            self.by_key.remove(key);
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.by_key.clear();
+    }
+
+    fn next(&mut self) -> u64 {
+        self.last += 1;
+        self.last
+    }
+}
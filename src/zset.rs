@@ -0,0 +1,221 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+
+/// A sorted set: a member to score map plus an index of `(score, member)` pairs
+/// kept in ascending order, so ordered reads don't need to sort.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ZSet {
+    scores: HashMap<String, f64>,
+    by_score: BTreeSet<(Score, String)>,
+}
+
+/// `f64` wrapper ordered by `total_cmp`, so it can be used as a `BTreeSet` key.
+#[derive(Debug, Clone, Copy)]
+struct Score(f64);
+
+impl PartialEq for Score {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+impl ZSet {
+    pub(crate) fn new() -> Self {
+        ZSet::default()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    pub(crate) fn score(&self, member: &str) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Sets the score of `member`, returning the previous one.
+    pub(crate) fn insert(&mut self, member: String, score: f64) -> Option<f64> {
+        // `total_cmp` orders -0.0 before 0.0, store a single zero so score ranges
+        // treat them the same.
+        let score = if score == 0.0 { 0.0 } else { score };
+        let previous = self.scores.insert(member.clone(), score);
+        if let Some(previous) = previous {
+            self.by_score.remove(&(Score(previous), member.clone()));
+        }
+        self.by_score.insert((Score(score), member));
+        previous
+    }
+
+    pub(crate) fn remove(&mut self, member: &str) -> Option<f64> {
+        let (member, score) = self.scores.remove_entry(member)?;
+        self.by_score.remove(&(Score(score), member));
+        Some(score)
+    }
+
+    /// Removes and returns the lowest-ranked member.
+    pub(crate) fn pop_first(&mut self) -> Option<(String, f64)> {
+        let (score, member) = self.by_score.pop_first()?;
+        self.scores.remove(&member);
+        Some((member, score.0))
+    }
+
+    /// Removes and returns the highest-ranked member.
+    pub(crate) fn pop_last(&mut self) -> Option<(String, f64)> {
+        let (score, member) = self.by_score.pop_last()?;
+        self.scores.remove(&member);
+        Some((member, score.0))
+    }
+
+    /// Iterates members by ascending score, ties broken by member.
+    pub(crate) fn iter(&self) -> impl DoubleEndedIterator<Item = (&String, f64)> {
+        self.by_score
+            .iter()
+            .map(|(score, member)| (member, score.0))
+    }
+
+    /// Returns the members ranked `start..=stop` in ascending order. Walks from
+    /// whichever end is closer, so the cost is linear in the distance from that
+    /// end plus the output.
+    pub(crate) fn range_by_rank(&self, start: usize, stop: usize) -> Vec<(&String, f64)> {
+        let len = self.len();
+        if start > stop || start >= len {
+            return Vec::new();
+        }
+        let stop = stop.min(len - 1);
+        let count = stop - start + 1;
+        if start <= len - 1 - stop {
+            self.iter().skip(start).take(count).collect()
+        } else {
+            let mut range: Vec<_> = self.iter().rev().skip(len - 1 - stop).take(count).collect();
+            range.reverse();
+            range
+        }
+    }
+
+    /// Iterates members with a score in `min..=max`, in ascending order.
+    pub(crate) fn range_by_score(
+        &self,
+        min: f64,
+        max: f64,
+    ) -> impl Iterator<Item = (&String, f64)> {
+        let min = if min == 0.0 { 0.0 } else { min };
+        let empty = min.is_nan() || max.is_nan() || min > max;
+        self.by_score
+            .range((Score(min), String::new())..)
+            .take_while(move |(score, _)| !empty && score.0 <= max)
+            .map(|(score, member)| (member, score.0))
+    }
+
+    /// Returns the 0-based ascending rank of `member`. Linear in the rank.
+    pub(crate) fn rank(&self, member: &str) -> Option<usize> {
+        let score = self.score(member)?;
+        Some(
+            self.by_score
+                .range(..(Score(score), member.to_string()))
+                .count(),
+        )
+    }
+}
+
+impl PartialEq for ZSet {
+    fn eq(&self, other: &Self) -> bool {
+        self.scores == other.scores
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Score, ZSet};
+    use std::time::{Duration, Instant};
+
+    fn assert_consistent(zset: &ZSet) {
+        assert_eq!(zset.scores.len(), zset.by_score.len());
+        for (member, score) in &zset.scores {
+            assert!(zset.by_score.contains(&(Score(*score), member.clone())));
+        }
+    }
+
+    #[test]
+    fn test_update_score_keeps_indexes_in_sync() {
+        let mut zset = ZSet::new();
+        zset.insert("a".to_string(), 1.0);
+        zset.insert("b".to_string(), 2.0);
+        assert_eq!(zset.insert("a".to_string(), 3.0), Some(1.0));
+        assert_consistent(&zset);
+        let order: Vec<&String> = zset.iter().map(|(member, _)| member).collect();
+        assert_eq!(order, vec!["b", "a"]);
+        assert_eq!(zset.remove("b"), Some(2.0));
+        assert_eq!(zset.remove("b"), None);
+        assert_consistent(&zset);
+        assert_eq!(zset.len(), 1);
+    }
+
+    #[test]
+    fn test_range_by_rank() {
+        let mut zset = ZSet::new();
+        for i in 0..10 {
+            zset.insert(format!("m{}", i), i as f64);
+        }
+        let front: Vec<f64> = zset.range_by_rank(1, 3).iter().map(|(_, s)| *s).collect();
+        assert_eq!(front, vec![1.0, 2.0, 3.0]);
+        let back: Vec<f64> = zset.range_by_rank(7, 20).iter().map(|(_, s)| *s).collect();
+        assert_eq!(back, vec![7.0, 8.0, 9.0]);
+        assert!(zset.range_by_rank(4, 3).is_empty());
+        assert_eq!(zset.rank("m4"), Some(4));
+        assert_eq!(zset.rank("missing"), None);
+    }
+
+    #[test]
+    fn test_range_by_score() {
+        let mut zset = ZSet::new();
+        for i in 0..10 {
+            zset.insert(format!("m{}", i), i as f64);
+        }
+        zset.insert("zero".to_string(), -0.0);
+        let scores: Vec<f64> = zset.range_by_score(2.0, 4.0).map(|(_, s)| s).collect();
+        assert_eq!(scores, vec![2.0, 3.0, 4.0]);
+        assert_eq!(zset.range_by_score(0.0, 0.0).count(), 2);
+        assert_eq!(
+            zset.range_by_score(f64::NEG_INFINITY, f64::INFINITY)
+                .count(),
+            11
+        );
+        assert_eq!(zset.range_by_score(5.0, 1.0).count(), 0);
+    }
+
+    #[test]
+    fn test_large_zset() {
+        let start = Instant::now();
+        let mut zset = ZSet::new();
+        for i in 0..10_000 {
+            zset.insert(format!("member{}", i), (i % 1000) as f64);
+        }
+        for i in (0..10_000).step_by(3) {
+            zset.insert(format!("member{}", i), -(i as f64));
+        }
+        assert_consistent(&zset);
+        assert_eq!(zset.len(), 10_000);
+        let ranked: Vec<f64> = zset.iter().map(|(_, score)| score).collect();
+        assert!(ranked.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert_eq!(zset.range_by_rank(0, 0)[0].1, -9999.0);
+        assert_eq!(zset.range_by_rank(9_999, 9_999)[0].1, 999.0);
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+}
@@ -0,0 +1,94 @@
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use dcs::server::serve_listener;
+use dcs::DCS;
+
+fn start() -> TcpStream {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let dcs = Arc::new(DCS::new());
+    thread::spawn(move || serve_listener(dcs, listener));
+    TcpStream::connect(addr).unwrap()
+}
+
+fn request(stream: &mut TcpStream, bytes: &[u8], expected: &[u8]) {
+    stream.write_all(bytes).unwrap();
+    let mut reply = vec![0; expected.len()];
+    stream.read_exact(&mut reply).unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(&reply),
+        String::from_utf8_lossy(expected)
+    );
+}
+
+#[test]
+fn test_set_get() {
+    let mut stream = start();
+    request(
+        &mut stream,
+        b"*3\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$3\r\nbar\r\n",
+        b"+OK\r\n",
+    );
+    request(
+        &mut stream,
+        b"*2\r\n$3\r\nGET\r\n$3\r\nfoo\r\n",
+        b"$3\r\nbar\r\n",
+    );
+    request(
+        &mut stream,
+        b"*2\r\n$3\r\nGET\r\n$4\r\nnope\r\n",
+        b"$-1\r\n",
+    );
+    request(&mut stream, b"SET foo baz\r\n", b"+OK\r\n");
+    request(&mut stream, b"GET foo\r\n", b"$3\r\nbaz\r\n");
+}
+
+#[test]
+fn test_errors_keep_connection_open() {
+    let mut stream = start();
+    request(
+        &mut stream,
+        b"*1\r\n$5\r\nBOGUS\r\n",
+        b"-ERR unknown command or wrong number of arguments for 'bogus'\r\n",
+    );
+    request(&mut stream, b"SET n x\r\n", b"+OK\r\n");
+    request(
+        &mut stream,
+        b"INCR n\r\n",
+        b"-ERR value is not an integer or out of range\r\n",
+    );
+    request(&mut stream, b"PING\r\n", b"+PONG\r\n");
+}
+
+#[test]
+fn test_pipelined_collections() {
+    let mut stream = start();
+    request(
+        &mut stream,
+        b"RPUSH q a b c\r\nLRANGE q 0 -1\r\nHSET h f v g w\r\nHGET h g\r\nSADD s x y x\r\nZADD z 2 b 1 a\r\nZRANGE z 0 -1 WITHSCORES\r\n",
+        b":3\r\n*3\r\n$1\r\na\r\n$1\r\nb\r\n$1\r\nc\r\n:2\r\n$1\r\nw\r\n:2\r\n:2\r\n*4\r\n$1\r\na\r\n$1\r\n1\r\n$1\r\nb\r\n$1\r\n2\r\n",
+    );
+}
+
+#[test]
+fn test_serve_method() {
+    // Find a free port, then let the server bind it.
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let dcs = Arc::new(DCS::new());
+    thread::spawn(move || dcs.serve(addr));
+    let mut stream = (0..50)
+        .find_map(|_| {
+            TcpStream::connect(addr)
+                .inspect_err(|_| thread::sleep(Duration::from_millis(20)))
+                .ok()
+        })
+        .unwrap();
+    request(&mut stream, b"PING\r\n", b"+PONG\r\n");
+}